@@ -0,0 +1,1407 @@
+use std::{
+  collections::HashMap,
+  net::SocketAddr,
+  ops::Deref,
+  str::FromStr,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+  time::Duration,
+};
+
+use axum::{
+  extract::{
+    rejection::{BytesRejection, JsonRejection},
+    ConnectInfo, DefaultBodyLimit, FromRef, Path, Query, Request, State,
+  },
+  http::{header, StatusCode},
+  middleware::{self, Next},
+  response::{IntoResponse, Response},
+  routing::{get, patch, post},
+  Json, Router,
+};
+use secp256k1::{PublicKey, SecretKey};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::Mutex, task, time};
+use tower::limit::GlobalConcurrencyLimitLayer;
+use tower_http::timeout::TimeoutLayer;
+use zeroize::{ZeroizeOnDrop, Zeroizing};
+
+use crate::{
+  auth,
+  config::ServerConfig,
+  generate_keypair, generate_mnemonic_keypair, keypair_from_mnemonic,
+  rate_limit::{rate_limit, RateLimiter},
+  wire::{Negotiated, Wire, WireBody},
+  writer::{self, ChainView, ChainWriter},
+  AnnounceError, AppendBlockError, AppendBlocksError, Block, Blockchain, Ledger, MiningError,
+  Order, PeerAnnouncement, SilocoinError, SyncSummary, Transaction, MAX_MEMO_BYTES,
+};
+
+/// Everything the HTTP API is served from. The ledger is owned by the task
+/// behind `writer`, which every change goes through, and reads come from
+/// `chain`, so neither waits on the other.
+#[derive(Clone)]
+pub struct Node {
+  pub writer: ChainWriter,
+  pub chain: ChainView,
+  /// Set by `POST /admin/freeze` while an operator backs up the chain. The
+  /// chain isn't changed or saved until it's cleared, and it always starts
+  /// cleared so a restart can't leave a node stuck frozen.
+  pub frozen: Arc<AtomicBool>,
+  /// Held for the length of a sync, so only one runs at a time.
+  pub syncing: Arc<Mutex<()>>,
+}
+
+impl Node {
+  /// Hands `ledger` to a new chain writer, with room for `write_queue_size`
+  /// changes to wait for it, and serves the node from there.
+  pub fn new(ledger: Ledger, write_queue_size: usize) -> Node {
+    let frozen = Arc::new(AtomicBool::new(false));
+    let (writer, chain) = writer::spawn(ledger, frozen.clone(), write_queue_size);
+    Node {
+      writer,
+      chain,
+      frozen,
+      syncing: Arc::new(Mutex::new(())),
+    }
+  }
+  pub fn is_frozen(&self) -> bool {
+    self.frozen.load(Ordering::Relaxed)
+  }
+}
+
+impl FromRef<Node> for ChainWriter {
+  fn from_ref(node: &Node) -> Self {
+    node.writer.clone()
+  }
+}
+
+impl FromRef<Node> for ChainView {
+  fn from_ref(node: &Node) -> Self {
+    node.chain.clone()
+  }
+}
+
+/// An error response, sent as `{ "error": ..., "code": ... }`. `code` is a
+/// stable identifier clients can match on, and `error` is for people.
+#[derive(Debug)]
+pub struct ApiError {
+  status: StatusCode,
+  code: &'static str,
+  message: String,
+}
+
+impl ApiError {
+  pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+    ApiError {
+      status,
+      code,
+      message: message.into(),
+    }
+  }
+}
+
+/// Turns a body `Json` couldn't read into a `400` that says what was wrong
+/// with it, rather than passing on the extractor's own message.
+impl From<JsonRejection> for ApiError {
+  fn from(rejection: JsonRejection) -> Self {
+    let message = match rejection {
+      JsonRejection::JsonSyntaxError(..) => "request body isn't valid JSON",
+      JsonRejection::JsonDataError(..) => "request body doesn't have the expected fields",
+      JsonRejection::MissingJsonContentType(..) => "request body must be sent as application/json",
+      JsonRejection::BytesRejection(rejection) => return rejection.into(),
+      _ => "request body couldn't be read",
+    };
+    ApiError::new(StatusCode::BAD_REQUEST, "invalid_body", message)
+  }
+}
+
+/// Turns a body that couldn't be read into a `400`, or a `413` if it was
+/// over the route's `DefaultBodyLimit`.
+impl From<BytesRejection> for ApiError {
+  fn from(rejection: BytesRejection) -> Self {
+    if rejection.status() == StatusCode::PAYLOAD_TOO_LARGE {
+      return ApiError::new(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        "body_too_large",
+        "request body is larger than this route accepts",
+      );
+    }
+    ApiError::new(
+      StatusCode::BAD_REQUEST,
+      "invalid_body",
+      "request body couldn't be read",
+    )
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ApiErrorBody {
+  error: String,
+  code: String,
+}
+
+impl IntoResponse for ApiError {
+  fn into_response(self) -> Response {
+    (
+      self.status,
+      Json(ApiErrorBody {
+        error: self.message,
+        code: self.code.to_string(),
+      }),
+    )
+      .into_response()
+  }
+}
+
+/// Syncs the ledger with its peers. Peers are polled from a copy of the
+/// ledger the chain writer hands out, and the result is applied by the
+/// writer, so changes are still applied while peers are being queried.
+/// Returns `None` without syncing if another sync is already running, and
+/// without applying anything if the node is frozen.
+pub async fn sync(node: &Node) -> Option<SyncSummary> {
+  let _syncing = node.syncing.try_lock().ok()?;
+  let snapshot = node.writer.start_sync().await?;
+  let round = snapshot.poll_peers().await;
+  // The writer checks again in case the node was frozen while we polled
+  node.writer.finish_sync(round).await
+}
+
+/// Mines the mempool into a block every `period`, as a node run with
+/// `--mine` does. Nothing is mined while the node is frozen.
+pub async fn mine_on_interval(node: Node, period: Duration) {
+  let mut interval = time::interval(period);
+  loop {
+    interval.tick().await;
+    if node.is_frozen() {
+      continue;
+    }
+    let job = match node.writer.start_mining().await {
+      Ok(Some(job)) => job,
+      Ok(None) => continue,
+      Err(err) => {
+        tracing::warn!(%err, "failed to mine pending transactions");
+        continue;
+      }
+    };
+    // The writer carries on while we mine, so a peer's block can replace
+    // our tip and cancel the job
+    let mined = match task::spawn_blocking(|| job.mine())
+      .await
+      .expect("mining task panicked")
+    {
+      Ok(mined) => node.writer.finish_mining(mined).await,
+      Err(err) => Err(err),
+    };
+    match mined {
+      // Frozen while we mined, so the block is dropped and its
+      // transactions wait in the mempool
+      Ok(None) => {}
+      Ok(Some(receipt)) => tracing::info!(
+        block_hash = receipt.block_hash,
+        height = receipt.height,
+        "mined block"
+      ),
+      Err(SilocoinError::Mining(MiningError::Cancelled)) => {
+        tracing::info!("our tip was replaced while mining, starting again on the new one");
+        interval.reset_immediately();
+      }
+      Err(err) => tracing::warn!(%err, "failed to mine pending transactions"),
+    }
+  }
+}
+
+/// Builds the node's HTTP API around `node`.
+pub fn build_app(node: Node) -> Router {
+  // The routes nodes use to talk to each other, which on a private network
+  // only accept requests signed with the network secret
+  let ledger = node.chain.get();
+  let network_secret: Option<Arc<str>> = ledger.config().network_secret.as_deref().map(Arc::from);
+  let max_body_bytes = ledger.config().max_body_bytes;
+  let peer_routes = Router::new()
+    .route("/handshake", get(handshake))
+    .nest(
+      "/peers",
+      Router::new()
+        .route("/", get(get_peers))
+        .route("/:addr", post(add_peer)),
+    )
+    .route("/debug/peers", get(get_peer_health))
+    .route("/debug/bootstrap", get(get_bootstrap_results))
+    .nest(
+      "/chain",
+      Router::new()
+        .route("/", get(get_blockchain))
+        .route(
+          "/",
+          patch(update_blockchain)
+            .layer(middleware::from_fn_with_state(
+              node.clone(),
+              reject_if_read_only,
+            ))
+            .layer(middleware::from_fn_with_state(
+              node.clone(),
+              reject_if_frozen,
+            )),
+        )
+        .route(
+          "/block",
+          post(append_block)
+            .layer(middleware::from_fn_with_state(
+              node.clone(),
+              reject_if_read_only,
+            ))
+            .layer(middleware::from_fn_with_state(
+              node.clone(),
+              reject_if_frozen,
+            )),
+        )
+        .route(
+          "/blocks",
+          post(append_blocks)
+            .layer(middleware::from_fn_with_state(
+              node.clone(),
+              reject_if_read_only,
+            ))
+            .layer(middleware::from_fn_with_state(
+              node.clone(),
+              reject_if_frozen,
+            )),
+        )
+        .route("/height", get(get_height))
+        .route("/headers", get(get_headers))
+        .route("/range", get(get_range)),
+    )
+    .route_layer(middleware::from_fn_with_state(
+      (network_secret, max_body_bytes),
+      auth::require_network_secret,
+    ));
+  // Payments are turned away by read-only and frozen nodes
+  let payment_routes = Router::new()
+    .route("/send", post(send))
+    .route("/submit", post(submit))
+    .route("/send-batch", post(send_batch))
+    .route_layer(middleware::from_fn_with_state(
+      node.clone(),
+      reject_if_read_only,
+    ))
+    .route_layer(middleware::from_fn_with_state(
+      node.clone(),
+      reject_if_frozen,
+    ));
+  Router::new()
+    .route("/", get(root))
+    .route("/info", get(get_info))
+    .route("/metrics", get(get_metrics))
+    .route("/accounts", get(get_accounts))
+    .nest(
+      "/wallet",
+      Router::new()
+        .route("/create", post(create_keypair))
+        .route("/restore", post(restore_keypair))
+        .route("/balance/:public_key", get(get_balance))
+        .route("/balances", post(get_balances))
+        .route("/deltas/:public_key", get(get_balance_deltas))
+        .route("/:public_key/export", get(export_wallet))
+        .route("/send/dry-run", post(send_dry_run))
+        .merge(payment_routes),
+    )
+    .route("/sync", post(trigger_sync))
+    .nest(
+      "/admin",
+      Router::new()
+        .route("/freeze", post(freeze))
+        .route("/unfreeze", post(unfreeze)),
+    )
+    .route("/chain/checkpoints", get(get_checkpoints))
+    .route("/chain/stats", get(get_chain_stats))
+    .route("/chain/tx/:signature", get(get_transaction))
+    .route("/chain/tx/:signature/confirmations", get(get_confirmations))
+    .route("/chain/tx/:signature/proof", get(get_transaction_proof))
+    .merge(peer_routes)
+    .layer(DefaultBodyLimit::max(max_body_bytes))
+    .with_state(node)
+}
+
+/// Wraps `app` in the server-wide limits from `server_config`: requests
+/// running past the timeout are answered with `408`, at most so many run at
+/// once, and each client is rate limited.
+pub fn with_server_limits(app: Router, server_config: &ServerConfig, chain: ChainView) -> Router {
+  let rate_limiter = Arc::new(RateLimiter::new(
+    server_config.rate_limit_per_sec,
+    server_config.rate_limit_burst,
+  ));
+  // The last layer added runs first, so throttled clients are turned away
+  // before they take up a concurrency slot
+  app
+    .layer(TimeoutLayer::new(server_config.request_timeout))
+    .layer(GlobalConcurrencyLimitLayer::new(
+      server_config.max_concurrent_requests,
+    ))
+    .layer(middleware::from_fn_with_state(
+      (rate_limiter, chain),
+      rate_limit,
+    ))
+}
+
+/// Answers `503` instead of running the route while the node is frozen.
+async fn reject_if_frozen(State(node): State<Node>, request: Request, next: Next) -> Response {
+  if node.is_frozen() {
+    return ApiError::new(
+      StatusCode::SERVICE_UNAVAILABLE,
+      "frozen",
+      "this node is frozen for a backup",
+    )
+    .into_response();
+  }
+  next.run(request).await
+}
+
+/// Answers `403` instead of running the route when the node is read-only.
+async fn reject_if_read_only(State(node): State<Node>, request: Request, next: Next) -> Response {
+  if node.chain.get().config().read_only {
+    return ApiError::new(StatusCode::FORBIDDEN, "read_only", "this node is read-only")
+      .into_response();
+  }
+  next.run(request).await
+}
+
+async fn root() -> Response {
+  (StatusCode::OK).into_response()
+}
+
+#[derive(Serialize, Deserialize)]
+struct CreateKeyPairResponse {
+  secret_key: String,
+  public_key: String,
+  /// Only returned when asked for with `?mnemonic=true`.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  mnemonic: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CreateKeyPairParams {
+  #[serde(default)]
+  mnemonic: bool,
+}
+
+async fn create_keypair(Query(params): Query<CreateKeyPairParams>) -> Response {
+  if params.mnemonic {
+    let Ok((mnemonic, secret_key, public_key)) = generate_mnemonic_keypair() else {
+      return ApiError::new(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "keygen_failed",
+        "could not generate mnemonic",
+      )
+      .into_response();
+    };
+    return (
+      StatusCode::OK,
+      Json(CreateKeyPairResponse {
+        secret_key: secret_key.display_secret().to_string(),
+        public_key: public_key.to_string(),
+        mnemonic: Some(mnemonic),
+      }),
+    )
+      .into_response();
+  }
+  let Ok((secret_key, public_key)) = generate_keypair() else {
+    return ApiError::new(
+      StatusCode::INTERNAL_SERVER_ERROR,
+      "keygen_failed",
+      "could not generate private key",
+    )
+    .into_response();
+  };
+  (
+    StatusCode::OK,
+    Json(CreateKeyPairResponse {
+      secret_key: secret_key.display_secret().to_string(),
+      public_key: public_key.to_string(),
+      mnemonic: None,
+    }),
+  )
+    .into_response()
+}
+
+#[derive(Serialize, Deserialize)]
+struct RestoreKeyPairBody {
+  mnemonic: Zeroizing<String>,
+}
+
+async fn restore_keypair(Json(body): Json<RestoreKeyPairBody>) -> Response {
+  let Ok((secret_key, public_key)) = keypair_from_mnemonic(&body.mnemonic) else {
+    return ApiError::new(
+      StatusCode::BAD_REQUEST,
+      "invalid_mnemonic",
+      "invalid mnemonic",
+    )
+    .into_response();
+  };
+  (
+    StatusCode::OK,
+    Json(CreateKeyPairResponse {
+      secret_key: secret_key.display_secret().to_string(),
+      public_key: public_key.to_string(),
+      mnemonic: None,
+    }),
+  )
+    .into_response()
+}
+
+#[derive(Serialize, Deserialize)]
+struct GetBalanceParams {
+  public_key: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct GetBalanceResponse {
+  balance: i64,
+}
+
+#[derive(Deserialize)]
+struct GetBalanceQuery {
+  /// Only count blocks buried at least this deep.
+  #[serde(default)]
+  min_confirmations: usize,
+}
+
+/// The most characters of a bad input echoed back in an error.
+const MAX_ECHOED_INPUT_CHARS: usize = 80;
+
+/// `input` quoted for an error message, cut short and with control
+/// characters escaped so it can't forge lines in logs.
+fn echo_input(input: &str) -> String {
+  let mut echoed: String = input
+    .chars()
+    .take(MAX_ECHOED_INPUT_CHARS)
+    .flat_map(char::escape_debug)
+    .collect();
+  if input.chars().count() > MAX_ECHOED_INPUT_CHARS {
+    echoed.push_str("...");
+  }
+  format!("\"{}\"", echoed)
+}
+
+/// Parses a hex public key, answering `400 invalid_public_key` with the
+/// offending input when it isn't one.
+fn parse_public_key(input: &str) -> Result<PublicKey, ApiError> {
+  PublicKey::from_str(input).map_err(|_| {
+    ApiError::new(
+      StatusCode::BAD_REQUEST,
+      "invalid_public_key",
+      format!("could not parse public key {}", echo_input(input)),
+    )
+  })
+}
+
+#[axum::debug_handler]
+async fn get_balance(
+  Path(params): Path<GetBalanceParams>,
+  Query(query): Query<GetBalanceQuery>,
+  State(chain): State<ChainView>,
+) -> Response {
+  let public_key = match parse_public_key(&params.public_key) {
+    Ok(public_key) => public_key,
+    Err(err) => return err.into_response(),
+  };
+  let Ok(balance) = chain
+    .get()
+    .get_final_balance(&public_key, query.min_confirmations)
+  else {
+    return ApiError::new(
+      StatusCode::INTERNAL_SERVER_ERROR,
+      "balance_unavailable",
+      "could not get balance",
+    )
+    .into_response();
+  };
+  (StatusCode::OK, Json(GetBalanceResponse { balance })).into_response()
+}
+
+#[derive(Serialize, Deserialize)]
+struct ExportedTransaction {
+  height: usize,
+  confirmations: usize,
+  transaction: Transaction,
+}
+
+/// Everything the node knows about a wallet. The secret key never reaches the
+/// node, so it has to be backed up separately.
+#[derive(Serialize, Deserialize)]
+struct ExportWalletResponse {
+  public_key: String,
+  balance: i64,
+  transactions: Vec<ExportedTransaction>,
+}
+
+async fn export_wallet(
+  Path(params): Path<GetBalanceParams>,
+  State(chain): State<ChainView>,
+) -> Response {
+  let public_key = match parse_public_key(&params.public_key) {
+    Ok(public_key) => public_key,
+    Err(err) => return err.into_response(),
+  };
+  let ledger = chain.get();
+  let Ok(balance) = ledger.get_balance(&public_key) else {
+    return ApiError::new(
+      StatusCode::INTERNAL_SERVER_ERROR,
+      "balance_unavailable",
+      "could not get balance",
+    )
+    .into_response();
+  };
+  let chain = ledger.chain();
+  let transactions = chain
+    .transactions_involving(&public_key)
+    .into_iter()
+    .map(|(height, transaction)| ExportedTransaction {
+      height,
+      confirmations: chain.height() - height,
+      transaction: transaction.clone(),
+    })
+    .collect();
+  (
+    StatusCode::OK,
+    Json(ExportWalletResponse {
+      public_key: public_key.to_string(),
+      balance,
+      transactions,
+    }),
+  )
+    .into_response()
+}
+
+#[derive(Serialize, Deserialize)]
+struct BalanceDelta {
+  height: usize,
+  delta: i64,
+}
+
+async fn get_balance_deltas(
+  Path(params): Path<GetBalanceParams>,
+  State(chain): State<ChainView>,
+) -> Response {
+  let public_key = match parse_public_key(&params.public_key) {
+    Ok(public_key) => public_key,
+    Err(err) => return err.into_response(),
+  };
+  let deltas: Vec<BalanceDelta> = chain
+    .get()
+    .chain()
+    .balance_deltas_for(&public_key)
+    .into_iter()
+    .map(|(height, delta)| BalanceDelta { height, delta })
+    .collect();
+  (StatusCode::OK, Json(deltas)).into_response()
+}
+
+/// The most accounts `/accounts` returns per page.
+const MAX_ACCOUNTS_PAGE: usize = 1000;
+
+#[derive(Deserialize)]
+struct GetAccountsQuery {
+  #[serde(default)]
+  offset: usize,
+  #[serde(default = "default_accounts_limit")]
+  limit: usize,
+}
+
+fn default_accounts_limit() -> usize {
+  100
+}
+
+#[derive(Serialize, Deserialize)]
+struct Account {
+  public_key: String,
+  balance: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct GetAccountsResponse {
+  /// How many accounts there are across every page.
+  total: usize,
+  accounts: Vec<Account>,
+}
+
+/// Lists every known address with its balance, ordered by public key so
+/// pages stay stable between requests.
+async fn get_accounts(
+  State(chain): State<ChainView>,
+  Query(query): Query<GetAccountsQuery>,
+) -> Response {
+  let ledger = chain.get();
+  let addresses: Vec<PublicKey> = ledger.chain().known_addresses().into_iter().collect();
+  let Ok(balances) = ledger.get_balances(&addresses) else {
+    return ApiError::new(
+      StatusCode::INTERNAL_SERVER_ERROR,
+      "balance_unavailable",
+      "could not get balances",
+    )
+    .into_response();
+  };
+  let mut accounts: Vec<Account> = balances
+    .into_iter()
+    .map(|(public_key, balance)| Account {
+      public_key: public_key.to_string(),
+      balance,
+    })
+    .collect();
+  accounts.sort_by(|a, b| a.public_key.cmp(&b.public_key));
+  let total = accounts.len();
+  let accounts = accounts
+    .into_iter()
+    .skip(query.offset)
+    .take(query.limit.min(MAX_ACCOUNTS_PAGE))
+    .collect();
+  (
+    StatusCode::OK,
+    Json(GetAccountsResponse { total, accounts }),
+  )
+    .into_response()
+}
+
+async fn get_balances(State(chain): State<ChainView>, Json(body): Json<Vec<String>>) -> Response {
+  let mut public_keys = Vec::with_capacity(body.len());
+  for raw_key in &body {
+    let public_key = match parse_public_key(raw_key) {
+      Ok(public_key) => public_key,
+      Err(err) => return err.into_response(),
+    };
+    public_keys.push(public_key);
+  }
+  let Ok(balances) = chain.get().get_balances(&public_keys) else {
+    return ApiError::new(
+      StatusCode::INTERNAL_SERVER_ERROR,
+      "balance_unavailable",
+      "could not get balances",
+    )
+    .into_response();
+  };
+  let balances: HashMap<String, i64> = balances
+    .into_iter()
+    .map(|(public_key, balance)| (public_key.to_string(), balance))
+    .collect();
+  (StatusCode::OK, Json(balances)).into_response()
+}
+
+#[derive(Serialize, Deserialize)]
+struct SendBody {
+  to_public_key: String,
+  /// Wiped from memory when the request is dropped.
+  from_secret_key: Zeroizing<String>,
+  amount: u64,
+  /// A note attached to the payment, at most `MAX_MEMO_BYTES` long.
+  #[serde(default)]
+  memo: Option<String>,
+  /// Sends even if the amount breaks the node's `max_send_fraction` policy.
+  #[serde(default)]
+  force: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SendResponse {
+  block_hash: String,
+  height: usize,
+}
+
+/// A secret key parsed from a request. It's erased when dropped, so every way
+/// out of a handler wipes it, early error returns included.
+struct ParsedSecretKey(SecretKey);
+
+impl ParsedSecretKey {
+  fn parse(input: &str) -> Result<ParsedSecretKey, ApiError> {
+    SecretKey::from_str(input)
+      .map(ParsedSecretKey)
+      .map_err(|_| {
+        ApiError::new(
+          StatusCode::BAD_REQUEST,
+          "invalid_secret_key",
+          "invalid private key for sending from",
+        )
+      })
+  }
+}
+
+impl Deref for ParsedSecretKey {
+  type Target = SecretKey;
+
+  fn deref(&self) -> &SecretKey {
+    &self.0
+  }
+}
+
+impl Drop for ParsedSecretKey {
+  fn drop(&mut self) {
+    self.0.non_secure_erase();
+  }
+}
+
+impl ZeroizeOnDrop for ParsedSecretKey {}
+
+/// Parses the keys in `params`, also checking its memo isn't too long.
+fn parse_send_body(params: &SendBody) -> Result<(PublicKey, ParsedSecretKey), ApiError> {
+  let Ok(to_public_key) = PublicKey::from_str(&params.to_public_key) else {
+    return Err(ApiError::new(
+      StatusCode::BAD_REQUEST,
+      "invalid_public_key",
+      "invalid public key for sending to",
+    ));
+  };
+  let from_secret_key = ParsedSecretKey::parse(&params.from_secret_key)?;
+  if params
+    .memo
+    .as_ref()
+    .is_some_and(|memo| memo.len() > MAX_MEMO_BYTES)
+  {
+    return Err(ApiError::new(
+      StatusCode::BAD_REQUEST,
+      "memo_too_long",
+      format!("memo is longer than {} bytes", MAX_MEMO_BYTES),
+    ));
+  }
+  Ok((to_public_key, from_secret_key))
+}
+
+async fn send(
+  State(writer): State<ChainWriter>,
+  State(chain): State<ChainView>,
+  Json(params): Json<SendBody>,
+) -> Response {
+  let (to_public_key, from_secret_key) = match parse_send_body(&params) {
+    Ok(keys) => keys,
+    Err(err) => return err.into_response(),
+  };
+  // Signed here so the secret key goes no further than this handler
+  let transaction = Transaction::with_memo(
+    &to_public_key,
+    &from_secret_key,
+    params.amount,
+    params.memo,
+    chain.get().chain().chain_id(),
+  );
+  drop(from_secret_key);
+  let result = match transaction {
+    Ok(transaction) => writer.pay(vec![transaction], params.force).await,
+    Err(err) => Err(err),
+  };
+  match result {
+    Ok(receipt) => (
+      StatusCode::OK,
+      Json(SendResponse {
+        block_hash: receipt.block_hash,
+        height: receipt.height,
+      }),
+    )
+      .into_response(),
+    Err(err) => send_error(err).into_response(),
+  }
+}
+
+async fn send_dry_run(State(chain): State<ChainView>, Json(params): Json<SendBody>) -> Response {
+  let (to_public_key, from_secret_key) = match parse_send_body(&params) {
+    Ok(keys) => keys,
+    Err(err) => return err.into_response(),
+  };
+  let result = chain.get().can_send(
+    &to_public_key,
+    &from_secret_key,
+    params.amount,
+    params.force,
+  );
+  drop(from_secret_key);
+  match result {
+    Ok(..) => (StatusCode::OK).into_response(),
+    Err(err) => send_error(err).into_response(),
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SubmitResponse {
+  signature: String,
+}
+
+/// Queues a payment in the mempool for a miner to pick up, rather than
+/// mining it straight away like `send`.
+async fn submit(
+  State(writer): State<ChainWriter>,
+  State(chain): State<ChainView>,
+  Json(params): Json<SendBody>,
+) -> Response {
+  let (to_public_key, from_secret_key) = match parse_send_body(&params) {
+    Ok(keys) => keys,
+    Err(err) => return err.into_response(),
+  };
+  // Signed here so the secret key never reaches the writer
+  let transaction = Transaction::with_memo(
+    &to_public_key,
+    &from_secret_key,
+    params.amount,
+    params.memo,
+    chain.get().chain().chain_id(),
+  );
+  drop(from_secret_key);
+  let result = match transaction {
+    Ok(transaction) => writer.submit(transaction, params.force).await,
+    Err(err) => Err(err),
+  };
+  match result {
+    Ok(signature) => (StatusCode::ACCEPTED, Json(SubmitResponse { signature })).into_response(),
+    Err(err) => send_error(err).into_response(),
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SendBatchRecipient {
+  to_public_key: String,
+  amount: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SendBatchBody {
+  from_secret_key: Zeroizing<String>,
+  recipients: Vec<SendBatchRecipient>,
+  #[serde(default)]
+  force: bool,
+}
+
+async fn send_batch(
+  State(writer): State<ChainWriter>,
+  State(chain): State<ChainView>,
+  Json(params): Json<SendBatchBody>,
+) -> Response {
+  let from_secret_key = match ParsedSecretKey::parse(&params.from_secret_key) {
+    Ok(from_secret_key) => from_secret_key,
+    Err(err) => return err.into_response(),
+  };
+  let mut recipients = Vec::with_capacity(params.recipients.len());
+  for recipient in &params.recipients {
+    let Ok(to_public_key) = PublicKey::from_str(&recipient.to_public_key) else {
+      return ApiError::new(
+        StatusCode::BAD_REQUEST,
+        "invalid_public_key",
+        format!(
+          "invalid public key for sending to {}",
+          echo_input(&recipient.to_public_key)
+        ),
+      )
+      .into_response();
+    };
+    recipients.push((to_public_key, recipient.amount));
+  }
+  let chain_id = chain.get().chain().chain_id();
+  let transactions = recipients
+    .iter()
+    .map(|(to, amount)| Transaction::new(to, &from_secret_key, *amount, chain_id))
+    .collect::<Result<Vec<_>, SilocoinError>>();
+  drop(from_secret_key);
+  let result = match transactions {
+    Ok(transactions) => writer.pay(transactions, params.force).await,
+    Err(err) => Err(err),
+  };
+  match result {
+    Ok(receipt) => (
+      StatusCode::OK,
+      Json(SendResponse {
+        block_hash: receipt.block_hash,
+        height: receipt.height,
+      }),
+    )
+      .into_response(),
+    Err(err) => send_error(err).into_response(),
+  }
+}
+
+/// A mining timeout, or the node being frozen mid-payment, is the node being
+/// unable to take the payment rather than anything wrong with it, so each
+/// gets a `503` of its own. Insufficient funds
+/// get their own code so wallets can pick out the shortfall.
+fn send_error(err: SilocoinError) -> ApiError {
+  let (status, code) = match err {
+    SilocoinError::Mining(_) => (StatusCode::SERVICE_UNAVAILABLE, "mining_timed_out"),
+    SilocoinError::InsufficientFunds(_) => (StatusCode::BAD_REQUEST, "insufficient_funds"),
+    SilocoinError::TooManyPending(_) => (StatusCode::TOO_MANY_REQUESTS, "too_many_pending"),
+    SilocoinError::Frozen => (StatusCode::SERVICE_UNAVAILABLE, "frozen"),
+    _ => (StatusCode::BAD_REQUEST, "send_refused"),
+  };
+  ApiError::new(status, code, err.to_string())
+}
+
+#[derive(Deserialize)]
+struct AddPeerPath {
+  addr: SocketAddr,
+}
+
+fn announce_error(err: AnnounceError) -> ApiError {
+  let (status, code) = match err {
+    AnnounceError::InvalidAddress => (StatusCode::BAD_REQUEST, "invalid_peer_address"),
+    AnnounceError::MalformedSignature | AnnounceError::SignatureMismatch => {
+      (StatusCode::BAD_REQUEST, "invalid_announcement")
+    }
+    AnnounceError::IdentityMismatch => (StatusCode::FORBIDDEN, "identity_mismatch"),
+  };
+  ApiError::new(status, code, err.to_string())
+}
+
+/// Adds the peer announcing itself at `addr`. The body must be its
+/// announcement, signed with the identity key it first announced with.
+async fn add_peer(
+  State(writer): State<ChainWriter>,
+  State(chain): State<ChainView>,
+  Path(path): Path<AddPeerPath>,
+  body: Result<Json<PeerAnnouncement>, JsonRejection>,
+) -> Response {
+  let announcement = match body {
+    Ok(Json(announcement)) => announcement,
+    Err(rejection) => return ApiError::from(rejection).into_response(),
+  };
+  if announcement.addr != path.addr {
+    return ApiError::new(
+      StatusCode::BAD_REQUEST,
+      "invalid_announcement",
+      "announcement is for a different address",
+    )
+    .into_response();
+  }
+  let ledger = chain.get();
+  if !ledger.config().verify_peers {
+    return match writer.accept_announcement(announcement).await {
+      Ok(..) => (StatusCode::OK).into_response(),
+      Err(err) => announce_error(err).into_response(),
+    };
+  }
+  if let Err(err) = ledger.check_announcement(&announcement) {
+    return announce_error(err).into_response();
+  }
+  let network_secret = ledger.config().network_secret.clone();
+  let client = ledger.client().clone();
+  let scheme = ledger.peer_scheme();
+  // Verify in the background so the handshake round-trip doesn't hold up
+  // the response
+  tokio::spawn(async move {
+    if Ledger::is_reachable_peer(&client, scheme, &announcement, network_secret.as_deref()).await {
+      if let Err(err) = writer.accept_announcement(announcement).await {
+        tracing::info!(addr = %path.addr, %err, "rejecting peer");
+      }
+    } else {
+      tracing::info!(addr = %path.addr, "rejecting unreachable peer");
+    }
+  });
+  (StatusCode::ACCEPTED).into_response()
+}
+
+#[derive(Deserialize)]
+struct OrderQuery {
+  #[serde(default)]
+  order: Order,
+}
+
+async fn get_blockchain(
+  State(chain): State<ChainView>,
+  Negotiated(format): Negotiated,
+  Query(query): Query<OrderQuery>,
+) -> Response {
+  let blockchain = chain.get().chain().ordered(query.order);
+  (StatusCode::OK, Wire(format, blockchain)).into_response()
+}
+
+#[derive(Serialize, Deserialize)]
+struct GetHeightResponse {
+  height: usize,
+}
+
+async fn get_height(State(chain): State<ChainView>) -> Response {
+  let height = chain.get().chain().height();
+  (StatusCode::OK, Json(GetHeightResponse { height })).into_response()
+}
+
+async fn get_headers(State(chain): State<ChainView>, Negotiated(format): Negotiated) -> Response {
+  (StatusCode::OK, Wire(format, chain.get().chain().headers())).into_response()
+}
+
+#[derive(Deserialize)]
+struct RangeQuery {
+  from: usize,
+  to: usize,
+  #[serde(default)]
+  order: Order,
+}
+
+async fn get_range(
+  State(chain): State<ChainView>,
+  Negotiated(format): Negotiated,
+  Query(query): Query<RangeQuery>,
+) -> Response {
+  (
+    StatusCode::OK,
+    Wire(
+      format,
+      chain.get().chain().range(query.from, query.to, query.order),
+    ),
+  )
+    .into_response()
+}
+
+#[derive(Deserialize)]
+struct GetStatsQuery {
+  #[serde(default = "default_stats_window")]
+  window: usize,
+}
+
+fn default_stats_window() -> usize {
+  100
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChainStats {
+  height: usize,
+  /// How many of the newest blocks `recent_tps` was measured over.
+  window_blocks: usize,
+  recent_tps: f64,
+}
+
+async fn get_chain_stats(
+  State(chain): State<ChainView>,
+  Query(query): Query<GetStatsQuery>,
+) -> Response {
+  let ledger = chain.get();
+  let chain = ledger.chain();
+  (
+    StatusCode::OK,
+    Json(ChainStats {
+      height: chain.height(),
+      window_blocks: query.window,
+      recent_tps: chain.recent_tps(query.window),
+    }),
+  )
+    .into_response()
+}
+
+async fn get_checkpoints(State(chain): State<ChainView>) -> Response {
+  (
+    StatusCode::OK,
+    Json(chain.get().config().checkpoints.clone()),
+  )
+    .into_response()
+}
+
+#[derive(Deserialize)]
+struct GetTransactionPath {
+  signature: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct GetTransactionResponse {
+  height: usize,
+  transaction: Transaction,
+}
+
+async fn get_transaction(
+  State(chain): State<ChainView>,
+  Path(path): Path<GetTransactionPath>,
+) -> Response {
+  let Some((height, transaction)) = chain.get().chain().find_transaction(&path.signature) else {
+    return ApiError::new(
+      StatusCode::NOT_FOUND,
+      "transaction_not_found",
+      "transaction not found",
+    )
+    .into_response();
+  };
+  (
+    StatusCode::OK,
+    Json(GetTransactionResponse {
+      height,
+      transaction,
+    }),
+  )
+    .into_response()
+}
+
+#[derive(Serialize, Deserialize)]
+struct GetConfirmationsResponse {
+  confirmations: usize,
+}
+
+async fn get_confirmations(
+  State(chain): State<ChainView>,
+  Path(path): Path<GetTransactionPath>,
+) -> Response {
+  let Some(confirmations) = chain.get().chain().confirmations(&path.signature) else {
+    return ApiError::new(
+      StatusCode::NOT_FOUND,
+      "transaction_not_found",
+      "transaction not found",
+    )
+    .into_response();
+  };
+  (
+    StatusCode::OK,
+    Json(GetConfirmationsResponse { confirmations }),
+  )
+    .into_response()
+}
+
+#[derive(Serialize, Deserialize)]
+struct GetTransactionProofResponse {
+  height: usize,
+  index: usize,
+  /// The root the block's hash commits to, also listed in `/chain/headers`.
+  merkle_root: String,
+  /// Sibling hashes from the transaction's leaf up, see
+  /// `merkle::verify_merkle_proof`.
+  proof: Vec<String>,
+}
+
+async fn get_transaction_proof(
+  State(chain): State<ChainView>,
+  Path(path): Path<GetTransactionPath>,
+) -> Response {
+  let ledger = chain.get();
+  let chain = ledger.chain();
+  let Some((height, index, block)) = chain
+    .locate_transaction(&path.signature)
+    .and_then(|(height, index)| Some((height, index, chain.block_at(height)?)))
+  else {
+    return ApiError::new(
+      StatusCode::NOT_FOUND,
+      "transaction_not_found",
+      "transaction not found",
+    )
+    .into_response();
+  };
+  (
+    StatusCode::OK,
+    Json(GetTransactionProofResponse {
+      height,
+      index,
+      merkle_root: block.merkle_root(),
+      proof: block.merkle_proof(index),
+    }),
+  )
+    .into_response()
+}
+
+#[derive(Deserialize, Serialize)]
+struct UpdateBlockchainBody {
+  blockchain: Blockchain,
+}
+
+async fn update_blockchain(
+  State(writer): State<ChainWriter>,
+  WireBody(body): WireBody<UpdateBlockchainBody>,
+) -> Response {
+  match writer.update_blockchain(body.blockchain).await {
+    Ok(..) => (StatusCode::OK).into_response(),
+    Err(err) => {
+      ApiError::new(StatusCode::BAD_REQUEST, "invalid_chain", err.to_string()).into_response()
+    }
+  }
+}
+
+#[derive(Deserialize, Serialize)]
+struct AppendBlockBody {
+  block: Block,
+}
+
+async fn append_block(
+  State(node): State<Node>,
+  WireBody(body): WireBody<AppendBlockBody>,
+) -> Response {
+  let result = node.writer.append_block(body.block).await;
+  match result {
+    // Other peers relay the same block, so seeing it again is expected
+    Ok(..) | Err(AppendBlockError::AlreadySeen) => (StatusCode::OK).into_response(),
+    Err(err @ AppendBlockError::DoesNotConnect) => {
+      // We're behind or on another fork, so catch up with the network
+      let node = node.clone();
+      tokio::spawn(async move {
+        sync(&node).await;
+      });
+      ApiError::new(
+        StatusCode::CONFLICT,
+        "block_does_not_connect",
+        err.to_string(),
+      )
+      .into_response()
+    }
+    Err(err @ (AppendBlockError::Invalid | AppendBlockError::BadTimestamp)) => {
+      ApiError::new(StatusCode::BAD_REQUEST, "invalid_block", err.to_string()).into_response()
+    }
+  }
+}
+
+#[derive(Deserialize, Serialize)]
+struct AppendBlocksBody {
+  blocks: Vec<Block>,
+}
+
+async fn append_blocks(
+  State(node): State<Node>,
+  WireBody(body): WireBody<AppendBlocksBody>,
+) -> Response {
+  let result = node.writer.append_blocks(body.blocks).await;
+  match result {
+    Ok(..) => (StatusCode::OK).into_response(),
+    Err(
+      err @ AppendBlocksError {
+        index: 0,
+        reason: AppendBlockError::DoesNotConnect,
+      },
+    ) => {
+      // We're behind or on another fork, so catch up with the network
+      let node = node.clone();
+      tokio::spawn(async move {
+        sync(&node).await;
+      });
+      ApiError::new(
+        StatusCode::CONFLICT,
+        "block_does_not_connect",
+        err.to_string(),
+      )
+      .into_response()
+    }
+    // Later blocks that don't connect mean the sequence itself is broken
+    Err(err) => {
+      ApiError::new(StatusCode::BAD_REQUEST, "invalid_block", err.to_string()).into_response()
+    }
+  }
+}
+
+/// Syncs straight away instead of waiting for the next interval.
+async fn trigger_sync(State(node): State<Node>) -> Response {
+  if node.is_frozen() {
+    return ApiError::new(
+      StatusCode::SERVICE_UNAVAILABLE,
+      "frozen",
+      "this node is frozen for a backup",
+    )
+    .into_response();
+  }
+  match sync(&node).await {
+    Some(summary) => (StatusCode::OK, Json(summary)).into_response(),
+    None => ApiError::new(
+      StatusCode::CONFLICT,
+      "sync_in_progress",
+      "a sync is already running",
+    )
+    .into_response(),
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+struct FreezeResponse {
+  frozen: bool,
+}
+
+/// Stops the chain changing until `unfreeze`, so its file can be copied.
+/// Only clients on this machine may freeze or unfreeze a node.
+async fn freeze(
+  State(node): State<Node>,
+  ConnectInfo(client): ConnectInfo<SocketAddr>,
+) -> Response {
+  set_frozen(&node, client, true)
+}
+
+async fn unfreeze(
+  State(node): State<Node>,
+  ConnectInfo(client): ConnectInfo<SocketAddr>,
+) -> Response {
+  set_frozen(&node, client, false)
+}
+
+fn set_frozen(node: &Node, client: SocketAddr, frozen: bool) -> Response {
+  if !client.ip().is_loopback() {
+    return ApiError::new(
+      StatusCode::FORBIDDEN,
+      "admin_only",
+      "admin endpoints are only served to local clients",
+    )
+    .into_response();
+  }
+  node.frozen.store(frozen, Ordering::Relaxed);
+  (StatusCode::OK, Json(FreezeResponse { frozen })).into_response()
+}
+
+async fn handshake(State(chain): State<ChainView>) -> Response {
+  (StatusCode::OK, Json(chain.get().handshake())).into_response()
+}
+
+async fn get_info(State(chain): State<ChainView>) -> Response {
+  (StatusCode::OK, Json(chain.get().info())).into_response()
+}
+
+async fn get_metrics(State(chain): State<ChainView>) -> Response {
+  (
+    StatusCode::OK,
+    [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+    chain.get().render_metrics(),
+  )
+    .into_response()
+}
+
+async fn get_peer_health(State(chain): State<ChainView>) -> Response {
+  (StatusCode::OK, Json(chain.get().peer_health())).into_response()
+}
+
+async fn get_bootstrap_results(State(chain): State<ChainView>) -> Response {
+  (
+    StatusCode::OK,
+    Json(chain.get().bootstrap_results().clone()),
+  )
+    .into_response()
+}
+
+async fn get_peers(State(chain): State<ChainView>) -> Response {
+  (StatusCode::OK, Json(chain.get().get_peers())).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+
+  /// Compiles only if `secret` is wiped from memory when it's dropped.
+  fn assert_zeroized_on_drop<T: ZeroizeOnDrop>(_secret: &T) {}
+
+  #[test]
+  fn request_bodies_wipe_their_key_material_on_drop() {
+    let secret_key = "01".repeat(32);
+    let send: SendBody = serde_json::from_value(json!({
+      "to_public_key": "",
+      "from_secret_key": secret_key,
+      "amount": 1,
+    }))
+    .unwrap();
+    let batch: SendBatchBody = serde_json::from_value(json!({
+      "from_secret_key": secret_key,
+      "recipients": [],
+    }))
+    .unwrap();
+    let restore: RestoreKeyPairBody =
+      serde_json::from_value(json!({ "mnemonic": "abandon ability" })).unwrap();
+
+    assert_zeroized_on_drop(&send.from_secret_key);
+    assert_zeroized_on_drop(&batch.from_secret_key);
+    assert_zeroized_on_drop(&restore.mnemonic);
+  }
+
+  #[test]
+  fn parsed_secret_keys_are_wiped_on_drop() {
+    let (_, to_public_key) = generate_keypair().unwrap();
+    let send: SendBody = serde_json::from_value(json!({
+      "to_public_key": to_public_key.to_string(),
+      "from_secret_key": "07".repeat(32),
+      "amount": 1,
+    }))
+    .unwrap();
+
+    let (_, from_secret_key) = parse_send_body(&send).unwrap();
+
+    assert_zeroized_on_drop(&from_secret_key);
+  }
+}
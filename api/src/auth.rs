@@ -0,0 +1,139 @@
+use std::{
+  sync::Arc,
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+  body::Body,
+  extract::{OriginalUri, Request, State},
+  http::StatusCode,
+  middleware::Next,
+  response::{IntoResponse, Response},
+};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::app::ApiError;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Carries the hex HMAC-SHA256 of a peer request under the network secret.
+pub const SIGNATURE_HEADER: &str = "x-silo-signature";
+/// Carries the Unix time, in seconds, a peer request was signed at.
+pub const TIMESTAMP_HEADER: &str = "x-silo-timestamp";
+/// How far a signed request's timestamp may be from our clock, which bounds
+/// how long a captured request can be replayed for.
+pub const MAX_SIGNATURE_AGE_SECS: u64 = 300;
+
+fn unix_secs() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|since_the_epoch| since_the_epoch.as_secs())
+    .unwrap_or(0)
+}
+
+/// The MAC over everything a signature vouches for: the method, the path
+/// and query, the timestamp and a digest of the body.
+fn mac(secret: &str, method: &str, path: &str, timestamp: u64, body: &[u8]) -> HmacSha256 {
+  let mut mac =
+    HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+  mac.update(method.as_bytes());
+  mac.update(b"\n");
+  mac.update(path.as_bytes());
+  mac.update(b"\n");
+  mac.update(timestamp.to_string().as_bytes());
+  mac.update(b"\n");
+  mac.update(&Sha256::digest(body));
+  mac
+}
+
+/// Sends the request built by `builder`, signed with `secret` when the node
+/// is configured with one.
+pub async fn send_signed(
+  builder: reqwest::RequestBuilder,
+  secret: Option<&str>,
+) -> reqwest::Result<reqwest::Response> {
+  let (client, request) = builder.build_split();
+  let mut request = request?;
+  if let Some(secret) = secret {
+    let url = request.url();
+    let path = match url.query() {
+      Some(query) => format!("{}?{}", url.path(), query),
+      None => url.path().to_string(),
+    };
+    let body = request
+      .body()
+      .and_then(|body| body.as_bytes())
+      .unwrap_or_default();
+    let timestamp = unix_secs();
+    let signature = mac(secret, request.method().as_str(), &path, timestamp, body)
+      .finalize()
+      .into_bytes();
+    let headers = request.headers_mut();
+    headers.insert(SIGNATURE_HEADER, super::to_hex(&signature).parse().unwrap());
+    headers.insert(TIMESTAMP_HEADER, timestamp.into());
+  }
+  client.execute(request).await
+}
+
+/// Middleware rejecting peer requests that aren't signed with the network
+/// secret, reading bodies of up to `max_body_bytes` to check them. Without a
+/// secret every request is let through.
+pub async fn require_network_secret(
+  State((secret, max_body_bytes)): State<(Option<Arc<str>>, usize)>,
+  request: Request,
+  next: Next,
+) -> Response {
+  let Some(secret) = secret else {
+    return next.run(request).await;
+  };
+  let headers = request.headers();
+  let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+  let (Some(signature), Some(timestamp)) = (
+    header(SIGNATURE_HEADER).and_then(super::from_hex),
+    header(TIMESTAMP_HEADER).and_then(|timestamp| timestamp.parse::<u64>().ok()),
+  ) else {
+    return ApiError::new(
+      StatusCode::UNAUTHORIZED,
+      "missing_signature",
+      "missing network signature",
+    )
+    .into_response();
+  };
+  if unix_secs().abs_diff(timestamp) > MAX_SIGNATURE_AGE_SECS {
+    return ApiError::new(
+      StatusCode::UNAUTHORIZED,
+      "signature_expired",
+      "network signature expired",
+    )
+    .into_response();
+  }
+  // Nested routers only see the rest of the path, but peers sign all of it
+  let path = match request.extensions().get::<OriginalUri>() {
+    Some(OriginalUri(uri)) => uri.path_and_query().map(|path| path.to_string()),
+    None => request.uri().path_and_query().map(|path| path.to_string()),
+  }
+  .unwrap_or_default();
+  let method = request.method().to_string();
+  let (parts, body) = request.into_parts();
+  let Ok(body) = axum::body::to_bytes(body, max_body_bytes).await else {
+    return ApiError::new(
+      StatusCode::PAYLOAD_TOO_LARGE,
+      "payload_too_large",
+      "request body too large",
+    )
+    .into_response();
+  };
+  if mac(&secret, &method, &path, timestamp, &body)
+    .verify_slice(&signature)
+    .is_err()
+  {
+    return ApiError::new(
+      StatusCode::UNAUTHORIZED,
+      "invalid_signature",
+      "invalid network signature",
+    )
+    .into_response();
+  }
+  next.run(Request::from_parts(parts, Body::from(body))).await
+}
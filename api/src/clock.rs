@@ -0,0 +1,34 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::SilocoinError;
+
+/// Where block timestamps come from. Swapping it out lets tests mine blocks
+/// with predictable timestamps.
+pub trait Clock: std::fmt::Debug + Send + Sync {
+  /// Milliseconds since the Unix epoch.
+  fn now_millis(&self) -> Result<u128, SilocoinError>;
+}
+
+/// Reads the system clock, failing rather than panicking if it's set before
+/// the Unix epoch.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+  fn now_millis(&self) -> Result<u128, SilocoinError> {
+    let since_the_epoch = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map_err(|_| SilocoinError::ClockBeforeEpoch)?;
+    Ok(since_the_epoch.as_millis())
+  }
+}
+
+/// Always reports the same time.
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock(pub u128);
+
+impl Clock for FixedClock {
+  fn now_millis(&self) -> Result<u128, SilocoinError> {
+    Ok(self.0)
+  }
+}
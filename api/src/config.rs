@@ -0,0 +1,492 @@
+use std::{
+  collections::{BTreeMap, HashSet},
+  net::{IpAddr, SocketAddr},
+  path::PathBuf,
+  str::FromStr,
+  time::Duration,
+};
+
+use secp256k1::PublicKey;
+use serde::Deserialize;
+
+use crate::Checkpoint;
+
+/// The peer every node bootstraps from when no peers or bootstrap peers are
+/// configured.
+pub const DEFAULT_BOOTSTRAP_PEER: &str = "127.0.0.1:3000";
+
+/// The largest request body accepted, unless overridden with
+/// `SILO_MAX_BODY_BYTES`.
+pub const DEFAULT_MAX_BODY_BYTES: usize = 16 * 1024 * 1024;
+
+/// How long a request may take before it's answered with `408`, unless
+/// overridden with `SILO_REQUEST_TIMEOUT_SECS`.
+pub const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 30;
+/// How long a connection can sit idle before TCP keep-alive probes start,
+/// unless overridden with `SILO_TCP_KEEPALIVE_SECS`.
+pub const DEFAULT_TCP_KEEPALIVE_SECS: u64 = 60;
+/// How many requests are served at once, unless overridden with
+/// `SILO_MAX_CONCURRENT_REQUESTS`. Requests beyond this wait their turn.
+pub const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 1024;
+
+/// How often a node started with `--mine` mines its mempool, unless
+/// overridden with `SILO_MINE_INTERVAL_SECS`.
+pub const DEFAULT_MINE_INTERVAL_SECS: u64 = 10;
+
+/// How many requests per second each client IP may make on average, unless
+/// overridden with `SILO_RATE_LIMIT_PER_SEC`.
+pub const DEFAULT_RATE_LIMIT_PER_SEC: f64 = 20.0;
+/// How many requests a client IP may make in a burst, unless overridden with
+/// `SILO_RATE_LIMIT_BURST`.
+pub const DEFAULT_RATE_LIMIT_BURST: f64 = 40.0;
+
+/// How many chain changes may wait for the chain writer, unless overridden
+/// with `SILO_WRITE_QUEUE_SIZE`. Requests beyond this wait for room.
+pub const DEFAULT_WRITE_QUEUE_SIZE: usize = 256;
+
+/// Command line flags that take a value, e.g. `--peers peers.txt`.
+const VALUE_FLAGS: &[&str] = &[
+  "--peers",
+  "--data-dir",
+  "--genesis",
+  "--config",
+  "--tls-cert",
+  "--tls-key",
+];
+
+/// The config file read when `--config` isn't given, if it exists.
+pub const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+/// Settings read from a TOML config file. Anything left out falls back to the
+/// usual default, and command line flags and env vars override the file.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileConfig {
+  pub port: Option<u16>,
+  /// The address to listen on, `0.0.0.0` by default. May be IPv6, such as `::`.
+  pub bind_host: Option<IpAddr>,
+  pub difficulty: Option<u32>,
+  pub chain_id: Option<u64>,
+  pub sync_interval_secs: Option<u64>,
+  pub data_dir: Option<PathBuf>,
+}
+
+impl FileConfig {
+  pub fn parse(input: &str) -> anyhow::Result<Self> {
+    Ok(toml::from_str(input)?)
+  }
+  /// Reads the file passed with `--config`, or `config.toml` if there is
+  /// one. Only a missing default file is treated as an empty config.
+  pub fn load(args: &[String]) -> anyhow::Result<Self> {
+    let (path, required) = match flag_value(args, "--config") {
+      Some(path) => (path, true),
+      None => (DEFAULT_CONFIG_PATH, false),
+    };
+    match std::fs::read_to_string(path) {
+      Ok(input) => FileConfig::parse(&input),
+      Err(err) if !required && err.kind() == std::io::ErrorKind::NotFound => {
+        Ok(FileConfig::default())
+      }
+      Err(err) => Err(anyhow::anyhow!("failed to read {}, {}", path, err)),
+    }
+  }
+}
+
+/// Returns the value following `flag` on the command line, if present.
+pub fn flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+  args
+    .iter()
+    .position(|arg| arg == flag)
+    .and_then(|index| args.get(index + 1))
+    .map(String::as_str)
+}
+
+/// Whether the bare `flag`, e.g. `--mine`, was passed on the command line.
+pub fn has_flag(args: &[String], flag: &str) -> bool {
+  args.iter().any(|arg| arg == flag)
+}
+
+/// Returns the command line arguments that aren't flags or their values.
+pub fn positional_args(args: &[String]) -> Vec<&str> {
+  let mut positional = Vec::new();
+  let mut iter = args.iter();
+  while let Some(arg) = iter.next() {
+    if VALUE_FLAGS.contains(&arg.as_str()) {
+      iter.next();
+    } else if !arg.starts_with("--") {
+      positional.push(arg.as_str());
+    }
+  }
+  positional
+}
+
+/// Parses peer addresses separated by commas or newlines, skipping (and
+/// warning about) any entry that isn't a valid `SocketAddr`.
+pub fn parse_peers(input: &str) -> HashSet<SocketAddr> {
+  parse_peer_list(input).into_iter().collect()
+}
+
+/// Like `parse_peers`, keeping the addresses in the order they're listed.
+pub fn parse_peer_list(input: &str) -> Vec<SocketAddr> {
+  input
+    .split([',', '\n'])
+    .map(str::trim)
+    .filter(|entry| !entry.is_empty())
+    .filter_map(|entry| match SocketAddr::from_str(entry) {
+      Ok(addr) => Some(addr),
+      Err(..) => {
+        tracing::warn!(entry, "skipping invalid peer address");
+        None
+      }
+    })
+    .collect()
+}
+
+/// The largest request body, in bytes, the API accepts, from
+/// `SILO_MAX_BODY_BYTES`. Larger bodies are rejected with `413`.
+pub fn max_body_bytes() -> usize {
+  std::env::var("SILO_MAX_BODY_BYTES")
+    .ok()
+    .and_then(|value| value.parse().ok())
+    .unwrap_or(DEFAULT_MAX_BODY_BYTES)
+}
+
+/// Tuning for the HTTP server, read from the environment.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ServerConfig {
+  pub request_timeout: Duration,
+  pub tcp_keepalive: Duration,
+  pub max_concurrent_requests: usize,
+  pub rate_limit_per_sec: f64,
+  pub rate_limit_burst: f64,
+  pub write_queue_size: usize,
+}
+
+impl ServerConfig {
+  pub fn from_env() -> Self {
+    ServerConfig {
+      request_timeout: Duration::from_secs(
+        env_value("SILO_REQUEST_TIMEOUT_SECS").unwrap_or(DEFAULT_REQUEST_TIMEOUT_SECS),
+      ),
+      tcp_keepalive: Duration::from_secs(
+        env_value("SILO_TCP_KEEPALIVE_SECS").unwrap_or(DEFAULT_TCP_KEEPALIVE_SECS),
+      ),
+      max_concurrent_requests: env_value("SILO_MAX_CONCURRENT_REQUESTS")
+        .unwrap_or(DEFAULT_MAX_CONCURRENT_REQUESTS),
+      rate_limit_per_sec: env_value("SILO_RATE_LIMIT_PER_SEC")
+        .unwrap_or(DEFAULT_RATE_LIMIT_PER_SEC),
+      rate_limit_burst: env_value("SILO_RATE_LIMIT_BURST").unwrap_or(DEFAULT_RATE_LIMIT_BURST),
+      // A channel can't be empty, so there's always room for one change
+      write_queue_size: env_value("SILO_WRITE_QUEUE_SIZE")
+        .unwrap_or(DEFAULT_WRITE_QUEUE_SIZE)
+        .max(1),
+    }
+  }
+}
+
+/// Parses checkpoints from `SILO_CHECKPOINTS`, written as comma separated
+/// `height:hash` pairs.
+pub fn checkpoints() -> anyhow::Result<Vec<Checkpoint>> {
+  let Ok(input) = std::env::var("SILO_CHECKPOINTS") else {
+    return Ok(Vec::new());
+  };
+  input
+    .split(',')
+    .map(str::trim)
+    .filter(|entry| !entry.is_empty())
+    .map(|entry| {
+      let (height, hash) = entry
+        .split_once(':')
+        .ok_or_else(|| anyhow::anyhow!("checkpoint {:?} isn't height:hash", entry))?;
+      Ok(Checkpoint {
+        height: height.parse()?,
+        hash: hash.to_lowercase(),
+      })
+    })
+    .collect()
+}
+
+/// Parses the identity keys of trusted miners from `SILO_TRUSTED_MINERS`,
+/// written as comma separated hex.
+pub fn trusted_miners() -> anyhow::Result<HashSet<PublicKey>> {
+  let Ok(input) = std::env::var("SILO_TRUSTED_MINERS") else {
+    return Ok(HashSet::new());
+  };
+  input
+    .split(',')
+    .map(str::trim)
+    .filter(|entry| !entry.is_empty())
+    .map(|entry| {
+      PublicKey::from_str(entry)
+        .map_err(|err| anyhow::anyhow!("trusted miner {:?} isn't a public key, {}", entry, err))
+    })
+    .collect()
+}
+
+/// The secret shared by every node on a private network, from
+/// `SILO_NETWORK_SECRET`. Peer requests must be signed with it when set.
+pub fn network_secret() -> Option<String> {
+  std::env::var("SILO_NETWORK_SECRET")
+    .ok()
+    .filter(|secret| !secret.is_empty())
+}
+
+/// The PEM certificate chain and private key named by `--tls-cert` and
+/// `--tls-key`. The node serves HTTPS when they're given and plain HTTP
+/// otherwise.
+pub fn tls_files(args: &[String]) -> anyhow::Result<Option<(PathBuf, PathBuf)>> {
+  match (
+    flag_value(args, "--tls-cert"),
+    flag_value(args, "--tls-key"),
+  ) {
+    (Some(cert), Some(key)) => Ok(Some((PathBuf::from(cert), PathBuf::from(key)))),
+    (None, None) => Ok(None),
+    _ => Err(anyhow::anyhow!(
+      "--tls-cert and --tls-key must be given together"
+    )),
+  }
+}
+
+/// A certificate authority to trust when reaching peers over HTTPS, on top
+/// of the system's, from the PEM file `SILO_PEER_CA_FILE`. Networks whose
+/// nodes use self-signed certificates need this.
+pub fn peer_ca() -> anyhow::Result<Option<reqwest::Certificate>> {
+  let Ok(path) = std::env::var("SILO_PEER_CA_FILE") else {
+    return Ok(None);
+  };
+  let pem =
+    std::fs::read(&path).map_err(|err| anyhow::anyhow!("failed to read {}, {}", path, err))?;
+  Ok(Some(reqwest::Certificate::from_pem(&pem)?))
+}
+
+/// How often a node started with `--mine` mines its mempool.
+pub fn mine_interval() -> Duration {
+  Duration::from_secs(env_value("SILO_MINE_INTERVAL_SECS").unwrap_or(DEFAULT_MINE_INTERVAL_SECS))
+}
+
+fn env_value<T: FromStr>(key: &str) -> Option<T> {
+  std::env::var(key).ok().and_then(|value| value.parse().ok())
+}
+
+/// Where the node keeps its files: `--data-dir`, then `SILO_DATA_DIR`, then
+/// the config file's `data_dir`, then a per-port directory under the
+/// platform's usual data location, so several nodes on one machine don't
+/// share files.
+pub fn data_dir(args: &[String], port: &str, file_config: &FileConfig) -> PathBuf {
+  if let Some(path) = flag_value(args, "--data-dir") {
+    return PathBuf::from(path);
+  }
+  if let Ok(path) = std::env::var("SILO_DATA_DIR") {
+    return PathBuf::from(path);
+  }
+  if let Some(path) = &file_config.data_dir {
+    return path.clone();
+  }
+  default_data_root().join("silocoin").join(port)
+}
+
+fn default_data_root() -> PathBuf {
+  let home = || std::env::var_os("HOME").map(PathBuf::from);
+  let root = if cfg!(windows) {
+    std::env::var_os("APPDATA").map(PathBuf::from)
+  } else if cfg!(target_os = "macos") {
+    home().map(|home| home.join("Library").join("Application Support"))
+  } else {
+    std::env::var_os("XDG_DATA_HOME")
+      .map(PathBuf::from)
+      .or_else(|| home().map(|home| home.join(".local").join("share")))
+  };
+  root.unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// Reads the genesis allocation from the file passed with `--genesis` or
+/// named by `SILO_GENESIS`, if either is given. The file is a JSON object
+/// mapping hex public keys to the coins they start with.
+pub fn genesis_allocation(args: &[String]) -> anyhow::Result<Option<BTreeMap<PublicKey, u64>>> {
+  let path = match flag_value(args, "--genesis") {
+    Some(path) => path.to_string(),
+    None => match std::env::var("SILO_GENESIS") {
+      Ok(path) => path,
+      Err(..) => return Ok(None),
+    },
+  };
+  let input = std::fs::read_to_string(path)?;
+  let allocation =
+    crate::public_key_map_hex::deserialize(&mut serde_json::Deserializer::from_str(&input))?;
+  Ok(Some(allocation))
+}
+
+/// Collects the initial peers from the `SILO_PEERS` env var and the file
+/// passed with `--peers`.
+pub fn initial_peers(args: &[String]) -> anyhow::Result<HashSet<SocketAddr>> {
+  let env_peers = std::env::var("SILO_PEERS").ok();
+  let file_peers = match flag_value(args, "--peers") {
+    Some(path) => Some(std::fs::read_to_string(path)?),
+    None => None,
+  };
+  let mut peers = HashSet::new();
+  for input in env_peers.iter().chain(file_peers.iter()) {
+    peers.extend(parse_peers(input));
+  }
+  Ok(peers)
+}
+
+/// The peers to seed the peer set from at startup, in the order to try
+/// them, from the `SILO_BOOTSTRAP_PEERS` env var. Falls back to the default
+/// bootstrap peer when neither it nor any initial peers are given.
+pub fn bootstrap_peers(args: &[String]) -> anyhow::Result<Vec<SocketAddr>> {
+  if let Ok(input) = std::env::var("SILO_BOOTSTRAP_PEERS") {
+    return Ok(parse_peer_list(&input));
+  }
+  if std::env::var("SILO_PEERS").is_err() && flag_value(args, "--peers").is_none() {
+    return Ok(vec![SocketAddr::from_str(DEFAULT_BOOTSTRAP_PEER)?]);
+  }
+  Ok(Vec::new())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse_peers_skips_invalid_entries() {
+    let peers = parse_peers("127.0.0.1:3000, not-an-addr\n10.0.0.2:3001,,300.1.1.1:80\n[::1]:3002");
+
+    let expected: HashSet<SocketAddr> = ["127.0.0.1:3000", "10.0.0.2:3001", "[::1]:3002"]
+      .into_iter()
+      .map(|addr| addr.parse().unwrap())
+      .collect();
+    assert_eq!(peers, expected);
+  }
+
+  #[test]
+  fn parse_peer_list_keeps_the_listed_order() {
+    let peers = parse_peer_list("10.0.0.3:3000,bad,10.0.0.1:3000\n10.0.0.2:3000");
+
+    let expected: Vec<SocketAddr> = ["10.0.0.3:3000", "10.0.0.1:3000", "10.0.0.2:3000"]
+      .into_iter()
+      .map(|addr| addr.parse().unwrap())
+      .collect();
+    assert_eq!(peers, expected);
+  }
+
+  #[test]
+  fn the_data_dir_flag_wins_over_the_config_file() {
+    let file_config = FileConfig {
+      data_dir: Some(PathBuf::from("/from/config")),
+      ..FileConfig::default()
+    };
+    let args = vec![
+      String::from("3000"),
+      String::from("--data-dir"),
+      String::from("/from/flag"),
+    ];
+
+    assert_eq!(
+      data_dir(&args, "3000", &file_config),
+      PathBuf::from("/from/flag")
+    );
+    assert_eq!(
+      data_dir(&args[..1], "3000", &file_config),
+      PathBuf::from("/from/config")
+    );
+  }
+
+  #[test]
+  fn the_default_data_dir_is_per_port() {
+    let args = vec![String::from("3000")];
+
+    let first = data_dir(&args, "3000", &FileConfig::default());
+    let second = data_dir(&args, "3001", &FileConfig::default());
+
+    assert!(first.ends_with("silocoin/3000"));
+    assert_ne!(first, second);
+  }
+
+  #[test]
+  fn genesis_allocation_reads_the_allocation_file() {
+    let path = std::env::temp_dir().join(format!("silo-genesis-{}", std::process::id()));
+    let key = "031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f";
+    std::fs::write(&path, format!(r#"{{"{key}": 1000}}"#)).unwrap();
+    let args = vec![
+      String::from("3000"),
+      String::from("--genesis"),
+      path.display().to_string(),
+    ];
+
+    let allocation = genesis_allocation(&args).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let expected = BTreeMap::from([(PublicKey::from_str(key).unwrap(), 1000)]);
+    assert_eq!(allocation, Some(expected));
+  }
+
+  #[test]
+  fn a_sample_config_file_parses_into_its_settings() {
+    let config = FileConfig::parse(
+      r#"
+        port = 4000
+        bind_host = "::"
+        difficulty = 18
+        chain_id = 7
+        sync_interval_secs = 30
+        data_dir = "/var/lib/silocoin"
+      "#,
+    )
+    .unwrap();
+
+    assert_eq!(
+      config,
+      FileConfig {
+        port: Some(4000),
+        bind_host: Some("::".parse().unwrap()),
+        difficulty: Some(18),
+        chain_id: Some(7),
+        sync_interval_secs: Some(30),
+        data_dir: Some(PathBuf::from("/var/lib/silocoin")),
+      }
+    );
+  }
+
+  #[test]
+  fn a_config_file_may_leave_settings_out_but_not_misspell_them() {
+    assert_eq!(
+      FileConfig::parse("difficulty = 12").unwrap(),
+      FileConfig {
+        difficulty: Some(12),
+        ..FileConfig::default()
+      }
+    );
+    assert_eq!(FileConfig::parse("").unwrap(), FileConfig::default());
+    assert!(FileConfig::parse("dificulty = 12").is_err());
+  }
+
+  #[test]
+  fn a_named_config_file_must_exist() {
+    let missing = std::env::temp_dir().join(format!("silo-missing-{}.toml", std::process::id()));
+    let args = vec![
+      String::from("3000"),
+      String::from("--config"),
+      missing.display().to_string(),
+    ];
+
+    assert!(FileConfig::load(&args).is_err());
+  }
+
+  #[test]
+  fn initial_peers_reads_the_peers_file() {
+    let path = std::env::temp_dir().join(format!("silo-peers-{}", std::process::id()));
+    std::fs::write(&path, "10.0.0.1:3000\nnonsense\n10.0.0.2:3000\n").unwrap();
+    let args = vec![
+      String::from("3000"),
+      String::from("--peers"),
+      path.display().to_string(),
+    ];
+
+    let peers = initial_peers(&args).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(peers.contains(&"10.0.0.1:3000".parse().unwrap()));
+    assert!(peers.contains(&"10.0.0.2:3000".parse().unwrap()));
+  }
+}
@@ -0,0 +1,63 @@
+use thiserror::Error;
+
+use crate::{InsufficientFunds, MiningError, VerifyError, PRUNE_CONFIRMATION_DEPTH};
+
+/// Everything the library's public API can fail with, so callers can match
+/// on what went wrong.
+#[derive(Debug, Error)]
+pub enum SilocoinError {
+  /// A transaction is badly signed or malformed.
+  #[error("invalid transaction, {0}")]
+  InvalidTransaction(#[from] VerifyError),
+  /// A key or signature couldn't be parsed.
+  #[error("invalid key, {0}")]
+  InvalidKey(#[from] secp256k1::Error),
+  /// A mnemonic isn't valid BIP39.
+  #[error("invalid mnemonic, {0}")]
+  InvalidMnemonic(#[from] bip39::Error),
+  #[error(transparent)]
+  InsufficientFunds(#[from] InsufficientFunds),
+  /// A payment the node won't make for any reason other than funds.
+  #[error("{0}")]
+  PaymentRefused(String),
+  /// The sender already has as many unconfirmed transactions in the mempool
+  /// as it's allowed.
+  #[error("sender already has {0} unconfirmed transactions waiting to be mined")]
+  TooManyPending(usize),
+  /// A chain or block failed validation.
+  #[error("{0}")]
+  InvalidChain(String),
+  /// A chain isn't heavier than ours by the fork choice rule, so it can't
+  /// replace it.
+  #[error("chain isn't heavier than the local chain")]
+  NotHeavier,
+  /// A chain would replace ours by rolling back more blocks than
+  /// `LedgerConfig::max_reorg_depth` allows.
+  #[error("chain forks {depth} blocks back, deeper than the {max} allowed")]
+  ReorgTooDeep { depth: usize, max: usize },
+  #[error(transparent)]
+  Mining(#[from] MiningError),
+  #[error("can't prune within {} blocks of the tip", PRUNE_CONFIRMATION_DEPTH)]
+  PruneTooRecent,
+  /// The node is read-only, so it won't mine or take payments.
+  #[error("node is read-only")]
+  ReadOnly,
+  /// The node was frozen for a backup, so the chain can't change.
+  #[error("node is frozen for a backup")]
+  Frozen,
+  #[error("system clock is set before the Unix epoch")]
+  ClockBeforeEpoch,
+  #[error(transparent)]
+  Io(#[from] std::io::Error),
+  /// Something couldn't be encoded or decoded as JSON or MessagePack.
+  #[error("{0}")]
+  Serialization(String),
+  #[error(transparent)]
+  Network(#[from] reqwest::Error),
+}
+
+impl From<serde_json::Error> for SilocoinError {
+  fn from(err: serde_json::Error) -> Self {
+    SilocoinError::Serialization(err.to_string())
+  }
+}
@@ -1,296 +1,4449 @@
-use base64::prelude::*;
-use rsa::rand_core::block;
+pub mod app;
+pub mod auth;
+pub mod clock;
+pub mod config;
+pub mod error;
+pub mod merkle;
+pub mod metrics;
+pub mod rate_limit;
+pub mod storage;
+pub mod wire;
+pub mod writer;
+
+use clock::{Clock, SystemClock};
+pub use error::SilocoinError;
+use metrics::Metrics;
+use rand::Rng;
+use reqwest::header;
 use secp256k1::{ecdsa::Signature, Message, PublicKey, Secp256k1, SecretKey};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use sha2::{Digest, Sha256};
 use std::{
-  collections::{HashMap, HashSet},
-  net::{SocketAddr, SocketAddrV4},
+  collections::{BTreeMap, HashMap, HashSet, VecDeque},
+  net::{IpAddr, SocketAddr},
   str::FromStr,
-  time::Duration,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex, OnceLock,
+  },
+  time::{Duration, Instant},
 };
+use wire::WireFormat;
 
 #[derive(Deserialize, Serialize, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub struct Transaction {
+  #[serde(with = "public_key_hex")]
   from: PublicKey,
+  #[serde(with = "public_key_hex")]
   to: PublicKey,
   amount: u64,
+  /// A short note from the sender, covered by the signature.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  memo: Option<String>,
   signature: String,
 }
 
-fn message_bytes(to: &PublicKey, from_public: &PublicKey, amount: u64) -> Vec<u8> {
+/// Serializes a `PublicKey` as the lowercase hex of its 33 byte compressed
+/// form, so our wire and disk formats don't depend on secp256k1's own serde
+/// implementation.
+pub mod public_key_hex {
+  use secp256k1::PublicKey;
+  use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+  pub fn serialize<S: Serializer>(
+    public_key: &PublicKey,
+    serializer: S,
+  ) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&super::to_hex(&public_key.serialize()))
+  }
+
+  pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PublicKey, D::Error> {
+    let hex = String::deserialize(deserializer)?;
+    let bytes = super::from_hex(&hex).ok_or_else(|| D::Error::custom("public key must be hex"))?;
+    PublicKey::from_slice(&bytes).map_err(D::Error::custom)
+  }
+}
+
+/// `public_key_hex` for an optional key, left out when `None`.
+pub mod optional_public_key_hex {
+  use secp256k1::PublicKey;
+  use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+  pub fn serialize<S: Serializer>(
+    public_key: &Option<PublicKey>,
+    serializer: S,
+  ) -> Result<S::Ok, S::Error> {
+    match public_key {
+      Some(public_key) => super::public_key_hex::serialize(public_key, serializer),
+      None => serializer.serialize_none(),
+    }
+  }
+
+  pub fn deserialize<'de, D: Deserializer<'de>>(
+    deserializer: D,
+  ) -> Result<Option<PublicKey>, D::Error> {
+    let Some(hex) = Option::<String>::deserialize(deserializer)? else {
+      return Ok(None);
+    };
+    let bytes = super::from_hex(&hex).ok_or_else(|| D::Error::custom("public key must be hex"))?;
+    PublicKey::from_slice(&bytes)
+      .map(Some)
+      .map_err(D::Error::custom)
+  }
+}
+
+/// Serializes a map keyed by `PublicKey` as a JSON object keyed by the keys'
+/// hex, the same form `public_key_hex` uses.
+pub mod public_key_map_hex {
+  use secp256k1::PublicKey;
+  use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+  use std::collections::BTreeMap;
+
+  pub fn serialize<S: Serializer, V: Serialize>(
+    map: &BTreeMap<PublicKey, V>,
+    serializer: S,
+  ) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(
+      map
+        .iter()
+        .map(|(public_key, value)| (super::to_hex(&public_key.serialize()), value)),
+    )
+  }
+
+  pub fn deserialize<'de, D: Deserializer<'de>, V: Deserialize<'de>>(
+    deserializer: D,
+  ) -> Result<BTreeMap<PublicKey, V>, D::Error> {
+    BTreeMap::<String, V>::deserialize(deserializer)?
+      .into_iter()
+      .map(|(hex, value)| {
+        let bytes =
+          super::from_hex(&hex).ok_or_else(|| D::Error::custom("public key must be hex"))?;
+        let public_key = PublicKey::from_slice(&bytes).map_err(D::Error::custom)?;
+        Ok((public_key, value))
+      })
+      .collect()
+  }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+  if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+    return None;
+  }
+  (0..hex.len())
+    .step_by(2)
+    .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).ok())
+    .collect()
+}
+
+/// How far ahead of our clock a block's timestamp may be by default.
+pub const DEFAULT_MAX_CLOCK_SKEW: Duration = Duration::from_secs(10 * 60);
+
+/// How many transactions the mempool holds by default.
+pub const DEFAULT_MAX_MEMPOOL_SIZE: usize = 10_000;
+
+/// How many unconfirmed transactions one sender may have in the mempool by
+/// default.
+pub const DEFAULT_MAX_PENDING_PER_SENDER: usize = 64;
+
+/// How long a transaction waits in the mempool by default before it's
+/// dropped.
+pub const DEFAULT_MEMPOOL_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// The version of the peer-to-peer protocol this node speaks. Bumped whenever
+/// the block or chain wire format changes incompatibly.
+pub const PROTOCOL_VERSION: u32 = 6;
+
+/// The most blocks `Blockchain::range` returns at once.
+pub const MAX_RANGE_BLOCKS: usize = 500;
+
+/// How many of the newest blocks `Blockchain::prune_before` always keeps, so
+/// a reorg never needs blocks that have been pruned.
+pub const PRUNE_CONFIRMATION_DEPTH: usize = 6;
+
+/// The chain ID used when a node isn't configured with one.
+pub const DEFAULT_CHAIN_ID: u64 = 1;
+/// The proof-of-work difficulty, in leading zero bits, used when a node
+/// isn't configured with one. See `benches/mine.rs` for how this was chosen.
+pub const DEFAULT_DIFFICULTY: u32 = 12;
+
+/// The most bytes of transactions a block may hold. See `Block::weight`.
+pub const MAX_BLOCK_WEIGHT: usize = 1_000_000;
+
+/// The longest memo a transaction may carry, in bytes.
+pub const MAX_MEMO_BYTES: usize = 256;
+
+fn message_bytes(
+  chain_id: u64,
+  to: &PublicKey,
+  from_public: &PublicKey,
+  amount: u64,
+  memo: Option<&str>,
+) -> Vec<u8> {
   let mut message = Vec::new();
+  // Binding the chain ID stops a transaction being replayed on another network
+  message.extend(chain_id.to_be_bytes());
   message.extend(from_public.to_string().bytes());
   message.extend(to.to_string().bytes());
   message.extend(amount.to_be_bytes());
+  // Left out when there's no memo so older signatures stay valid
+  if let Some(memo) = memo {
+    message.extend((memo.len() as u64).to_be_bytes());
+    message.extend(memo.bytes());
+  }
   let mut hasher = sha2::Sha256::new();
   hasher.update(message);
   hasher.finalize().to_vec()
 }
 
 impl Transaction {
-  pub fn new(to: &PublicKey, from: &SecretKey, amount: u64) -> anyhow::Result<Self> {
+  pub fn new(
+    to: &PublicKey,
+    from: &SecretKey,
+    amount: u64,
+    chain_id: u64,
+  ) -> Result<Self, SilocoinError> {
+    Transaction::with_memo(to, from, amount, None, chain_id)
+  }
+  /// Like `new`, attaching `memo` to the payment.
+  pub fn with_memo(
+    to: &PublicKey,
+    from: &SecretKey,
+    amount: u64,
+    memo: Option<String>,
+    chain_id: u64,
+  ) -> Result<Self, SilocoinError> {
+    if memo
+      .as_ref()
+      .is_some_and(|memo| memo.len() > MAX_MEMO_BYTES)
+    {
+      return Err(VerifyError::MemoTooLong.into());
+    }
     let context = Secp256k1::new();
     let from_public = PublicKey::from_secret_key(&context, from);
-    let message = message_bytes(to, &from_public, amount);
+    if from_public == *to {
+      return Err(VerifyError::SelfTransfer.into());
+    }
+    let message = message_bytes(chain_id, to, &from_public, amount, memo.as_deref());
     let signature = context.sign_ecdsa(&Message::from_digest_slice(message.as_slice())?, from);
     Ok(Transaction {
-      to: to.clone(),
+      to: *to,
       from: from_public,
       amount,
+      memo,
       signature: signature.to_string(),
     })
   }
-  pub fn verify(&self) -> anyhow::Result<bool> {
+  pub fn from(&self) -> &PublicKey {
+    &self.from
+  }
+  pub fn to(&self) -> &PublicKey {
+    &self.to
+  }
+  pub fn amount(&self) -> u64 {
+    self.amount
+  }
+  pub fn memo(&self) -> Option<&str> {
+    self.memo.as_deref()
+  }
+  pub fn signature(&self) -> &str {
+    &self.signature
+  }
+  /// Every field of the transaction in a fixed byte layout: both keys
+  /// compressed, the amount big-endian, then the memo (if any) and the
+  /// signature, each prefixed with its length. Merkle leaves hash this, so a
+  /// block hash commits to its transactions' signatures as well as what they
+  /// pay.
+  pub fn encode(&self) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(self.size());
+    bytes.extend(self.from.serialize());
+    bytes.extend(self.to.serialize());
+    bytes.extend(self.amount.to_be_bytes());
+    if let Some(memo) = self.memo() {
+      bytes.extend((memo.len() as u64).to_be_bytes());
+      bytes.extend(memo.bytes());
+    }
+    bytes.extend((self.signature.len() as u64).to_be_bytes());
+    bytes.extend(self.signature.bytes());
+    bytes
+  }
+  /// The bytes this transaction takes up in a block: both keys compressed,
+  /// the amount, the memo with its length and the DER signature.
+  pub fn size(&self) -> usize {
+    let memo = self.memo().map_or(0, |memo| 8 + memo.len());
+    33 + 33 + 8 + memo + self.signature.len() / 2
+  }
+  /// Checks the signature, failing for transactions signed for any chain
+  /// other than `chain_id`, and that the transaction pays someone other than
+  /// its sender. Both keys are valid curve points, since a `PublicKey` can't
+  /// hold anything else.
+  pub fn verify(&self, chain_id: u64) -> Result<(), VerifyError> {
+    if self.memo().is_some_and(|memo| memo.len() > MAX_MEMO_BYTES) {
+      return Err(VerifyError::MemoTooLong);
+    }
+    if self.from == self.to {
+      return Err(VerifyError::SelfTransfer);
+    }
     let context = Secp256k1::new();
-    let message = message_bytes(&self.to, &self.from, self.amount);
-    let signature = Signature::from_str(&self.signature)?;
-    Ok(
-      context
-        .verify_ecdsa(
-          &Message::from_digest_slice(&message.as_slice())?,
-          &signature,
-          &self.from,
-        )
-        .is_ok(),
-    )
+    let message = message_bytes(chain_id, &self.to, &self.from, self.amount, self.memo());
+    let message =
+      Message::from_digest_slice(message.as_slice()).expect("message_bytes is a SHA-256 digest");
+    let signature =
+      Signature::from_str(&self.signature).map_err(|_| VerifyError::MalformedSignature)?;
+    let mut normalized = signature;
+    normalized.normalize_s();
+    if normalized != signature {
+      return Err(VerifyError::NonCanonicalSignature);
+    }
+    context
+      .verify_ecdsa(&message, &signature, &self.from)
+      .map_err(|_| VerifyError::SignatureMismatch)
+  }
+}
+
+/// Why `Transaction::verify` rejected a transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyError {
+  /// The signature isn't a hex DER encoded ECDSA signature.
+  MalformedSignature,
+  /// The signature uses the high-S form, which would let anyone change a
+  /// transaction's signature without invalidating it.
+  NonCanonicalSignature,
+  /// The signature wasn't made by `from` over this transaction on this chain.
+  SignatureMismatch,
+  /// The memo is longer than `MAX_MEMO_BYTES`.
+  MemoTooLong,
+  /// `from` and `to` are the same key.
+  SelfTransfer,
+}
+
+impl std::fmt::Display for VerifyError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      VerifyError::MalformedSignature => write!(f, "signature is malformed"),
+      VerifyError::NonCanonicalSignature => {
+        write!(f, "signature isn't in canonical low-S form")
+      }
+      VerifyError::SignatureMismatch => write!(f, "signature doesn't match the transaction"),
+      VerifyError::MemoTooLong => write!(f, "memo is longer than {} bytes", MAX_MEMO_BYTES),
+      VerifyError::SelfTransfer => write!(f, "sender and recipient are the same key"),
+    }
   }
 }
 
+impl std::error::Error for VerifyError {}
+
 // Lets setup the serialization to store it as a Public Key
-pub fn generate_keypair() -> anyhow::Result<(SecretKey, PublicKey)> {
+pub fn generate_keypair() -> Result<(SecretKey, PublicKey), SilocoinError> {
   let secp = Secp256k1::new();
   let mut rng = rand::thread_rng();
   Ok(secp.generate_keypair(&mut rng))
 }
 
-fn now() -> u128 {
-  use std::time::{SystemTime, UNIX_EPOCH};
-  let start = SystemTime::now();
-  let since_the_epoch = start
-    .duration_since(UNIX_EPOCH)
-    .expect("Time went backwards");
-  since_the_epoch.as_millis()
+/// Generates a keypair along with the 12 word BIP39 mnemonic it can be
+/// restored from with `keypair_from_mnemonic`.
+pub fn generate_mnemonic_keypair() -> Result<(String, SecretKey, PublicKey), SilocoinError> {
+  let entropy: [u8; 16] = rand::thread_rng().gen();
+  let mnemonic = bip39::Mnemonic::from_entropy(&entropy)?;
+  let (secret_key, public_key) = keypair_from_mnemonic(&mnemonic.to_string())?;
+  Ok((mnemonic.to_string(), secret_key, public_key))
+}
+
+/// Derives a keypair from a BIP39 mnemonic. The secret key is the first 32
+/// bytes of the mnemonic's BIP39 seed (with an empty passphrase), so the same
+/// words always give the same key. This isn't BIP32 derivation, so other
+/// wallets won't arrive at the same key from these words.
+pub fn keypair_from_mnemonic(phrase: &str) -> Result<(SecretKey, PublicKey), SilocoinError> {
+  let mnemonic = bip39::Mnemonic::parse_normalized(phrase.trim())?;
+  let seed = mnemonic.to_seed("");
+  let mut secret = [0u8; 32];
+  secret.copy_from_slice(&seed[..32]);
+  generate_keypair_from_seed(secret)
+}
+
+/// Uses `seed` as the secret key, so the same seed always gives the same
+/// keypair. Fails for the vanishingly few seeds that aren't valid secp256k1
+/// keys, such as all zeroes. Use `generate_keypair` for fresh keys.
+pub fn generate_keypair_from_seed(seed: [u8; 32]) -> Result<(SecretKey, PublicKey), SilocoinError> {
+  let secret_key = SecretKey::from_slice(&seed)?;
+  let public_key = PublicKey::from_secret_key(&Secp256k1::new(), &secret_key);
+  Ok((secret_key, public_key))
+}
+
+fn leading_zero_bits(bytes: &[u8]) -> u32 {
+  let mut bits = 0;
+  for byte in bytes {
+    bits += byte.leading_zeros();
+    if *byte != 0 {
+      break;
+    }
+  }
+  bits
+}
+
+/// Stops a block being mined from another thread. Clones share the same
+/// flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+  pub fn cancel(&self) {
+    self.0.store(true, Ordering::Relaxed);
+  }
+  pub fn is_cancelled(&self) -> bool {
+    self.0.load(Ordering::Relaxed)
+  }
+}
+
+/// How a block's nonce is searched for.
+#[derive(Debug, Clone)]
+pub struct MiningOptions {
+  /// How many threads search at once.
+  pub threads: usize,
+  /// How long to search before giving up, or forever if `None`.
+  pub timeout: Option<Duration>,
+  /// Gives up on the search once cancelled.
+  pub cancel: CancelToken,
+}
+
+impl Default for MiningOptions {
+  fn default() -> Self {
+    MiningOptions {
+      threads: 1,
+      timeout: None,
+      cancel: CancelToken::default(),
+    }
+  }
+}
+
+/// Why mining stopped without finding a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MiningError {
+  /// No nonce meeting the difficulty was found within the timeout.
+  TimedOut(Duration),
+  /// The search was cancelled, usually because the tip it was building on
+  /// was replaced.
+  Cancelled,
+}
+
+impl std::fmt::Display for MiningError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      MiningError::TimedOut(timeout) => write!(f, "no block found within {:?}", timeout),
+      MiningError::Cancelled => write!(f, "mining was cancelled"),
+    }
+  }
 }
 
+impl std::error::Error for MiningError {}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Hash, Eq, PartialEq)]
-struct Block {
+pub struct Block {
   time: u128,
-  transaction: Transaction,
+  transactions: Vec<Transaction>,
+  /// The coins each account starts with. Only the genesis block has any.
+  #[serde(
+    default,
+    skip_serializing_if = "BTreeMap::is_empty",
+    with = "public_key_map_hex"
+  )]
+  allocation: BTreeMap<PublicKey, u64>,
   prev_block_hash: String,
+  /// The number of leading zero bits `hash` was mined to.
+  difficulty: u32,
   nonce: u64,
+  /// The identity key of the node that mined the block, covered by `hash`.
+  /// Blocks mined before nodes signed them have none.
+  #[serde(
+    default,
+    skip_serializing_if = "Option::is_none",
+    with = "optional_public_key_hex"
+  )]
+  miner: Option<PublicKey>,
+  /// `miner`'s signature over `hash`.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  miner_signature: Option<String>,
   hash: String,
 }
 
 impl Block {
-  pub fn new(transaction: Transaction, prev_block_hash: String) -> anyhow::Result<Self> {
-    let mut block = Block {
-      time: now(),
-      transaction,
+  pub fn new(
+    transactions: Vec<Transaction>,
+    prev_block_hash: String,
+    chain_id: u64,
+    difficulty: u32,
+    mining: MiningOptions,
+    clock: &dyn Clock,
+  ) -> Result<Self, SilocoinError> {
+    Block::mined(
+      clock.now_millis()?,
+      transactions,
       prev_block_hash,
+      chain_id,
+      difficulty,
+      mining,
+      None,
+    )
+  }
+  pub fn hash(&self) -> &str {
+    &self.hash
+  }
+  pub fn prev_hash(&self) -> &str {
+    &self.prev_block_hash
+  }
+  pub fn nonce(&self) -> u64 {
+    self.nonce
+  }
+  /// When the block was mined, in milliseconds since the Unix epoch.
+  pub fn timestamp(&self) -> u128 {
+    self.time
+  }
+  pub fn transactions(&self) -> &[Transaction] {
+    &self.transactions
+  }
+  pub fn allocation(&self) -> &BTreeMap<PublicKey, u64> {
+    &self.allocation
+  }
+  pub fn difficulty(&self) -> u32 {
+    self.difficulty
+  }
+  /// The identity key of the node that mined and signed the block, if any.
+  pub fn miner(&self) -> Option<&PublicKey> {
+    self.miner.as_ref()
+  }
+  /// The fixed first block of every chain. Mining it single threaded is
+  /// deterministic, so every node on the network agrees on its hash.
+  fn genesis(
+    chain_id: u64,
+    difficulty: u32,
+    allocation: BTreeMap<PublicKey, u64>,
+    mining: MiningOptions,
+  ) -> Result<Self, SilocoinError> {
+    let mut block = Block {
+      time: 0,
+      transactions: vec![],
+      allocation,
+      prev_block_hash: String::from("0"),
+      difficulty,
       nonce: 0,
+      miner: None,
+      miner_signature: None,
       hash: String::new(),
     };
-    let difficulty = 2;
-    block.mine(difficulty)?;
+    block.mine(chain_id, mining)?;
     Ok(block)
   }
-  fn mine(&mut self, difficulty: usize) -> anyhow::Result<()> {
-    while !self.verify_hash(difficulty) {
-      self.nonce += 1;
-      self.hash = self.calculate_hash()?;
+  fn mined(
+    time: u128,
+    transactions: Vec<Transaction>,
+    prev_block_hash: String,
+    chain_id: u64,
+    difficulty: u32,
+    mining: MiningOptions,
+    miner: Option<&SecretKey>,
+  ) -> Result<Self, SilocoinError> {
+    let context = Secp256k1::new();
+    let mut block = Block {
+      time,
+      transactions,
+      allocation: BTreeMap::new(),
+      prev_block_hash,
+      difficulty,
+      nonce: 0,
+      miner: miner.map(|miner| PublicKey::from_secret_key(&context, miner)),
+      miner_signature: None,
+      hash: String::new(),
+    };
+    if block.weight() > MAX_BLOCK_WEIGHT {
+      return Err(SilocoinError::InvalidChain(format!(
+        "block weighs {} bytes, more than the {} allowed",
+        block.weight(),
+        MAX_BLOCK_WEIGHT
+      )));
     }
+    block.mine(chain_id, mining)?;
+    if let Some(miner) = miner {
+      let signature = context.sign_ecdsa(&block.hash_message(), miner);
+      block.miner_signature = Some(signature.to_string());
+    }
+    Ok(block)
+  }
+  /// Searches for a nonce meeting the block's difficulty across
+  /// `mining.threads` worker threads. Thread `n` tries nonces `n`,
+  /// `n + threads`, `n + 2 * threads` and so on, and every thread stops as
+  /// soon as any of them succeeds, or all of them give up with
+  /// `MiningError::TimedOut` once `mining.timeout` passes or
+  /// `MiningError::Cancelled` once `mining.cancel` is cancelled.
+  fn mine(&mut self, chain_id: u64, mining: MiningOptions) -> Result<(), MiningError> {
+    let threads = mining.threads.max(1) as u64;
+    let deadline = mining.timeout.map(|timeout| Instant::now() + timeout);
+    let found = AtomicBool::new(false);
+    // Only the nonce changes between attempts
+    let merkle_root = merkle::root(self.merkle_leaves());
+    let results = std::thread::scope(|scope| {
+      let workers: Vec<_> = (0..threads)
+        .map(|offset| {
+          let mut candidate = self.clone();
+          candidate.nonce = offset;
+          let found = &found;
+          let cancel = &mining.cancel;
+          let merkle_root = &merkle_root;
+          scope.spawn(move || -> Option<Block> {
+            while !found.load(Ordering::Relaxed) && !cancel.is_cancelled() {
+              candidate.hash = candidate.hash_with_merkle_root(chain_id, merkle_root);
+              if candidate.verify_hash() {
+                found.store(true, Ordering::Relaxed);
+                return Some(candidate);
+              }
+              if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                found.store(true, Ordering::Relaxed);
+                return None;
+              }
+              candidate.nonce = candidate.nonce.wrapping_add(threads);
+            }
+            None
+          })
+        })
+        .collect();
+      workers
+        .into_iter()
+        .map(|worker| worker.join().expect("mining thread panicked"))
+        .collect::<Vec<_>>()
+    });
+    // Several threads can succeed at once; keep the lowest nonce among them
+    let Some(block) = results
+      .into_iter()
+      .flatten()
+      .min_by_key(|block| block.nonce)
+    else {
+      // Workers only stop without a block once cancelled or the timeout passes
+      if mining.cancel.is_cancelled() {
+        return Err(MiningError::Cancelled);
+      }
+      return Err(MiningError::TimedOut(mining.timeout.unwrap_or_default()));
+    };
+    *self = block;
     Ok(())
   }
-  fn verify_hash(&self, difficulty: usize) -> bool {
-    let target = "0".repeat(difficulty);
-    self.hash.starts_with(&target)
+  /// Whether the SHA-256 digest behind `hash` starts with at least
+  /// `difficulty` zero bits.
+  fn verify_hash(&self) -> bool {
+    let Some(digest) = from_hex(&self.hash) else {
+      return false;
+    };
+    leading_zero_bits(&digest) >= self.difficulty
+  }
+  /// The total size of the block's transactions, which may be at most
+  /// `MAX_BLOCK_WEIGHT`. The header is the same size in every block, so it
+  /// isn't counted.
+  pub fn weight(&self) -> usize {
+    self.transactions.iter().map(Transaction::size).sum()
+  }
+  fn merkle_leaves(&self) -> Vec<[u8; 32]> {
+    self.transactions.iter().map(merkle::leaf_hash).collect()
+  }
+  /// The hex root of the Merkle tree over the block's transactions, which
+  /// the block's hash commits to. See `merkle`.
+  pub fn merkle_root(&self) -> String {
+    to_hex(&merkle::root(self.merkle_leaves()))
+  }
+  /// The sibling hashes proving the transaction at `tx_index` is in this
+  /// block, checked with `merkle::verify_merkle_proof`. Empty if the block
+  /// has no such transaction.
+  pub fn merkle_proof(&self, tx_index: usize) -> Vec<String> {
+    merkle::proof(self.merkle_leaves(), tx_index)
+  }
+  /// Whether the block is within `MAX_BLOCK_WEIGHT`, every transaction is
+  /// correctly signed and `hash` is both the real hash of this block and
+  /// meets the difficulty it claims. Whether that difficulty is the one the
+  /// chain requires is up to the chain.
+  fn verify(&self, chain_id: u64) -> bool {
+    if self.weight() > MAX_BLOCK_WEIGHT {
+      return false;
+    }
+    for transaction in &self.transactions {
+      if transaction.verify(chain_id).is_err() {
+        return false;
+      }
+    }
+    self.hash == self.calculate_hash(chain_id) && self.verify_hash() && self.verify_miner()
+  }
+  /// Whether `miner_signature` is `miner`'s signature over the hash, or
+  /// neither is set.
+  fn verify_miner(&self) -> bool {
+    match (&self.miner, &self.miner_signature) {
+      (None, None) => true,
+      (Some(miner), Some(signature)) => Signature::from_str(signature).is_ok_and(|signature| {
+        Secp256k1::verification_only()
+          .verify_ecdsa(&self.hash_message(), &signature, miner)
+          .is_ok()
+      }),
+      _ => false,
+    }
+  }
+  /// The hash as a message for the miner to sign. A hash that isn't 32 bytes
+  /// of hex gives a message no signature matches, and fails `verify` anyway.
+  fn hash_message(&self) -> Message {
+    let digest = from_hex(&self.hash)
+      .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+      .unwrap_or_default();
+    Message::from_digest(digest)
+  }
+  /// Hashes the block's fields in a fixed byte layout: integers big-endian,
+  /// and variable-length fields prefixed with their length, so no two blocks
+  /// share an encoding. Transactions are covered by their Merkle root.
+  fn calculate_hash(&self, chain_id: u64) -> String {
+    self.hash_with_merkle_root(chain_id, &merkle::root(self.merkle_leaves()))
   }
-  fn verify(&self, difficulty: usize) -> anyhow::Result<bool> {
-    Ok(self.transaction.verify()? && self.verify_hash(difficulty))
+  /// `calculate_hash` with the Merkle root already worked out.
+  fn hash_with_merkle_root(&self, chain_id: u64, merkle_root: &[u8; 32]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(chain_id.to_be_bytes());
+    hasher.update(self.time.to_be_bytes());
+    hasher.update((self.transactions.len() as u64).to_be_bytes());
+    hasher.update(merkle_root);
+    hasher.update((self.prev_block_hash.len() as u64).to_be_bytes());
+    hasher.update(self.prev_block_hash.as_bytes());
+    hasher.update(self.difficulty.to_be_bytes());
+    hasher.update(self.nonce.to_be_bytes());
+    hasher.update((self.allocation.len() as u64).to_be_bytes());
+    for (public_key, amount) in &self.allocation {
+      hasher.update(public_key.serialize());
+      hasher.update(amount.to_be_bytes());
+    }
+    // Left out when there's no miner so unsigned blocks keep their hashes
+    if let Some(miner) = &self.miner {
+      hasher.update(miner.serialize());
+    }
+    to_hex(&hasher.finalize())
   }
-  fn calculate_hash(&self) -> anyhow::Result<String> {
-    let headers = format!(
-      "{}{:?}{}{}",
+}
+
+/// A block waiting to be mined, from `Ledger::start_mining`. Mining it takes
+/// a while, so it's done without holding the ledger and handed back with
+/// `Ledger::finish_mining`.
+#[derive(Debug)]
+pub struct MiningJob {
+  time: u128,
+  transactions: Vec<Transaction>,
+  prev_block_hash: String,
+  chain_id: u64,
+  difficulty: u32,
+  mining: MiningOptions,
+  /// Signs the block once it's mined.
+  miner: SecretKey,
+}
+
+impl MiningJob {
+  /// Searches for the block's nonce, blocking until one is found or the job
+  /// times out or is cancelled.
+  pub fn mine(self) -> Result<MinedBlock, SilocoinError> {
+    let mine_start = Instant::now();
+    let block = Block::mined(
       self.time,
-      message_bytes(
-        &self.transaction.to,
-        &self.transaction.from,
-        self.transaction.amount
-      ),
+      self.transactions,
       self.prev_block_hash,
-      self.nonce
-    );
-    let mut hasher = Sha256::new();
-    hasher.update(headers);
-    Ok(String::from(&BASE64_STANDARD.encode(hasher.finalize())))
+      self.chain_id,
+      self.difficulty,
+      self.mining,
+      Some(&self.miner),
+    )?;
+    Ok(MinedBlock {
+      block,
+      mining_time: mine_start.elapsed(),
+    })
   }
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone, Hash, Eq, PartialEq)]
-pub struct Blockchain {
-  chain: Vec<Block>,
+/// A block `MiningJob::mine` found, ready for `Ledger::finish_mining`.
+#[derive(Debug)]
+pub struct MinedBlock {
+  block: Block,
+  mining_time: Duration,
 }
 
-impl Blockchain {
-  pub fn new() -> anyhow::Result<Self> {
-    Ok(Blockchain { chain: vec![] })
+/// A block hash the node trusts at a given height. Chains with a different
+/// block at that height are rejected however popular they are.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+  pub height: usize,
+  pub hash: String,
+}
+
+/// Why `Blockchain::append_block` refused a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AppendBlockError {
+  /// The block is badly signed, mis-hashed or doesn't meet the difficulty.
+  Invalid,
+  /// The block is valid but doesn't build on our tip, so one of us is on a
+  /// different fork or behind.
+  DoesNotConnect,
+  /// The block is timestamped before our tip, or too far ahead of our clock.
+  BadTimestamp,
+  /// We've already appended this block.
+  AlreadySeen,
+}
+
+impl std::fmt::Display for AppendBlockError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      AppendBlockError::Invalid => write!(f, "block is invalid"),
+      AppendBlockError::DoesNotConnect => write!(f, "block doesn't connect to the tip"),
+      AppendBlockError::BadTimestamp => write!(f, "block has an implausible timestamp"),
+      AppendBlockError::AlreadySeen => write!(f, "block was already appended"),
+    }
   }
-  pub fn add_block(&mut self, transaction: Transaction) -> anyhow::Result<()> {
-    let last_block = &self.chain.last();
-    let prev_hash = if let Some(block) = last_block {
-      block.hash.clone()
-    } else {
-      String::from("0")
-    };
-    let new_block = Block::new(transaction, prev_hash)?;
-    self.chain.push(new_block);
-    Ok(())
+}
+
+impl std::error::Error for AppendBlockError {}
+
+/// Why `Blockchain::append_blocks` refused a sequence of blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AppendBlocksError {
+  /// The position of the first block refused.
+  pub index: usize,
+  pub reason: AppendBlockError,
+}
+
+impl std::fmt::Display for AppendBlocksError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "block {}: {}", self.index, self.reason)
   }
 }
 
-#[derive(Debug, Clone)]
-pub struct Ledger {
-  chain: Blockchain,
-  addr: SocketAddr,
-  pending_transactions: Vec<Transaction>,
-  peers: HashSet<SocketAddr>,
+impl std::error::Error for AppendBlocksError {}
+
+/// Stands in for the blocks before `height` once they've been pruned.
+#[derive(Deserialize, Serialize, Debug, Clone, Hash, Eq, PartialEq)]
+pub struct Snapshot {
+  /// The number of blocks pruned, and so the height of the first block kept.
+  pub height: usize,
+  pub genesis_hash: String,
+  /// The hash of the last pruned block, which the first block kept links to.
+  pub prev_hash: String,
+  /// How much each account's balance changed over the pruned blocks.
+  #[serde(with = "public_key_map_hex")]
+  pub balance_deltas: BTreeMap<PublicKey, i64>,
 }
 
-impl Ledger {
-  pub fn new(initial_peers: HashSet<SocketAddr>, addr: SocketAddr) -> anyhow::Result<Self> {
-    Ok(Self {
-      chain: Blockchain::new()?,
-      peers: initial_peers,
-      addr,
-      pending_transactions: Vec::new(),
+/// Which end of the chain a listing of blocks starts from.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+  /// Oldest first, as the chain is stored.
+  #[default]
+  Asc,
+  /// Newest first, starting from the tip.
+  Desc,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Hash, Eq, PartialEq)]
+pub struct Blockchain {
+  chain_id: u64,
+  /// The number of leading zero bits each block's hash must have.
+  difficulty: u32,
+  /// Set once blocks have been pruned from the front of `chain`.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  snapshot: Option<Snapshot>,
+  chain: Vec<Block>,
+}
+
+impl Blockchain {
+  pub fn new(chain_id: u64, difficulty: u32) -> Result<Self, SilocoinError> {
+    Blockchain::with_allocation(
+      chain_id,
+      difficulty,
+      BTreeMap::new(),
+      MiningOptions::default(),
+    )
+  }
+  /// A chain whose genesis block hands out `allocation`, mined with
+  /// `mining`. Nodes only agree on a genesis block, and so a network, if they
+  /// use the same allocation.
+  pub fn with_allocation(
+    chain_id: u64,
+    difficulty: u32,
+    allocation: BTreeMap<PublicKey, u64>,
+    mining: MiningOptions,
+  ) -> Result<Self, SilocoinError> {
+    Ok(Blockchain {
+      chain_id,
+      difficulty,
+      snapshot: None,
+      chain: vec![Block::genesis(chain_id, difficulty, allocation, mining)?],
     })
   }
-  pub fn get_balance(&self, public_key: &PublicKey) -> anyhow::Result<i64> {
-    let mut balance: i64 = 100;
-    for transaction in self.chain.chain.iter().map(|element| &element.transaction) {
-      // If the user is not involved with the transaction
-      // ignore it
-      if public_key != &transaction.from && public_key != &transaction.to {
-        continue;
-      } else if public_key == &transaction.from && public_key == &transaction.to {
+  pub fn difficulty(&self) -> u32 {
+    self.difficulty
+  }
+  pub fn chain_id(&self) -> u64 {
+    self.chain_id
+  }
+  pub fn snapshot(&self) -> Option<&Snapshot> {
+    self.snapshot.as_ref()
+  }
+  /// Iterates over the blocks that haven't been pruned.
+  pub fn iter(&self) -> std::slice::Iter<'_, Block> {
+    self.chain.iter()
+  }
+  /// How many blocks `iter` yields, which leaves out pruned blocks.
+  pub fn len(&self) -> usize {
+    self.chain.len()
+  }
+  pub fn is_empty(&self) -> bool {
+    self.chain.is_empty()
+  }
+  /// How many blocks the chain has, counting pruned blocks and genesis, which
+  /// is also the height of the next block.
+  pub fn height(&self) -> usize {
+    self.pruned_len() + self.chain.len()
+  }
+  fn pruned_len(&self) -> usize {
+    self.snapshot.as_ref().map_or(0, |snapshot| snapshot.height)
+  }
+  /// The block at `height`, if it hasn't been pruned.
+  pub fn block_at(&self, height: usize) -> Option<&Block> {
+    self.chain.get(height.checked_sub(self.pruned_len())?)
+  }
+  pub fn genesis_hash(&self) -> Option<&str> {
+    match &self.snapshot {
+      Some(snapshot) => Some(snapshot.genesis_hash.as_str()),
+      None => self.chain.first().map(|block| block.hash.as_str()),
+    }
+  }
+  /// Whether `other` grew from the same genesis block as this chain, and so
+  /// belongs to the same network.
+  pub fn shares_genesis_with(&self, other: &Blockchain) -> bool {
+    self.chain_id == other.chain_id
+      && self.difficulty == other.difficulty
+      && self.genesis_hash() == other.genesis_hash()
+  }
+  /// The first height at which `other` holds a different block from this
+  /// chain, or this chain's length if `other` holds all of its blocks.
+  pub fn fork_height(&self, other: &Blockchain) -> usize {
+    // Blocks either chain has pruned are covered by a snapshot both agree on
+    let checked_from = self.pruned_len().max(other.pruned_len());
+    (checked_from..self.height())
+      .find(|height| {
+        other.block_at(*height).map(|block| &block.hash)
+          != self.block_at(*height).map(|block| &block.hash)
+      })
+      .unwrap_or(self.height())
+  }
+  /// Checks every block is valid and links onto the one before it, naming
+  /// the first block that doesn't.
+  pub fn validate(&self) -> Result<(), SilocoinError> {
+    let mut prev_hash = self
+      .snapshot
+      .as_ref()
+      .map_or("0", |snapshot| snapshot.prev_hash.as_str());
+    let mut prev_time = 0;
+    for (height, block) in (self.pruned_len()..).zip(&self.chain) {
+      if block.prev_block_hash != prev_hash {
+        return Err(SilocoinError::InvalidChain(format!(
+          "block {} doesn't link to the block before it",
+          height
+        )));
+      }
+      if block.time < prev_time {
+        return Err(SilocoinError::InvalidChain(format!(
+          "block {} is timestamped before the block before it",
+          height
+        )));
+      }
+      // `Block::verify` checks these too, but this names the culprit. The
+      // signed message covers `from`, so a swapped sender fails here.
+      for transaction in &block.transactions {
+        if let Err(err) = transaction.verify(self.chain_id) {
+          return Err(SilocoinError::InvalidChain(format!(
+            "transaction {} in block {} is invalid, {}",
+            transaction.signature, height, err
+          )));
+        }
+      }
+      if block.difficulty != self.difficulty {
+        return Err(SilocoinError::InvalidChain(format!(
+          "block {} was mined at difficulty {} instead of {}",
+          height, block.difficulty, self.difficulty
+        )));
+      }
+      if !block.verify(self.chain_id) {
+        return Err(SilocoinError::InvalidChain(format!(
+          "block {} is invalid",
+          height
+        )));
+      }
+      if height > 0 && !block.allocation.is_empty() {
+        return Err(SilocoinError::InvalidChain(format!(
+          "block {} allocates coins outside of genesis",
+          height
+        )));
+      }
+      prev_hash = &block.hash;
+      prev_time = block.time;
+    }
+    if self.chain.is_empty() {
+      return Err(SilocoinError::InvalidChain(String::from(
+        "chain has no genesis block",
+      )));
+    }
+    Ok(())
+  }
+  pub fn is_valid(&self) -> bool {
+    self.validate().is_ok()
+  }
+  /// Checks the chain agrees with every checkpoint it's long enough to
+  /// reach. Checkpoints behind a pruning snapshot can't be checked and are
+  /// skipped.
+  pub fn check_checkpoints(&self, checkpoints: &[Checkpoint]) -> Result<(), SilocoinError> {
+    for checkpoint in checkpoints {
+      let Some(block) = self.block_at(checkpoint.height) else {
         continue;
-      } else if public_key == &transaction.to {
-        balance += transaction.amount as i64;
-      } else if public_key == &transaction.from {
-        balance -= transaction.amount as i64;
+      };
+      if block.hash != checkpoint.hash {
+        return Err(SilocoinError::InvalidChain(format!(
+          "block {} contradicts the checkpoint {}",
+          checkpoint.height, checkpoint.hash
+        )));
       }
     }
-    Ok(balance)
+    Ok(())
   }
-  pub async fn send(
+  /// Mines `transactions` into a new block on the tip as `mining` says,
+  /// timestamped by `clock`, returning its hash.
+  pub fn add_block(
     &mut self,
-    to: &PublicKey,
-    from: &SecretKey,
-    amount: u64,
-  ) -> anyhow::Result<()> {
-    let context = Secp256k1::new();
-    let from_public_key = PublicKey::from_secret_key(&context, &from);
-    let from_balance = self.get_balance(&from_public_key)?;
-    if amount as i64 > from_balance {
-      return Err(anyhow::Error::msg("insufficient funds for transaction"));
+    transactions: Vec<Transaction>,
+    mining: MiningOptions,
+    clock: &dyn Clock,
+  ) -> Result<String, SilocoinError> {
+    let new_block = Block::new(
+      transactions,
+      self.tip_hash(),
+      self.chain_id,
+      self.difficulty,
+      mining,
+      clock,
+    )?;
+    let hash = new_block.hash.clone();
+    self.chain.push(new_block);
+    Ok(hash)
+  }
+  /// Appends an already mined `block` to the tip, checking it first.
+  pub fn append_block(&mut self, block: Block) -> Result<(), AppendBlockError> {
+    self.check_next_block(&block, &self.tip_hash(), self.tip_time())?;
+    self.chain.push(block);
+    Ok(())
+  }
+  /// Appends `blocks` in order, but only if every one of them is valid and
+  /// builds on the one before it. Nothing is appended otherwise.
+  pub fn append_blocks(&mut self, blocks: Vec<Block>) -> Result<(), AppendBlocksError> {
+    let mut tip_hash = self.tip_hash();
+    let mut tip_time = self.tip_time();
+    for (index, block) in blocks.iter().enumerate() {
+      self
+        .check_next_block(block, &tip_hash, tip_time)
+        .map_err(|reason| AppendBlocksError { index, reason })?;
+      tip_hash = block.hash.clone();
+      tip_time = block.time;
     }
-    self.chain.add_block(Transaction::new(to, &from, amount)?)?;
-    let client = reqwest::Client::new();
-    for peer in &self.peers {
-      let data = json!({
-        "blockchain": self.get_blockchain()
-      });
-      client
-        .patch(format!("http://{}/chain", peer))
-        .json(&data)
-        .send()
-        .await
-        .ok();
+    self.chain.extend(blocks);
+    Ok(())
+  }
+  /// Checks that `block` is valid and builds on the block hashed `tip_hash`
+  /// and timestamped `tip_time`.
+  fn check_next_block(
+    &self,
+    block: &Block,
+    tip_hash: &str,
+    tip_time: u128,
+  ) -> Result<(), AppendBlockError> {
+    if block.difficulty != self.difficulty
+      || !block.verify(self.chain_id)
+      || !block.allocation.is_empty()
+    {
+      return Err(AppendBlockError::Invalid);
+    }
+    if block.prev_block_hash != tip_hash {
+      return Err(AppendBlockError::DoesNotConnect);
+    }
+    if block.time < tip_time {
+      return Err(AppendBlockError::BadTimestamp);
     }
     Ok(())
   }
-  pub fn get_blockchain(&self) -> Blockchain {
-    self.chain.clone()
+  /// Returns the blocks with heights in `from..to`, clamped to the chain and
+  /// to at most `MAX_RANGE_BLOCKS` blocks. Newest-first ranges keep the
+  /// blocks nearest `to`, so the tip comes first.
+  pub fn range(&self, from: usize, to: usize, order: Order) -> Vec<Block> {
+    let to = to.min(self.height());
+    match order {
+      Order::Asc => self.slice(from, to.min(from.saturating_add(MAX_RANGE_BLOCKS)), order),
+      Order::Desc => self.slice(from.max(to.saturating_sub(MAX_RANGE_BLOCKS)), to, order),
+    }
   }
-  pub async fn update_blockchain(&mut self, blockchain: &Blockchain) {
-    println!("{:#?}", blockchain);
-    self.chain = blockchain.clone();
-  }
-  pub async fn sync(&mut self) {
-    // Sync peers first
-    let temp_peers = self.peers.clone();
-    let mut usage_map: HashMap<Blockchain, usize> = HashMap::new();
-    println!("Temp Peers: {:#?}", temp_peers);
-    for peer in &temp_peers {
-      println!("My Address: {}", self.addr);
-      if peer == &self.addr {
-        continue;
-      }
-      println!("http://{}/peers/", peer);
-      match reqwest::Client::new()
-        .post(format!("http://{}/peers/{}", peer, self.addr))
-        .send()
-        .await
-      {
-        Ok(..) => {}
-        Err(err) => println!("{:#?}", err),
-      }
-
-      let wrapped_response = reqwest::get(format!("http://{}/peers", peer)).await;
-      let Ok(response) = wrapped_response else {
-        // Remove peer if there is an error requesting it
-        println!("{:#?}", wrapped_response);
-        continue;
-      };
-      let wrapped_json_response = response.json::<Vec<SocketAddr>>().await;
-      // let wrapped_json_response = response.text().await;
-      let Ok(res_peers) = wrapped_json_response else {
-        println!("{:#?}", wrapped_json_response);
-        continue;
-      };
-      println!("res_peers = {:#?}", res_peers);
-      self.peers.extend(res_peers);
-      let request = reqwest::Client::new()
-        .get(format!("http://{}/chain", peer))
-        .timeout(Duration::from_secs(4))
-        .send()
-        .await;
-      let Ok(response) = request else {
-        // Remove peer if there is an error requesting it
-        println!("failed to get chain, {:#?}", request);
-        continue;
-      };
-      let Ok(blockchain) = response.json::<Blockchain>().await else {
-        println!("failed to parse chain");
-        continue;
-      };
-      let count = *usage_map.get(&blockchain).unwrap_or(&0);
-      usage_map.insert(blockchain, count + 1);
-    }
-    let count = *usage_map.get(&self.chain).unwrap_or(&0);
-    usage_map.insert(self.chain.clone(), count + 1);
-    let (Some(most_popular_blockchain), _) = usage_map.iter().fold(
-      (None, &0usize),
-      |(acc_blockchain, acc_count), (blockchain, count)| {
-        if count > acc_count {
-          (Some(blockchain), count)
-        } else {
-          (acc_blockchain, acc_count)
-        }
-      },
-    ) else {
-      return;
-    };
-    self.update_blockchain(most_popular_blockchain).await;
+  /// Returns the blocks with heights in `from..to`, clamped to the chain, in
+  /// `order`.
+  pub fn slice(&self, from: usize, to: usize, order: Order) -> Vec<Block> {
+    let from = from.max(self.pruned_len());
+    let to = to.min(self.height());
+    if from >= to {
+      return vec![];
+    }
+    let blocks = &self.chain[from - self.pruned_len()..to - self.pruned_len()];
+    match order {
+      Order::Asc => blocks.to_vec(),
+      Order::Desc => blocks.iter().rev().cloned().collect(),
+    }
   }
-  pub fn add_peer(&mut self, new_addr: SocketAddr) {
-    println!("Adding {} as a peer", new_addr);
-    self.peers.insert(new_addr);
-    println!("New Peers List {:#?}", self.peers);
+  /// A copy of this chain with its blocks in `order`. Newest-first copies
+  /// are for display only; they won't validate.
+  pub fn ordered(&self, order: Order) -> Blockchain {
+    Blockchain {
+      chain_id: self.chain_id,
+      difficulty: self.difficulty,
+      snapshot: self.snapshot.clone(),
+      chain: self.slice(0, self.height(), order),
+    }
   }
-  pub fn get_peers(&self) -> HashSet<SocketAddr> {
-    self.peers.clone()
+  pub fn headers(&self) -> Vec<BlockHeader> {
+    (self.pruned_len()..)
+      .zip(&self.chain)
+      .map(|(height, block)| BlockHeader {
+        height,
+        hash: block.hash.clone(),
+        prev_hash: block.prev_block_hash.clone(),
+        merkle_root: block.merkle_root(),
+      })
+      .collect()
+  }
+  /// The height of the last block this chain shares with `headers`, or
+  /// `None` if they don't even share a genesis block.
+  fn common_ancestor(&self, headers: &[BlockHeader]) -> Option<usize> {
+    headers
+      .iter()
+      .take_while(|header| {
+        self
+          .block_at(header.height)
+          .is_some_and(|block| block.hash == header.hash)
+      })
+      .last()
+      .map(|header| header.height)
+  }
+  /// The chain made of our blocks up to `ancestor` followed by `blocks`, or
+  /// `None` if `blocks` don't link onto it.
+  fn spliced(&self, ancestor: usize, blocks: Vec<Block>) -> Option<Blockchain> {
+    let mut chain = self
+      .chain
+      .get(..=ancestor.checked_sub(self.pruned_len())?)?
+      .to_vec();
+    for block in blocks {
+      if block.prev_block_hash != chain.last()?.hash {
+        return None;
+      }
+      chain.push(block);
+    }
+    Some(Blockchain {
+      chain_id: self.chain_id,
+      difficulty: self.difficulty,
+      snapshot: self.snapshot.clone(),
+      chain,
+    })
+  }
+  /// Replaces the blocks before `height` with a snapshot of how they changed
+  /// each balance. The newest `PRUNE_CONFIRMATION_DEPTH` blocks are never
+  /// pruned.
+  pub fn prune_before(&mut self, height: usize) -> Result<(), SilocoinError> {
+    if height + PRUNE_CONFIRMATION_DEPTH > self.height() {
+      return Err(SilocoinError::PruneTooRecent);
+    }
+    if height <= self.pruned_len() {
+      return Ok(());
+    }
+    let snapshot = self
+      .snapshot_at(height)
+      .ok_or_else(|| SilocoinError::InvalidChain(String::from("chain has no genesis block")))?;
+    self.chain.drain(..height - self.pruned_len());
+    self.snapshot = Some(snapshot);
+    Ok(())
+  }
+  /// The snapshot that would replace the blocks before `height`, or `None`
+  /// if some of those blocks have already been pruned away or don't exist.
+  fn snapshot_at(&self, height: usize) -> Option<Snapshot> {
+    if height <= self.pruned_len() || height > self.height() {
+      return None;
+    }
+    let mut balance_deltas = self
+      .snapshot
+      .as_ref()
+      .map(|snapshot| snapshot.balance_deltas.clone())
+      .unwrap_or_default();
+    let pruned_blocks = &self.chain[..height - self.pruned_len()];
+    for (public_key, amount) in pruned_blocks.iter().flat_map(|block| &block.allocation) {
+      *balance_deltas.entry(*public_key).or_insert(0) += *amount as i64;
+    }
+    for transaction in pruned_blocks.iter().flat_map(|block| &block.transactions) {
+      *balance_deltas.entry(transaction.to).or_insert(0) += transaction.amount as i64;
+      *balance_deltas.entry(transaction.from).or_insert(0) -= transaction.amount as i64;
+    }
+    Some(Snapshot {
+      height,
+      genesis_hash: self.genesis_hash()?.to_string(),
+      prev_hash: self.block_at(height - 1)?.hash.clone(),
+      balance_deltas,
+    })
+  }
+  /// Whether our own blocks (or our own snapshot) reproduce `snapshot`
+  /// exactly, so a chain pruned at it can be trusted.
+  pub fn vouches_for(&self, snapshot: &Snapshot) -> bool {
+    self.snapshot.as_ref() == Some(snapshot)
+      || self.snapshot_at(snapshot.height).as_ref() == Some(snapshot)
+  }
+  /// Finds the transaction with the given signature, along with the height
+  /// of the block it was mined in.
+  pub fn find_transaction(&self, signature: &str) -> Option<(usize, Transaction)> {
+    (self.pruned_len()..)
+      .zip(&self.chain)
+      .find_map(|(height, block)| {
+        block
+          .transactions
+          .iter()
+          .find(|transaction| transaction.signature == signature)
+          .map(|transaction| (height, transaction.clone()))
+      })
+  }
+  /// How many of the chain's unpruned blocks were mined and signed by one of
+  /// `miners`.
+  pub fn blocks_mined_by(&self, miners: &HashSet<PublicKey>) -> usize {
+    self
+      .chain
+      .iter()
+      .filter(|block| block.miner.is_some_and(|miner| miners.contains(&miner)))
+      .count()
+  }
+  /// The height of the block holding the transaction with the given
+  /// signature, and the transaction's index within it.
+  pub fn locate_transaction(&self, signature: &str) -> Option<(usize, usize)> {
+    (self.pruned_len()..)
+      .zip(&self.chain)
+      .find_map(|(height, block)| {
+        block
+          .transactions
+          .iter()
+          .position(|transaction| transaction.signature == signature)
+          .map(|index| (height, index))
+      })
+  }
+  /// Every address that has received an allocation or sent or received a
+  /// transaction, including in pruned blocks.
+  pub fn known_addresses(&self) -> HashSet<PublicKey> {
+    let mut addresses: HashSet<PublicKey> = self
+      .snapshot
+      .iter()
+      .flat_map(|snapshot| snapshot.balance_deltas.keys().copied())
+      .collect();
+    for block in &self.chain {
+      addresses.extend(block.allocation.keys());
+      for transaction in &block.transactions {
+        addresses.insert(transaction.from);
+        addresses.insert(transaction.to);
+      }
+    }
+    addresses
+  }
+  /// Every transaction sent or received by `public_key`, oldest first, along
+  /// with the height of the block it was mined in.
+  pub fn transactions_involving(&self, public_key: &PublicKey) -> Vec<(usize, &Transaction)> {
+    (self.pruned_len()..)
+      .zip(&self.chain)
+      .flat_map(|(height, block)| {
+        block
+          .transactions
+          .iter()
+          .map(move |transaction| (height, transaction))
+      })
+      .filter(|(_, transaction)| transaction.from == *public_key || transaction.to == *public_key)
+      .collect()
+  }
+  /// How `public_key`'s balance changed in each block that moved it, oldest
+  /// first, as `(height, delta)`. Allocations aren't included, and neither
+  /// are pruned blocks, which only the snapshot's total accounts for.
+  pub fn balance_deltas_for(&self, public_key: &PublicKey) -> Vec<(usize, i64)> {
+    (self.pruned_len()..)
+      .zip(&self.chain)
+      .filter_map(|(height, block)| {
+        let delta: i64 = block
+          .transactions
+          .iter()
+          .map(|transaction| {
+            if transaction.to == *public_key {
+              transaction.amount as i64
+            } else if transaction.from == *public_key {
+              -(transaction.amount as i64)
+            } else {
+              0
+            }
+          })
+          .sum();
+        (delta != 0).then_some((height, delta))
+      })
+      .collect()
+  }
+  /// Transactions per second over the newest `window_blocks` blocks, from
+  /// the transactions mined after the first of them and the time between
+  /// the first and last. Zero when there are fewer than two blocks to go on
+  /// or they share a timestamp. The genesis block is left out, as its
+  /// timestamp is always zero.
+  pub fn recent_tps(&self, window_blocks: usize) -> f64 {
+    let mined = if self.pruned_len() == 0 {
+      self.chain.get(1..).unwrap_or_default()
+    } else {
+      &self.chain
+    };
+    let window = &mined[mined.len().saturating_sub(window_blocks)..];
+    let (Some(first), Some(last)) = (window.first(), window.last()) else {
+      return 0.0;
+    };
+    let elapsed_millis = last.time.saturating_sub(first.time);
+    if elapsed_millis == 0 {
+      return 0.0;
+    }
+    let transactions: usize = window[1..]
+      .iter()
+      .map(|block| block.transactions.len())
+      .sum();
+    transactions as f64 * 1000.0 / elapsed_millis as f64
+  }
+  /// How many blocks deep the transaction is, counting the block it was
+  /// mined in, or `None` if it isn't in the chain.
+  pub fn confirmations(&self, signature: &str) -> Option<usize> {
+    let (height, _) = self.find_transaction(signature)?;
+    Some(self.height() - height)
+  }
+  fn tip_hash(&self) -> String {
+    match (self.chain.last(), &self.snapshot) {
+      (Some(block), _) => block.hash.clone(),
+      (None, Some(snapshot)) => snapshot.prev_hash.clone(),
+      (None, None) => String::from("0"),
+    }
+  }
+  /// The tip block's timestamp, or zero if every block has been pruned.
+  fn tip_time(&self) -> u128 {
+    self.chain.last().map_or(0, |block| block.time)
+  }
+}
+
+impl<'a> IntoIterator for &'a Blockchain {
+  type Item = &'a Block;
+  type IntoIter = std::slice::Iter<'a, Block>;
+
+  fn into_iter(self) -> Self::IntoIter {
+    self.iter()
+  }
+}
+
+/// The parts of a block needed to check how it links into a chain, and that
+/// a transaction is in it, without its transactions.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct BlockHeader {
+  pub height: usize,
+  pub hash: String,
+  pub prev_hash: String,
+  /// The root `merkle::verify_merkle_proof` checks a transaction against.
+  pub merkle_root: String,
+}
+
+/// What one sync round learned from our peers, gathered by
+/// `Ledger::poll_peers` and applied by `Ledger::finish_sync`.
+#[derive(Debug, Default)]
+pub struct SyncRound {
+  /// Our chain's height and tip when the round started.
+  height: usize,
+  tip_hash: String,
+  /// How many peers we tried to reach.
+  peers_contacted: usize,
+  /// How many peers were on that same tip.
+  matching_peers: usize,
+  /// Valid peer chains that differ from ours, and how many peers hold each.
+  votes: HashMap<Blockchain, usize>,
+  gossiped_peers: Vec<Vec<PeerAnnouncement>>,
+  metrics: Metrics,
+}
+
+/// The outcome of a sync round, as returned by `Ledger::finish_sync`.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct SyncSummary {
+  pub peers_contacted: usize,
+  /// Whether the round moved us onto a different tip.
+  pub chain_changed: bool,
+  pub height: usize,
+}
+
+/// A summary of a node's view of the network, cheap enough to exchange on
+/// every sync round.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Handshake {
+  /// Peers too old to report this are treated as speaking version 0.
+  #[serde(default)]
+  pub protocol_version: u32,
+  pub addr: SocketAddr,
+  pub height: usize,
+  pub tip_hash: String,
+  /// The key this node signs its announcements with.
+  #[serde(with = "public_key_hex")]
+  pub identity: PublicKey,
+  /// The latest signed announcement from each of our peers that sent one.
+  pub peers: Vec<PeerAnnouncement>,
+}
+
+fn announcement_bytes(
+  chain_id: u64,
+  addr: &SocketAddr,
+  height: usize,
+  identity: &PublicKey,
+) -> Vec<u8> {
+  let addr = addr.to_string();
+  let mut message = Vec::new();
+  message.extend(chain_id.to_be_bytes());
+  message.extend(identity.serialize());
+  message.extend((addr.len() as u64).to_be_bytes());
+  message.extend(addr.bytes());
+  message.extend((height as u64).to_be_bytes());
+  Sha256::digest(message).to_vec()
+}
+
+/// A node's signed claim to be reachable at `addr`, made with its identity
+/// key so other nodes can't announce addresses on its behalf.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct PeerAnnouncement {
+  pub addr: SocketAddr,
+  /// The announcing node's height when it signed.
+  pub height: usize,
+  #[serde(with = "public_key_hex")]
+  pub identity: PublicKey,
+  signature: String,
+}
+
+impl PeerAnnouncement {
+  pub fn new(addr: SocketAddr, height: usize, identity_key: &SecretKey, chain_id: u64) -> Self {
+    let context = Secp256k1::new();
+    let identity = PublicKey::from_secret_key(&context, identity_key);
+    let message = announcement_bytes(chain_id, &addr, height, &identity);
+    let message = Message::from_digest_slice(message.as_slice())
+      .expect("announcement_bytes is a SHA-256 digest");
+    PeerAnnouncement {
+      addr,
+      height,
+      identity,
+      signature: context.sign_ecdsa(&message, identity_key).to_string(),
+    }
+  }
+  /// Checks the announcement was signed by `identity` for `chain_id`.
+  pub fn verify(&self, chain_id: u64) -> Result<(), AnnounceError> {
+    let message = announcement_bytes(chain_id, &self.addr, self.height, &self.identity);
+    let message = Message::from_digest_slice(message.as_slice())
+      .expect("announcement_bytes is a SHA-256 digest");
+    let signature =
+      Signature::from_str(&self.signature).map_err(|_| AnnounceError::MalformedSignature)?;
+    Secp256k1::verification_only()
+      .verify_ecdsa(&message, &signature, &self.identity)
+      .map_err(|_| AnnounceError::SignatureMismatch)
+  }
+}
+
+/// Why `Ledger::check_announcement` turned away a peer's announcement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnnounceError {
+  /// The address can't be a reachable peer. See `Ledger::add_peer`.
+  InvalidAddress,
+  /// The signature isn't a hex DER encoded ECDSA signature.
+  MalformedSignature,
+  /// The signature wasn't made by `identity` over this announcement on this
+  /// chain.
+  SignatureMismatch,
+  /// The address first announced itself with a different identity key.
+  IdentityMismatch,
+}
+
+impl std::fmt::Display for AnnounceError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      AnnounceError::InvalidAddress => write!(f, "invalid peer address"),
+      AnnounceError::MalformedSignature => write!(f, "signature is malformed"),
+      AnnounceError::SignatureMismatch => {
+        write!(f, "signature doesn't match the announcement")
+      }
+      AnnounceError::IdentityMismatch => {
+        write!(f, "address is registered to a different identity key")
+      }
+    }
+  }
+}
+
+impl std::error::Error for AnnounceError {}
+
+/// Describes what software and rules a node is running.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct NodeInfo {
+  pub version: String,
+  pub protocol_version: u32,
+  pub chain_id: u64,
+  pub difficulty: u32,
+}
+
+/// How many times a broadcast to a single peer is attempted.
+const BROADCAST_ATTEMPTS: u32 = 3;
+/// How many consecutive failures a peer can have before it's evicted.
+pub const MAX_PEER_FAILURES: u32 = 5;
+
+/// The most new peers accepted from a single peer's handshake per sync.
+pub const MAX_GOSSIPED_PEERS: usize = 16;
+
+/// How many recently seen block hashes a node remembers to avoid relaying a
+/// block twice.
+pub const MAX_SEEN_BLOCKS: usize = 1024;
+
+/// Exponential backoff from 200ms, plus up to 100ms of jitter so retries from
+/// many nodes don't land at once.
+fn retry_delay(attempt: u32) -> Duration {
+  let jitter = rand::thread_rng().gen_range(0..100);
+  Duration::from_millis(200 * 2u64.pow(attempt - 1) + jitter)
+}
+
+/// What we've seen of a peer, shown at `/debug/peers`.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerHealth {
+  /// Failed requests in a row. Reaching `MAX_PEER_FAILURES` evicts the peer.
+  pub consecutive_failures: u32,
+  /// When a request to the peer last succeeded, in milliseconds since the
+  /// Unix epoch.
+  pub last_success: Option<u128>,
+  /// The height the peer reported in its last handshake.
+  pub last_height: Option<usize>,
+}
+
+fn record_peer_result(
+  peer_health: &Mutex<HashMap<SocketAddr, PeerHealth>>,
+  peer: SocketAddr,
+  success: bool,
+  clock: &dyn Clock,
+) {
+  let mut peer_health = peer_health
+    .lock()
+    .unwrap_or_else(|poisoned| poisoned.into_inner());
+  let health = peer_health.entry(peer).or_default();
+  if success {
+    health.consecutive_failures = 0;
+    health.last_success = clock.now_millis().ok();
+  } else {
+    health.consecutive_failures += 1;
+  }
+}
+
+/// The hashes of the last `MAX_SEEN_BLOCKS` blocks inserted, forgetting the
+/// oldest first.
+#[derive(Debug, Clone, Default)]
+struct SeenBlocks {
+  order: VecDeque<String>,
+  hashes: HashSet<String>,
+}
+
+impl SeenBlocks {
+  fn contains(&self, hash: &str) -> bool {
+    self.hashes.contains(hash)
+  }
+  fn insert(&mut self, hash: String) {
+    if !self.hashes.insert(hash.clone()) {
+      return;
+    }
+    self.order.push_back(hash);
+    if self.order.len() > MAX_SEEN_BLOCKS {
+      if let Some(oldest) = self.order.pop_front() {
+        self.hashes.remove(&oldest);
+      }
+    }
+  }
+}
+
+/// A transaction in the mempool, and when this node received it.
+#[derive(Debug, Clone)]
+struct PendingTransaction {
+  transaction: Transaction,
+  received_at: u128,
+}
+
+/// Identifies the block a payment was mined into.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SendReceipt {
+  pub block_hash: String,
+  pub height: usize,
+  pub signatures: Vec<String>,
+}
+
+/// Where `Ledger::start_payments` put a payment.
+#[derive(Debug)]
+pub(crate) enum Placement {
+  /// In the block being mined.
+  InBlock,
+  /// Left for a later block, as this one is full or already has one of the
+  /// payment's transactions.
+  Deferred,
+  Refused(SilocoinError),
+}
+
+/// Why `Ledger::can_send_batch` refused a payment the sender can't afford.
+/// There are no fees yet, so `required` is just the amount sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InsufficientFunds {
+  /// The total the payment needs.
+  pub required: u64,
+  /// The sender's balance less anything already pending.
+  pub available: i64,
+  /// How much more the sender needs.
+  pub shortfall: u64,
+}
+
+impl std::fmt::Display for InsufficientFunds {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "insufficient funds for transaction, needs {} but only {} is available, {} short",
+      self.required, self.available, self.shortfall
+    )
+  }
+}
+
+impl std::error::Error for InsufficientFunds {}
+
+/// Node-level settings for a `Ledger`.
+#[derive(Debug, Clone)]
+pub struct LedgerConfig {
+  /// Whether peers announcing themselves must answer a handshake at their
+  /// claimed address before being added.
+  pub verify_peers: bool,
+  /// The network this node signs and verifies transactions for.
+  pub chain_id: u64,
+  /// The balance every account starts with before any transactions, on top
+  /// of anything the genesis allocation gives it.
+  pub initial_balance: i64,
+  /// The coins handed out by the genesis block.
+  pub genesis_allocation: BTreeMap<PublicKey, u64>,
+  /// The number of leading zero bits a block's hash needs to be accepted.
+  pub difficulty: u32,
+  /// How many threads to search for a block's nonce with.
+  pub mining_threads: usize,
+  /// How long to search for a nonce before giving up, or forever if `None`.
+  pub mining_timeout: Option<Duration>,
+  /// Timestamps the blocks this node mines.
+  pub clock: Arc<dyn Clock>,
+  /// Block hashes the chain must have at given heights.
+  pub checkpoints: Vec<Checkpoint>,
+  /// Signs requests to peers when set, for private networks. See `auth`.
+  pub network_secret: Option<String>,
+  /// Whether `start_mining` mines a block even when the mempool is empty.
+  pub mine_empty_blocks: bool,
+  /// If set, sends of more than this fraction of the sender's confirmed
+  /// balance are refused unless forced, to catch fat-fingered amounts.
+  pub max_send_fraction: Option<f64>,
+  /// How far ahead of `clock` a block's timestamp may be before the block is
+  /// refused.
+  pub max_clock_skew: Duration,
+  /// The most transactions the mempool holds. Adding one more evicts the
+  /// oldest.
+  pub max_mempool_size: usize,
+  /// The most unconfirmed transactions one sender may have in the mempool.
+  /// More are refused until some are mined, so no one sender can crowd
+  /// everyone else out.
+  pub max_pending_per_sender: usize,
+  /// How long a transaction may wait in the mempool before it's dropped.
+  pub mempool_ttl: Duration,
+  /// Whether the node only serves queries and follows its peers, refusing to
+  /// mine or accept payments, blocks and chains pushed to it.
+  pub read_only: bool,
+  /// Signs this node's peer announcements.
+  pub identity_key: SecretKey,
+  /// The format chains and blocks are asked for in when syncing.
+  pub wire_format: WireFormat,
+  /// Identity keys whose mined blocks count in a chain's favour when peers
+  /// are split between chains. See `Ledger::finish_sync`.
+  pub trusted_miners: HashSet<PublicKey>,
+  /// Whether peers are reached over HTTPS rather than HTTP. Every node on a
+  /// network is expected to do the same.
+  pub peer_tls: bool,
+  /// A certificate authority trusted for peers' HTTPS certificates, on top
+  /// of the system's.
+  pub peer_ca: Option<reqwest::Certificate>,
+  /// The most blocks of the local chain a new chain may roll back, or no
+  /// limit if `None`. Chains that fork deeper than this are refused, whether
+  /// they come from a sync or a client.
+  pub max_reorg_depth: Option<usize>,
+  /// The largest request body, in bytes, the node's API accepts. Larger
+  /// bodies are refused with `413`.
+  pub max_body_bytes: usize,
+}
+
+impl Default for LedgerConfig {
+  fn default() -> Self {
+    LedgerConfig {
+      verify_peers: false,
+      chain_id: DEFAULT_CHAIN_ID,
+      initial_balance: 100,
+      genesis_allocation: BTreeMap::new(),
+      difficulty: DEFAULT_DIFFICULTY,
+      mining_threads: 1,
+      mining_timeout: None,
+      clock: Arc::new(SystemClock),
+      max_send_fraction: None,
+      mine_empty_blocks: false,
+      network_secret: None,
+      checkpoints: Vec::new(),
+      max_clock_skew: DEFAULT_MAX_CLOCK_SKEW,
+      max_mempool_size: DEFAULT_MAX_MEMPOOL_SIZE,
+      max_pending_per_sender: DEFAULT_MAX_PENDING_PER_SENDER,
+      mempool_ttl: DEFAULT_MEMPOOL_TTL,
+      read_only: false,
+      identity_key: SecretKey::new(&mut rand::thread_rng()),
+      wire_format: WireFormat::MsgPack,
+      peer_tls: false,
+      trusted_miners: HashSet::new(),
+      peer_ca: None,
+      max_reorg_depth: None,
+      max_body_bytes: config::DEFAULT_MAX_BODY_BYTES,
+    }
+  }
+}
+
+impl LedgerConfig {
+  /// How this node searches for the nonces of the blocks it mines.
+  fn mining_options(&self) -> MiningOptions {
+    MiningOptions {
+      threads: self.mining_threads,
+      timeout: self.mining_timeout,
+      cancel: CancelToken::default(),
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct Ledger {
+  chain: Blockchain,
+  /// Whether `chain` passed `validate_chain`. It's checked once when the
+  /// ledger is made and again only when the chain is replaced, as blocks
+  /// appended to it are checked on their own.
+  chain_valid: bool,
+  addr: SocketAddr,
+  /// Transactions waiting to be mined, oldest first.
+  pending_transactions: Vec<PendingTransaction>,
+  peers: HashSet<SocketAddr>,
+  config: LedgerConfig,
+  metrics: Metrics,
+  /// Failures, last success and last height of each peer, shared with
+  /// background broadcast tasks.
+  peer_health: Arc<Mutex<HashMap<SocketAddr, PeerHealth>>>,
+  /// Every account's balance, built lazily from `chain` and cleared whenever
+  /// it changes.
+  balance_cache: OnceLock<HashMap<PublicKey, i64>>,
+  /// Blocks we've recently mined or taken from a peer, so each is only
+  /// relayed once however many peers send it to us.
+  seen_blocks: SeenBlocks,
+  /// The latest verified announcement from each peer. A peer's first one
+  /// registers its identity key, which later ones must be signed with.
+  announcements: HashMap<SocketAddr, PeerAnnouncement>,
+  /// Whether each bootstrap peer tried by `bootstrap` answered.
+  bootstrap_results: BTreeMap<SocketAddr, bool>,
+  /// The tip each job from `start_mining` or `start_payments` builds on, and
+  /// the token cancelling it once that's no longer our tip.
+  in_flight_mining: Vec<(String, CancelToken)>,
+  /// Makes every request to peers, so connections to them are pooled and
+  /// reused between requests.
+  client: reqwest::Client,
+}
+
+impl Ledger {
+  pub fn new(
+    initial_peers: HashSet<SocketAddr>,
+    addr: SocketAddr,
+    config: LedgerConfig,
+  ) -> Result<Self, SilocoinError> {
+    let mut client = reqwest::Client::builder();
+    if let Some(peer_ca) = &config.peer_ca {
+      client = client.add_root_certificate(peer_ca.clone());
+    }
+    let mut ledger = Self {
+      client: client.build()?,
+      chain: Blockchain::with_allocation(
+        config.chain_id,
+        config.difficulty,
+        config.genesis_allocation.clone(),
+        config.mining_options(),
+      )?,
+      chain_valid: false,
+      peers: initial_peers,
+      addr,
+      pending_transactions: Vec::new(),
+      config,
+      metrics: Metrics::default(),
+      peer_health: Arc::new(Mutex::new(HashMap::new())),
+      balance_cache: OnceLock::new(),
+      seen_blocks: SeenBlocks::default(),
+      announcements: HashMap::new(),
+      bootstrap_results: BTreeMap::new(),
+      in_flight_mining: Vec::new(),
+    };
+    ledger.chain_valid = ledger.validate_chain(&ledger.chain).is_ok();
+    Ok(ledger)
+  }
+  pub fn config(&self) -> &LedgerConfig {
+    &self.config
+  }
+  /// The client the ledger sends requests to peers with. Clones share its
+  /// connection pool.
+  pub fn client(&self) -> &reqwest::Client {
+    &self.client
+  }
+  /// The URL scheme peers are reached with, see `LedgerConfig::peer_tls`.
+  pub fn peer_scheme(&self) -> &'static str {
+    if self.config.peer_tls {
+      "https"
+    } else {
+      "http"
+    }
+  }
+  pub fn get_balance(&self, public_key: &PublicKey) -> Result<i64, SilocoinError> {
+    let balances = self.get_balances(&[*public_key])?;
+    Ok(balances[public_key])
+  }
+  /// The balance of `public_key` counting only blocks with at least
+  /// `min_confirmations` confirmations, so recent blocks that could still be
+  /// reorganised away don't count yet.
+  pub fn get_final_balance(
+    &self,
+    public_key: &PublicKey,
+    min_confirmations: usize,
+  ) -> Result<i64, SilocoinError> {
+    let balance = self.get_balance(public_key)?;
+    let unconfirmed: i64 = self
+      .chain
+      .balance_deltas_for(public_key)
+      .into_iter()
+      .filter(|(height, _)| self.chain.height() - height < min_confirmations)
+      .map(|(_, delta)| delta)
+      .sum();
+    Ok(balance - unconfirmed)
+  }
+  /// The confirmed balance of `public_key` less what it's already promised
+  /// in the mempool, so the same funds can't be spent twice. Payments to it
+  /// still in the mempool don't count until they're mined, as they may never
+  /// be.
+  pub fn get_available_balance(&self, public_key: &PublicKey) -> Result<i64, SilocoinError> {
+    let pending_debits: i64 = self
+      .pending_transactions
+      .iter()
+      .filter(|pending| pending.transaction.from == *public_key)
+      .map(|pending| pending.transaction.amount as i64)
+      .sum();
+    Ok(self.get_balance(public_key)? - pending_debits)
+  }
+  /// Computes the balance of every key in `public_keys` in a single pass over
+  /// the chain.
+  pub fn get_balances(
+    &self,
+    public_keys: &[PublicKey],
+  ) -> Result<HashMap<PublicKey, i64>, SilocoinError> {
+    let all_balances = self
+      .balance_cache
+      .get_or_init(|| self.compute_all_balances());
+    Ok(
+      public_keys
+        .iter()
+        .map(|public_key| {
+          let balance = all_balances
+            .get(public_key)
+            .copied()
+            .unwrap_or(self.config.initial_balance);
+          (*public_key, balance)
+        })
+        .collect(),
+    )
+  }
+
+  /// Computes the balance of every key that has ever transacted, in a single
+  /// pass over the chain.
+  pub fn compute_all_balances(&self) -> HashMap<PublicKey, i64> {
+    let mut balances: HashMap<PublicKey, i64> = HashMap::new();
+    // Pruned blocks are accounted for by the snapshot that replaced them
+    if let Some(snapshot) = self.chain.snapshot() {
+      for (public_key, delta) in &snapshot.balance_deltas {
+        balances.insert(*public_key, self.config.initial_balance + delta);
+      }
+    }
+    for (public_key, amount) in self.chain.chain.iter().flat_map(|block| &block.allocation) {
+      *balances
+        .entry(*public_key)
+        .or_insert(self.config.initial_balance) += *amount as i64;
+    }
+    for transaction in self
+      .chain
+      .chain
+      .iter()
+      .flat_map(|element| &element.transactions)
+    {
+      *balances
+        .entry(transaction.to)
+        .or_insert(self.config.initial_balance) += transaction.amount as i64;
+      *balances
+        .entry(transaction.from)
+        .or_insert(self.config.initial_balance) -= transaction.amount as i64;
+    }
+    balances
+  }
+  /// Runs every check `send` would, without mining or broadcasting anything.
+  pub fn can_send(
+    &self,
+    to: &PublicKey,
+    from: &SecretKey,
+    amount: u64,
+    force: bool,
+  ) -> Result<(), SilocoinError> {
+    self.can_send_batch(from, &[(*to, amount)], force)
+  }
+  /// Runs every check `send_batch` would, without mining or broadcasting
+  /// anything. `force` skips the `max_send_fraction` policy.
+  pub fn can_send_batch(
+    &self,
+    from: &SecretKey,
+    recipients: &[(PublicKey, u64)],
+    force: bool,
+  ) -> Result<(), SilocoinError> {
+    let from_public_key = PublicKey::from_secret_key(&Secp256k1::new(), from);
+    self.can_pay(&from_public_key, recipients, force, 0)
+  }
+  /// `can_send_batch` for the sender with public key `from_public_key`, who
+  /// has already spent `already_spent` on payments not yet in the chain or
+  /// mempool.
+  fn can_pay(
+    &self,
+    from_public_key: &PublicKey,
+    recipients: &[(PublicKey, u64)],
+    force: bool,
+    already_spent: u64,
+  ) -> Result<(), SilocoinError> {
+    if recipients.is_empty() {
+      return Err(SilocoinError::PaymentRefused(String::from(
+        "no recipients for transaction",
+      )));
+    }
+    let total = recipients
+      .iter()
+      .try_fold(0u64, |total, (_, amount)| total.checked_add(*amount))
+      .ok_or_else(|| SilocoinError::PaymentRefused(String::from("transaction total overflows")))?;
+    let from_balance = self.get_available_balance(from_public_key)?
+      - i64::try_from(already_spent).unwrap_or(i64::MAX);
+    let shortfall = i128::from(total) - i128::from(from_balance);
+    if shortfall > 0 {
+      return Err(
+        InsufficientFunds {
+          required: total,
+          available: from_balance,
+          shortfall: u64::try_from(shortfall).unwrap_or(u64::MAX),
+        }
+        .into(),
+      );
+    }
+    if let Some(max_fraction) = self.config.max_send_fraction.filter(|_| !force) {
+      let confirmed_balance = self.get_balance(from_public_key)?;
+      if total as f64 > confirmed_balance as f64 * max_fraction {
+        return Err(SilocoinError::PaymentRefused(format!(
+          "transaction sends more than {}% of the balance, force it to send anyway",
+          max_fraction * 100.0
+        )));
+      }
+    }
+    Ok(())
+  }
+  #[cfg(test)]
+  pub(crate) fn send(
+    &mut self,
+    to: &PublicKey,
+    from: &SecretKey,
+    amount: u64,
+    memo: Option<String>,
+    force: bool,
+  ) -> Result<SendReceipt, SilocoinError> {
+    self.can_send(to, from, amount, force)?;
+    let transaction = Transaction::with_memo(to, from, amount, memo, self.chain.chain_id)?;
+    self.pay(vec![transaction], force)
+  }
+  /// Mines `transactions` into a block of their own, as the chain writer
+  /// does with a payment when no others are waiting.
+  #[cfg(test)]
+  fn pay(
+    &mut self,
+    transactions: Vec<Transaction>,
+    force: bool,
+  ) -> Result<SendReceipt, SilocoinError> {
+    let (mut placements, job) = self.start_payments([(transactions.as_slice(), force)]);
+    if let Some(Placement::Refused(err)) = placements.pop() {
+      return Err(err);
+    }
+    let job = job?.expect("a lone payment always gets a block");
+    let mined = job.mine()?;
+    self.finish_mining(mined)
+  }
+  /// Checks already signed `transactions`, all from one sender, could be
+  /// sent as `send_batch` would, on top of what `spent` says each sender has
+  /// already spent in the same block.
+  fn check_payment(
+    &self,
+    transactions: &[Transaction],
+    force: bool,
+    spent: &HashMap<PublicKey, u64>,
+  ) -> Result<(), SilocoinError> {
+    let Some(from) = transactions.first().map(|transaction| transaction.from) else {
+      return Err(SilocoinError::PaymentRefused(String::from(
+        "no recipients for transaction",
+      )));
+    };
+    if transactions
+      .iter()
+      .any(|transaction| transaction.from != from)
+    {
+      return Err(SilocoinError::PaymentRefused(String::from(
+        "a payment's transactions must all be from one sender",
+      )));
+    }
+    for transaction in transactions {
+      transaction.verify(self.chain.chain_id)?;
+    }
+    let recipients: Vec<(PublicKey, u64)> = transactions
+      .iter()
+      .map(|transaction| (transaction.to, transaction.amount))
+      .collect();
+    let already_spent = spent.get(&from).copied().unwrap_or(0);
+    self.can_pay(&from, &recipients, force, already_spent)
+  }
+  /// Checks each of `payments`, as `send_batch` would and on top of the ones
+  /// before it, and returns where each went along with the job of mining
+  /// those that fit into one block, if any did. The first payment that
+  /// passes always gets in, so none waits forever. Like `start_mining`, the
+  /// job is cancelled if our tip changes before it's handed to
+  /// `finish_mining`.
+  pub(crate) fn start_payments<'a>(
+    &mut self,
+    payments: impl IntoIterator<Item = (&'a [Transaction], bool)>,
+  ) -> (Vec<Placement>, Result<Option<MiningJob>, SilocoinError>) {
+    let mut spent: HashMap<PublicKey, u64> = HashMap::new();
+    let mut transactions: Vec<Transaction> = Vec::new();
+    let mut weight = 0;
+    let mut placements = Vec::new();
+    for (payment, force) in payments {
+      if let Err(err) = self.check_payment(payment, force, &spent) {
+        placements.push(Placement::Refused(err));
+        continue;
+      }
+      let payment_weight: usize = payment.iter().map(Transaction::size).sum();
+      if !transactions.is_empty()
+        && (weight + payment_weight > MAX_BLOCK_WEIGHT
+          || payment
+            .iter()
+            .any(|transaction| transactions.contains(transaction)))
+      {
+        placements.push(Placement::Deferred);
+        continue;
+      }
+      let total = payment.iter().fold(0u64, |total, transaction| {
+        total.saturating_add(transaction.amount)
+      });
+      let spent = spent.entry(payment[0].from).or_insert(0);
+      *spent = spent.saturating_add(total);
+      weight += payment_weight;
+      transactions.extend_from_slice(payment);
+      placements.push(Placement::InBlock);
+    }
+    if transactions.is_empty() {
+      return (placements, Ok(None));
+    }
+    let job = self.mining_job(transactions).map(|job| {
+      self.track_mining(&job);
+      Some(job)
+    });
+    (placements, job)
+  }
+  /// Leaves an already signed payment in the mempool for the next
+  /// `start_mining`, returning its signature.
+  pub(crate) fn submit(
+    &mut self,
+    transaction: Transaction,
+    force: bool,
+  ) -> Result<String, SilocoinError> {
+    if self.config.read_only {
+      return Err(SilocoinError::ReadOnly);
+    }
+    transaction.verify(self.chain.chain_id)?;
+    self.can_pay(
+      &transaction.from,
+      &[(transaction.to, transaction.amount)],
+      force,
+      0,
+    )?;
+    let pending_from_sender = self
+      .pending_transactions
+      .iter()
+      .filter(|pending| pending.transaction.from == transaction.from)
+      .count();
+    if pending_from_sender >= self.config.max_pending_per_sender {
+      return Err(SilocoinError::TooManyPending(pending_from_sender));
+    }
+    let signature = transaction.signature.clone();
+    self.add_pending(transaction);
+    Ok(signature)
+  }
+  /// Picks the mempool transactions for the next block and returns the job
+  /// of mining them. Transactions that are already mined, badly signed or no
+  /// longer affordable are dropped. With nothing left to mine, there's no
+  /// job unless `mine_empty_blocks` is set. The transactions stay in the
+  /// mempool until `finish_mining`. The job is cancelled if our tip changes
+  /// before then, since its block could no longer be appended.
+  pub(crate) fn start_mining(&mut self) -> Result<Option<MiningJob>, SilocoinError> {
+    self.expire_pending();
+    let pending = std::mem::take(&mut self.pending_transactions);
+    let mut spent: HashMap<PublicKey, i64> = HashMap::new();
+    let mut transactions: Vec<Transaction> = Vec::new();
+    let mut weight = 0;
+    for entry in pending {
+      let transaction = &entry.transaction;
+      if transaction.verify(self.chain.chain_id).is_err()
+        || self
+          .chain
+          .find_transaction(&transaction.signature)
+          .is_some()
+        || transactions.contains(transaction)
+      {
+        continue;
+      }
+      if weight + transaction.size() > MAX_BLOCK_WEIGHT {
+        // Left for the next block
+        self.pending_transactions.push(entry);
+        continue;
+      }
+      let balance = self.get_balance(&transaction.from)?;
+      let spent = spent.entry(transaction.from).or_insert(0);
+      if *spent + transaction.amount as i64 > balance {
+        tracing::info!(
+          signature = transaction.signature,
+          "dropping unaffordable transaction"
+        );
+        continue;
+      }
+      *spent += transaction.amount as i64;
+      weight += transaction.size();
+      transactions.push(transaction.clone());
+      self.pending_transactions.push(entry);
+    }
+    if transactions.is_empty() && !self.config.mine_empty_blocks {
+      return Ok(None);
+    }
+    let job = self.mining_job(transactions)?;
+    self.track_mining(&job);
+    Ok(Some(job))
+  }
+  /// Appends a block mined from a `MiningJob` to our tip and broadcasts it.
+  /// Fails with `MiningError::Cancelled` if the tip it was mined on has
+  /// since been replaced.
+  pub(crate) fn finish_mining(&mut self, mined: MinedBlock) -> Result<SendReceipt, SilocoinError> {
+    let MinedBlock { block, mining_time } = mined;
+    if block.prev_block_hash != self.chain.tip_hash() {
+      return Err(MiningError::Cancelled.into());
+    }
+    let receipt = SendReceipt {
+      block_hash: block.hash.clone(),
+      height: self.chain.height(),
+      signatures: block
+        .transactions
+        .iter()
+        .map(|transaction| transaction.signature.clone())
+        .collect(),
+    };
+    self
+      .chain
+      .append_block(block.clone())
+      .map_err(|err| SilocoinError::InvalidChain(err.to_string()))?;
+    self.invalidate_balance_cache();
+    self.cancel_stale_mining();
+    self.metrics.record_block_mined(mining_time);
+    self
+      .pending_transactions
+      .retain(|pending| !block.transactions.contains(&pending.transaction));
+    self.seen_blocks.insert(block.hash.clone());
+    self.broadcast_block(&block);
+    Ok(receipt)
+  }
+  /// The job of mining `transactions` into a block on the tip.
+  fn mining_job(&self, transactions: Vec<Transaction>) -> Result<MiningJob, SilocoinError> {
+    if self.config.read_only {
+      return Err(SilocoinError::ReadOnly);
+    }
+    Ok(MiningJob {
+      time: self.config.clock.now_millis()?,
+      transactions,
+      prev_block_hash: self.chain.tip_hash(),
+      chain_id: self.chain.chain_id,
+      difficulty: self.chain.difficulty,
+      mining: self.config.mining_options(),
+      miner: self.config.identity_key,
+    })
+  }
+  /// Remembers `job` so `cancel_stale_mining` can cancel it.
+  fn track_mining(&mut self, job: &MiningJob) {
+    self
+      .in_flight_mining
+      .push((job.prev_block_hash.clone(), job.mining.cancel.clone()));
+  }
+  /// Cancels the jobs from `start_mining` and `start_payments` whose tip our
+  /// tip has moved on from, so they can start again on the new tip.
+  fn cancel_stale_mining(&mut self) {
+    let tip_hash = self.chain.tip_hash();
+    self.in_flight_mining.retain(|(prev_hash, cancel)| {
+      if *prev_hash == tip_hash {
+        return true;
+      }
+      cancel.cancel();
+      false
+    });
+  }
+  /// Appends a block a peer mined to our tip and relays it to our other
+  /// peers. Mined transactions leave the mempool.
+  pub(crate) fn append_block(&mut self, block: Block) -> Result<(), AppendBlockError> {
+    if self.seen_blocks.contains(&block.hash) {
+      return Err(AppendBlockError::AlreadySeen);
+    }
+    if self.misses_checkpoint(self.chain.height(), &block) {
+      return Err(AppendBlockError::Invalid);
+    }
+    if self.is_future_dated(&block) {
+      return Err(AppendBlockError::BadTimestamp);
+    }
+    if self.overdraws(std::slice::from_ref(&block)).is_some() {
+      return Err(AppendBlockError::Invalid);
+    }
+    let mined = block.transactions.clone();
+    self.chain.append_block(block.clone())?;
+    self.invalidate_balance_cache();
+    self.cancel_stale_mining();
+    self
+      .pending_transactions
+      .retain(|pending| !mined.contains(&pending.transaction));
+    self.seen_blocks.insert(block.hash.clone());
+    if !self.config.read_only {
+      self.broadcast_block(&block);
+    }
+    Ok(())
+  }
+  /// Appends a run of blocks to our tip all at once, or none of them if any
+  /// is refused. Mined transactions leave the mempool.
+  pub(crate) fn append_blocks(&mut self, blocks: Vec<Block>) -> Result<(), AppendBlocksError> {
+    let height = self.chain.height();
+    if let Some(index) =
+      (0..blocks.len()).find(|index| self.misses_checkpoint(height + index, &blocks[*index]))
+    {
+      return Err(AppendBlocksError {
+        index,
+        reason: AppendBlockError::Invalid,
+      });
+    }
+    if let Some(index) = blocks.iter().position(|block| self.is_future_dated(block)) {
+      return Err(AppendBlocksError {
+        index,
+        reason: AppendBlockError::BadTimestamp,
+      });
+    }
+    if let Some(index) = self.overdraws(&blocks) {
+      return Err(AppendBlocksError {
+        index,
+        reason: AppendBlockError::Invalid,
+      });
+    }
+    let mined: Vec<Transaction> = blocks
+      .iter()
+      .flat_map(|block| block.transactions.iter().cloned())
+      .collect();
+    self.chain.append_blocks(blocks)?;
+    self.invalidate_balance_cache();
+    self.cancel_stale_mining();
+    self
+      .pending_transactions
+      .retain(|pending| !mined.contains(&pending.transaction));
+    Ok(())
+  }
+  /// The position of the first of `blocks` that would spend more than a
+  /// sender holds if appended to our tip, see `find_overdraft`.
+  fn overdraws(&self, blocks: &[Block]) -> Option<usize> {
+    let mut balances = self
+      .balance_cache
+      .get_or_init(|| self.compute_all_balances())
+      .clone();
+    self.find_overdraft(&mut balances, blocks)
+  }
+  /// Whether `block` is timestamped more than `max_clock_skew` ahead of our
+  /// clock. A clock we can't read refuses everything.
+  fn is_future_dated(&self, block: &Block) -> bool {
+    !self
+      .config
+      .clock
+      .now_millis()
+      .is_ok_and(|now| block.time <= now + self.config.max_clock_skew.as_millis())
+  }
+  /// Whether `block` would sit at `height` but a checkpoint names another
+  /// block there.
+  fn misses_checkpoint(&self, height: usize, block: &Block) -> bool {
+    self
+      .config
+      .checkpoints
+      .iter()
+      .any(|checkpoint| checkpoint.height == height && checkpoint.hash != block.hash)
+  }
+  /// Pushes `block` to every peer in the background, retrying each a few
+  /// times with jittered backoff. Peers that still can't be reached count a
+  /// failure towards eviction. A peer answering `409` is behind or on another
+  /// fork and syncs by itself, so that counts as delivered.
+  fn broadcast_block(&self, block: &Block) {
+    let data = json!({ "block": block });
+    for peer in self.peers.iter().filter(|peer| **peer != self.addr) {
+      let peer = *peer;
+      let data = data.clone();
+      let peer_health = self.peer_health.clone();
+      let clock = self.config.clock.clone();
+      let network_secret = self.config.network_secret.clone();
+      let client = self.client.clone();
+      let scheme = self.peer_scheme();
+      tokio::spawn(async move {
+        for attempt in 0..BROADCAST_ATTEMPTS {
+          if attempt > 0 {
+            tokio::time::sleep(retry_delay(attempt)).await;
+          }
+          let response = auth::send_signed(
+            client
+              .post(format!("{}://{}/chain/block", scheme, peer))
+              .json(&data)
+              .timeout(Duration::from_secs(4)),
+            network_secret.as_deref(),
+          )
+          .await;
+          if response.is_ok_and(|response| {
+            response.status().is_success() || response.status() == reqwest::StatusCode::CONFLICT
+          }) {
+            record_peer_result(&peer_health, peer, true, clock.as_ref());
+            return;
+          }
+        }
+        tracing::warn!(%peer, "failed to broadcast block");
+        record_peer_result(&peer_health, peer, false, clock.as_ref());
+      });
+    }
+  }
+  pub fn chain(&self) -> &Blockchain {
+    &self.chain
+  }
+  pub fn get_blockchain(&self) -> Blockchain {
+    self.chain.clone()
+  }
+  /// Replaces the local chain with `blockchain`, see `replace_chain`.
+  pub(crate) fn update_blockchain(&mut self, blockchain: &Blockchain) -> Result<(), SilocoinError> {
+    self.replace_chain(blockchain, self.chain_valid)
+  }
+  /// Replaces the local chain with `blockchain`, refusing chains that grew
+  /// from a different genesis block or fail `validate_chain`. While the local
+  /// chain is valid, `blockchain` must also be heavier than it and fork no
+  /// deeper than `max_reorg_depth`; an invalid local chain can be replaced by
+  /// any valid one. Replacing the chain with itself does nothing.
+  fn replace_chain(
+    &mut self,
+    blockchain: &Blockchain,
+    local_chain_valid: bool,
+  ) -> Result<(), SilocoinError> {
+    if blockchain.height() == self.chain.height() && blockchain.tip_hash() == self.chain.tip_hash()
+    {
+      return Ok(());
+    }
+    if !self.chain.shares_genesis_with(blockchain) {
+      return Err(SilocoinError::InvalidChain(String::from(
+        "chain has a different genesis block",
+      )));
+    }
+    if !self.trusts_snapshot_of(blockchain) {
+      return Err(SilocoinError::InvalidChain(String::from(
+        "chain is pruned at a snapshot we can't check",
+      )));
+    }
+    self.validate_chain(blockchain)?;
+    if local_chain_valid {
+      if self.fork_choice_key(blockchain) <= self.fork_choice_key(&self.chain) {
+        return Err(SilocoinError::NotHeavier);
+      }
+      let depth = self.chain.height() - self.chain.fork_height(blockchain);
+      if let Some(max) = self
+        .config
+        .max_reorg_depth
+        .filter(|max_reorg_depth| depth > *max_reorg_depth)
+      {
+        return Err(SilocoinError::ReorgTooDeep { depth, max });
+      }
+    }
+    let old_chain = std::mem::replace(&mut self.chain, blockchain.clone());
+    self.chain_valid = true;
+    self.invalidate_balance_cache();
+    self.cancel_stale_mining();
+    self.recover_orphaned_transactions(&old_chain);
+    Ok(())
+  }
+  /// How the fork choice ranks `blockchain`: by length, as every block takes
+  /// the same work, then by how many of its blocks trusted miners mined, then
+  /// by lowest tip hash, so every node breaks the last tie the same way.
+  fn fork_choice_key(&self, blockchain: &Blockchain) -> (usize, usize, std::cmp::Reverse<String>) {
+    (
+      blockchain.height(),
+      blockchain.blocks_mined_by(&self.config.trusted_miners),
+      std::cmp::Reverse(blockchain.tip_hash()),
+    )
+  }
+  /// Validates `blockchain`, and checks it against our checkpoints and clock.
+  fn validate_chain(&self, blockchain: &Blockchain) -> Result<(), SilocoinError> {
+    blockchain.validate()?;
+    if let Some(height) = (blockchain.pruned_len()..)
+      .zip(blockchain)
+      .find_map(|(height, block)| self.is_future_dated(block).then_some(height))
+    {
+      return Err(SilocoinError::InvalidChain(format!(
+        "block {} is timestamped too far in the future",
+        height
+      )));
+    }
+    blockchain.check_checkpoints(&self.config.checkpoints)?;
+    let mut balances = blockchain
+      .snapshot()
+      .map(|snapshot| {
+        snapshot
+          .balance_deltas
+          .iter()
+          .map(|(public_key, delta)| (*public_key, self.config.initial_balance + delta))
+          .collect()
+      })
+      .unwrap_or_default();
+    if let Some(index) = self.find_overdraft(&mut balances, blockchain) {
+      return Err(SilocoinError::InvalidChain(format!(
+        "block {} spends more than a sender holds",
+        blockchain.pruned_len() + index
+      )));
+    }
+    Ok(())
+  }
+  /// Applies `blocks` in order to `balances`, which holds every account's
+  /// balance before the first of them, and returns the position of the first
+  /// block with a transaction spending more than its sender holds at that
+  /// point.
+  fn find_overdraft<'a>(
+    &self,
+    balances: &mut HashMap<PublicKey, i64>,
+    blocks: impl IntoIterator<Item = &'a Block>,
+  ) -> Option<usize> {
+    let initial_balance = self.config.initial_balance;
+    for (index, block) in blocks.into_iter().enumerate() {
+      for (public_key, amount) in &block.allocation {
+        *balances.entry(*public_key).or_insert(initial_balance) += *amount as i64;
+      }
+      for transaction in &block.transactions {
+        let held = balances
+          .get(&transaction.from)
+          .copied()
+          .unwrap_or(initial_balance);
+        let Some(amount) = i64::try_from(transaction.amount)
+          .ok()
+          .filter(|amount| *amount <= held)
+        else {
+          return Some(index);
+        };
+        *balances.entry(transaction.from).or_insert(initial_balance) -= amount;
+        *balances.entry(transaction.to).or_insert(initial_balance) += amount;
+      }
+    }
+    None
+  }
+  /// Whether `blockchain` is unpruned, or pruned at a snapshot our own chain
+  /// reproduces.
+  fn trusts_snapshot_of(&self, blockchain: &Blockchain) -> bool {
+    blockchain
+      .snapshot()
+      .is_none_or(|snapshot| self.chain.vouches_for(snapshot))
+  }
+  /// Prunes the local chain before `height`, see `Blockchain::prune_before`.
+  #[cfg(test)]
+  pub(crate) fn prune_before(&mut self, height: usize) -> Result<(), SilocoinError> {
+    self.chain.prune_before(height)?;
+    self.invalidate_balance_cache();
+    Ok(())
+  }
+  /// Logs a reorg if the current chain abandoned blocks from `old_chain`, and
+  /// returns any still-valid transactions only those blocks held to the
+  /// mempool so they can be mined again.
+  fn recover_orphaned_transactions(&mut self, old_chain: &Blockchain) {
+    let fork_height = old_chain.fork_height(&self.chain);
+    let orphaned_blocks = &old_chain.chain[fork_height - old_chain.pruned_len()..];
+    if orphaned_blocks.is_empty() {
+      return;
+    }
+    let mut orphaned_transactions = 0;
+    for transaction in orphaned_blocks.iter().flat_map(|block| &block.transactions) {
+      let still_valid = transaction.verify(self.chain.chain_id).is_ok();
+      if !still_valid
+        || self
+          .chain
+          .find_transaction(&transaction.signature)
+          .is_some()
+        || self
+          .pending_transactions
+          .iter()
+          .any(|pending| pending.transaction == *transaction)
+      {
+        continue;
+      }
+      self.add_pending(transaction.clone());
+      orphaned_transactions += 1;
+    }
+    tracing::warn!(
+      fork_height,
+      dropped_blocks = orphaned_blocks.len(),
+      orphaned_transactions,
+      "chain reorganisation"
+    );
+  }
+  /// Queues `transaction` to be mined, evicting the oldest pending
+  /// transactions if that takes the mempool over `max_mempool_size`.
+  fn add_pending(&mut self, transaction: Transaction) {
+    self.pending_transactions.push(PendingTransaction {
+      transaction,
+      // An unreadable clock makes the transaction expire on the next tick
+      received_at: self.config.clock.now_millis().unwrap_or(0),
+    });
+    let overflow = self
+      .pending_transactions
+      .len()
+      .saturating_sub(self.config.max_mempool_size);
+    for evicted in self.pending_transactions.drain(..overflow) {
+      tracing::info!(
+        signature = evicted.transaction.signature,
+        "evicting transaction from the full mempool"
+      );
+    }
+  }
+  /// Drops pending transactions that have waited longer than `mempool_ttl`.
+  pub(crate) fn expire_pending(&mut self) {
+    let Ok(now) = self.config.clock.now_millis() else {
+      return;
+    };
+    let ttl = self.config.mempool_ttl.as_millis();
+    self.pending_transactions.retain(|pending| {
+      let expired = now.saturating_sub(pending.received_at) > ttl;
+      if expired {
+        tracing::info!(
+          signature = pending.transaction.signature,
+          "dropping expired transaction"
+        );
+      }
+      !expired
+    });
+  }
+  fn invalidate_balance_cache(&mut self) {
+    self.balance_cache = OnceLock::new();
+  }
+  /// Evicts failing peers and returns a copy of the ledger to query peers
+  /// from, so `poll_peers` can run without holding on to this ledger.
+  pub(crate) fn sync_snapshot(&mut self) -> Ledger {
+    self.evict_failing_peers();
+    let mut snapshot = self.clone();
+    snapshot.metrics = Metrics::default();
+    snapshot
+  }
+  /// Asks every peer for its view of the network and downloads any valid
+  /// chain that differs from ours. Nothing is changed until the result is
+  /// passed to `finish_sync`.
+  pub async fn poll_peers(&self) -> SyncRound {
+    let mut round = SyncRound {
+      height: self.chain.height(),
+      tip_hash: self.chain.tip_hash(),
+      ..SyncRound::default()
+    };
+    for peer in &self.peers {
+      if peer == &self.addr {
+        continue;
+      }
+      round.peers_contacted += 1;
+      match auth::send_signed(
+        self
+          .client
+          .post(format!(
+            "{}://{}/peers/{}",
+            self.peer_scheme(),
+            peer,
+            self.addr
+          ))
+          .json(&self.announcement())
+          .timeout(Duration::from_secs(4)),
+        self.network_secret(),
+      )
+      .await
+      {
+        Ok(response) if !response.status().is_success() => {
+          tracing::warn!(%peer, status = %response.status(), "peer refused our announcement")
+        }
+        Ok(..) => {}
+        Err(err) => tracing::warn!(%peer, %err, "failed to announce ourselves"),
+      }
+
+      let response = auth::send_signed(
+        self
+          .client
+          .get(format!("{}://{}/handshake", self.peer_scheme(), peer))
+          .timeout(Duration::from_secs(4)),
+        self.network_secret(),
+      )
+      .await;
+      let response = match response {
+        Ok(response) => response,
+        Err(err) => {
+          // Peers that keep failing are eventually evicted
+          tracing::warn!(%peer, %err, "handshake failed");
+          round.metrics.record_sync(false);
+          self.record_peer_result(*peer, false);
+          continue;
+        }
+      };
+      let handshake = match response.json::<Handshake>().await {
+        Ok(handshake) => handshake,
+        Err(err) => {
+          tracing::warn!(%peer, %err, "peer sent an unreadable handshake");
+          round.metrics.record_sync(false);
+          self.record_peer_result(*peer, false);
+          continue;
+        }
+      };
+      self.record_peer_result(*peer, true);
+      self
+        .lock_peer_health()
+        .entry(*peer)
+        .or_default()
+        .last_height = Some(handshake.height);
+      if handshake.protocol_version != PROTOCOL_VERSION {
+        tracing::warn!(
+          %peer,
+          peer_protocol_version = handshake.protocol_version,
+          protocol_version = PROTOCOL_VERSION,
+          "skipping peer with incompatible protocol version"
+        );
+        continue;
+      }
+      round.gossiped_peers.push(handshake.peers);
+      // A peer on the same tip as us votes for our chain, so there is no
+      // need to download the full chain from it
+      if handshake.height == round.height && handshake.tip_hash == round.tip_hash {
+        round.matching_peers += 1;
+        round.metrics.record_sync(true);
+        continue;
+      }
+      round.metrics.record_chain_download();
+      let Some(blockchain) = self.fetch_peer_chain(peer).await else {
+        tracing::warn!(%peer, "failed to get chain");
+        round.metrics.record_sync(false);
+        continue;
+      };
+      if !self.chain.shares_genesis_with(&blockchain) {
+        tracing::warn!(%peer, "peer is on a chain with a different genesis block");
+        round.metrics.record_sync(false);
+        continue;
+      }
+      if !self.trusts_snapshot_of(&blockchain) {
+        tracing::warn!(%peer, "peer sent a chain pruned at a snapshot we can't check");
+        round.metrics.record_sync(false);
+        continue;
+      }
+      if let Err(err) = self.validate_chain(&blockchain) {
+        tracing::warn!(%peer, %err, "peer sent an invalid chain");
+        round.metrics.record_sync(false);
+        continue;
+      }
+      round.metrics.record_sync(true);
+      *round.votes.entry(blockchain).or_insert(0) += 1;
+    }
+    round
+  }
+  /// Applies what `poll_peers` learned, switching to the best chain among
+  /// those at least as heavy as ours. Chains rank by the fork choice, with
+  /// how many peers vote for each only deciding between chains with the same
+  /// work and trusted blocks.
+  pub(crate) fn finish_sync(&mut self, round: SyncRound) -> SyncSummary {
+    let tip_hash = self.chain.tip_hash();
+    self.expire_pending();
+    self.metrics.merge(&round.metrics);
+    for gossiped_peers in round.gossiped_peers {
+      self.add_gossiped_peers(gossiped_peers);
+    }
+    let mut usage_map = round.votes;
+    // An invalid local chain (and the peers agreeing with it) gets no say, so
+    // any valid peer chain replaces it
+    let local_chain_valid = self.chain_valid;
+    if local_chain_valid {
+      // Peers only vouched for the tip we had when the round started, and
+      // we may have appended blocks since
+      let matching_peers =
+        if self.chain.height() == round.height && self.chain.tip_hash() == round.tip_hash {
+          round.matching_peers
+        } else {
+          0
+        };
+      *usage_map.entry(self.chain.clone()).or_insert(0) += matching_peers + 1;
+    } else {
+      tracing::warn!("local chain is invalid");
+    }
+    // Only chains heavier than ours could replace it. Votes come after work
+    // and trusted blocks, so a crowd of peers can't outvote a chain trusted
+    // miners built, and the tip hash settles the rest the same way on every
+    // node whatever the map's order
+    let local_key = local_chain_valid.then(|| self.fork_choice_key(&self.chain));
+    let most_popular_blockchain = usage_map
+      .iter()
+      .filter(|(blockchain, _)| {
+        local_key
+          .as_ref()
+          .is_none_or(|local_key| self.fork_choice_key(blockchain) >= *local_key)
+      })
+      .max_by_key(|(blockchain, count)| {
+        let (height, trusted_blocks, tip_hash) = self.fork_choice_key(blockchain);
+        (height, trusted_blocks, **count, tip_hash)
+      });
+    if let Some((most_popular_blockchain, _)) = most_popular_blockchain {
+      if let Err(err) = self.replace_chain(most_popular_blockchain, local_chain_valid) {
+        tracing::warn!(
+          %err,
+          tip_hash = most_popular_blockchain.tip_hash(),
+          "keeping the local chain"
+        );
+      }
+    }
+    SyncSummary {
+      peers_contacted: round.peers_contacted,
+      chain_changed: self.chain.tip_hash() != tip_hash,
+      height: self.chain.height(),
+    }
+  }
+  /// Downloads `peer`'s chain, fetching only the blocks after our common
+  /// ancestor when its headers connect to our chain, and the whole chain
+  /// otherwise.
+  async fn fetch_peer_chain(&self, peer: &SocketAddr) -> Option<Blockchain> {
+    if let Some(blockchain) = self.fetch_missing_blocks(peer).await {
+      return Some(blockchain);
+    }
+    tracing::debug!(%peer, "falling back to a full chain download");
+    let response = auth::send_signed(
+      self
+        .client
+        .get(format!("{}://{}/chain", self.peer_scheme(), peer))
+        .header(header::ACCEPT, self.config.wire_format.accept_header())
+        .timeout(Duration::from_secs(4)),
+      self.network_secret(),
+    )
+    .await
+    .inspect_err(|err| tracing::warn!(%peer, %err, "chain download failed"))
+    .ok()?;
+    wire::read_response::<Blockchain>(response).await.ok()
+  }
+  /// Fetches `peer`'s headers, finds where they leave our chain, and
+  /// downloads just the blocks after that point.
+  async fn fetch_missing_blocks(&self, peer: &SocketAddr) -> Option<Blockchain> {
+    let client = &self.client;
+    let headers = auth::send_signed(
+      client
+        .get(format!("{}://{}/chain/headers", self.peer_scheme(), peer))
+        .header(header::ACCEPT, self.config.wire_format.accept_header())
+        .timeout(Duration::from_secs(4)),
+      self.network_secret(),
+    )
+    .await
+    .ok()?;
+    let headers = wire::read_response::<Vec<BlockHeader>>(headers)
+      .await
+      .ok()?;
+    let ancestor = self.chain.common_ancestor(&headers)?;
+    // Header heights are absolute, so a pruned peer sends fewer headers than
+    // its height
+    let height = headers.last()?.height + 1;
+    let mut blocks = Vec::new();
+    let mut from = ancestor + 1;
+    while from < height {
+      let range = auth::send_signed(
+        client
+          .get(format!(
+            "{}://{}/chain/range?from={}&to={}",
+            self.peer_scheme(),
+            peer,
+            from,
+            height
+          ))
+          .header(header::ACCEPT, self.config.wire_format.accept_header())
+          .timeout(Duration::from_secs(4)),
+        self.network_secret(),
+      )
+      .await
+      .ok()?;
+      let range = wire::read_response::<Vec<Block>>(range).await.ok()?;
+      if range.is_empty() {
+        return None;
+      }
+      from += range.len();
+      blocks.extend(range);
+    }
+    self.chain.spliced(ancestor, blocks)
+  }
+  /// Drops peers that have failed `MAX_PEER_FAILURES` times in a row.
+  fn evict_failing_peers(&mut self) {
+    let mut peer_health = self
+      .peer_health
+      .lock()
+      .unwrap_or_else(|poisoned| poisoned.into_inner());
+    peer_health.retain(|peer, health| {
+      if health.consecutive_failures < MAX_PEER_FAILURES {
+        return true;
+      }
+      tracing::info!(
+        %peer,
+        failures = health.consecutive_failures,
+        "evicting failing peer"
+      );
+      self.peers.remove(peer);
+      false
+    });
+  }
+  fn record_peer_result(&self, peer: SocketAddr, success: bool) {
+    record_peer_result(&self.peer_health, peer, success, self.config.clock.as_ref());
+  }
+  fn lock_peer_health(&self) -> std::sync::MutexGuard<'_, HashMap<SocketAddr, PeerHealth>> {
+    self
+      .peer_health
+      .lock()
+      .unwrap_or_else(|poisoned| poisoned.into_inner())
+  }
+  /// The health of every known peer, including ones we haven't heard from.
+  pub fn peer_health(&self) -> BTreeMap<SocketAddr, PeerHealth> {
+    let peer_health = self.lock_peer_health();
+    self
+      .peers
+      .iter()
+      .map(|peer| (*peer, peer_health.get(peer).cloned().unwrap_or_default()))
+      .collect()
+  }
+  /// Adds addresses a peer told us about, accepting at most
+  /// `MAX_GOSSIPED_PEERS` new ones so a single peer can't flood (or eclipse)
+  /// our peer set.
+  fn add_gossiped_peers(&mut self, gossiped_peers: Vec<PeerAnnouncement>) {
+    let mut accepted = 0;
+    for announcement in gossiped_peers {
+      if accepted == MAX_GOSSIPED_PEERS {
+        break;
+      }
+      if announcement.addr == self.addr || self.peers.contains(&announcement.addr) {
+        continue;
+      }
+      let addr = announcement.addr;
+      match self.accept_announcement(announcement) {
+        Ok(..) => accepted += 1,
+        Err(err) => tracing::info!(%addr, %err, "rejecting gossiped peer"),
+      }
+    }
+  }
+  /// Seeds the peer set from the first of `bootstraps` to answer a
+  /// handshake, adding it and the peers it gossips, and returns it. Each
+  /// bootstrap tried is recorded in `bootstrap_results`. If none answer,
+  /// they're all kept as peers so later syncs retry them.
+  pub async fn bootstrap(&mut self, bootstraps: &[SocketAddr]) -> Option<SocketAddr> {
+    for bootstrap in bootstraps {
+      if *bootstrap == self.addr {
+        continue;
+      }
+      let request = auth::send_signed(
+        self
+          .client
+          .get(format!("{}://{}/handshake", self.peer_scheme(), bootstrap))
+          .timeout(Duration::from_secs(4)),
+        self.network_secret(),
+      )
+      .await;
+      let handshake = match request {
+        Ok(response) => response.json::<Handshake>().await.ok(),
+        Err(..) => None,
+      };
+      let Some(handshake) =
+        handshake.filter(|handshake| handshake.protocol_version == PROTOCOL_VERSION)
+      else {
+        tracing::warn!(
+          %bootstrap,
+          "bootstrap peer didn't answer a compatible handshake"
+        );
+        self.bootstrap_results.insert(*bootstrap, false);
+        continue;
+      };
+      tracing::info!(%bootstrap, "bootstrapping");
+      self.bootstrap_results.insert(*bootstrap, true);
+      self.record_peer_result(*bootstrap, true);
+      self.add_peer(*bootstrap);
+      self.add_gossiped_peers(handshake.peers);
+      return Some(*bootstrap);
+    }
+    for bootstrap in bootstraps {
+      if *bootstrap != self.addr {
+        self.add_peer(*bootstrap);
+      }
+    }
+    None
+  }
+  /// Whether each bootstrap peer tried at startup answered.
+  pub fn bootstrap_results(&self) -> &BTreeMap<SocketAddr, bool> {
+    &self.bootstrap_results
+  }
+  /// This node's announcement of its address and height, signed with its
+  /// identity key.
+  pub fn announcement(&self) -> PeerAnnouncement {
+    PeerAnnouncement::new(
+      self.addr,
+      self.chain.height(),
+      &self.config.identity_key,
+      self.chain.chain_id,
+    )
+  }
+  /// Checks `announcement` is for a usable address, is signed, and uses the
+  /// identity key its address first announced with, if any.
+  pub fn check_announcement(&self, announcement: &PeerAnnouncement) -> Result<(), AnnounceError> {
+    if !self.is_valid_peer_addr(&announcement.addr) {
+      return Err(AnnounceError::InvalidAddress);
+    }
+    announcement.verify(self.chain.chain_id)?;
+    match self.announcements.get(&announcement.addr) {
+      Some(registered) if registered.identity != announcement.identity => {
+        Err(AnnounceError::IdentityMismatch)
+      }
+      _ => Ok(()),
+    }
+  }
+  /// Adds the peer behind `announcement` once `check_announcement` passes,
+  /// remembering the announcement to gossip on.
+  pub(crate) fn accept_announcement(
+    &mut self,
+    announcement: PeerAnnouncement,
+  ) -> Result<(), AnnounceError> {
+    self.check_announcement(&announcement)?;
+    self.add_peer(announcement.addr);
+    self.announcements.insert(announcement.addr, announcement);
+    Ok(())
+  }
+  /// Adds `new_addr` to the peer set, returning whether it was accepted.
+  ///
+  /// Unspecified addresses, port 0 and loopback addresses (unless this node
+  /// is itself on loopback) are rejected, since requests to them can never
+  /// reach another node.
+  pub(crate) fn add_peer(&mut self, new_addr: SocketAddr) -> bool {
+    if !self.is_valid_peer_addr(&new_addr) {
+      tracing::info!(%new_addr, "rejecting peer");
+      return false;
+    }
+    if self.peers.insert(new_addr) {
+      tracing::info!(%new_addr, "adding peer");
+    }
+    true
+  }
+  /// Whether any known peer is at `ip`, on any port.
+  pub fn is_peer_ip(&self, ip: IpAddr) -> bool {
+    self.peers.iter().any(|peer| peer.ip() == ip)
+  }
+  pub fn is_valid_peer_addr(&self, addr: &SocketAddr) -> bool {
+    if addr.ip().is_unspecified() || addr.port() == 0 {
+      return false;
+    }
+    !addr.ip().is_loopback() || self.addr.ip().is_loopback()
+  }
+  /// Checks that `announcement` came from a node actually at its address by
+  /// asking that address for a handshake and making sure it reports the same
+  /// address and identity key.
+  pub async fn is_reachable_peer(
+    client: &reqwest::Client,
+    scheme: &str,
+    announcement: &PeerAnnouncement,
+    network_secret: Option<&str>,
+  ) -> bool {
+    let request = auth::send_signed(
+      client
+        .get(format!("{}://{}/handshake", scheme, announcement.addr))
+        .timeout(Duration::from_secs(4)),
+      network_secret,
+    )
+    .await;
+    let Ok(response) = request else {
+      return false;
+    };
+    match response.json::<Handshake>().await {
+      Ok(handshake) => {
+        handshake.addr == announcement.addr && handshake.identity == announcement.identity
+      }
+      Err(..) => false,
+    }
+  }
+  pub fn info(&self) -> NodeInfo {
+    NodeInfo {
+      version: String::from(env!("CARGO_PKG_VERSION")),
+      protocol_version: PROTOCOL_VERSION,
+      chain_id: self.chain.chain_id,
+      difficulty: self.chain.difficulty,
+    }
+  }
+  pub fn handshake(&self) -> Handshake {
+    Handshake {
+      protocol_version: PROTOCOL_VERSION,
+      addr: self.addr,
+      height: self.chain.height(),
+      tip_hash: self.chain.tip_hash(),
+      identity: PublicKey::from_secret_key(&Secp256k1::new(), &self.config.identity_key),
+      peers: self
+        .peers
+        .iter()
+        .filter_map(|peer| self.announcements.get(peer).cloned())
+        .collect(),
+    }
+  }
+  pub fn metrics(&self) -> &Metrics {
+    &self.metrics
+  }
+  /// Renders this node's metrics in the Prometheus text exposition format.
+  pub fn render_metrics(&self) -> String {
+    self.metrics.render(
+      self.chain.height(),
+      self.peers.len(),
+      self.pending_transactions.len(),
+    )
+  }
+  fn network_secret(&self) -> Option<&str> {
+    self.config.network_secret.as_deref()
+  }
+  pub fn get_peers(&self) -> HashSet<SocketAddr> {
+    self.peers.clone()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// A config mining at a difficulty low enough for every block to be found
+  /// at once.
+  fn test_config() -> LedgerConfig {
+    LedgerConfig {
+      difficulty: 1,
+      ..LedgerConfig::default()
+    }
+  }
+
+  /// A ledger at `addr` with no peers and the test config.
+  fn ledger_at(addr: &str) -> Ledger {
+    Ledger::new(HashSet::new(), addr.parse().unwrap(), test_config()).unwrap()
+  }
+
+  /// A ledger with no peers and `config`.
+  fn ledger_with(config: LedgerConfig) -> Ledger {
+    Ledger::new(HashSet::new(), "10.0.0.1:3000".parse().unwrap(), config).unwrap()
+  }
+
+  /// The same keypair every run for the same `seed`.
+  fn keypair(seed: u8) -> (SecretKey, PublicKey) {
+    generate_keypair_from_seed([seed; 32]).unwrap()
+  }
+
+  /// A payment of `amount` from `from` to `to`, signed for the test chain.
+  fn payment(to: &PublicKey, from: &SecretKey, amount: u64) -> Transaction {
+    Transaction::new(to, from, amount, DEFAULT_CHAIN_ID).unwrap()
+  }
+
+  #[test]
+  fn the_same_seed_gives_the_same_keypair() {
+    let seed = [7; 32];
+
+    assert_eq!(
+      generate_keypair_from_seed(seed).unwrap(),
+      generate_keypair_from_seed(seed).unwrap()
+    );
+    assert_ne!(
+      generate_keypair_from_seed(seed).unwrap(),
+      generate_keypair_from_seed([8; 32]).unwrap()
+    );
+    assert!(generate_keypair_from_seed([0; 32]).is_err());
+  }
+
+  #[test]
+  fn add_peer_rejects_unroutable_addresses() {
+    let mut ledger = ledger_at("10.0.0.1:3000");
+    assert!(!ledger.add_peer("0.0.0.0:0".parse().unwrap()));
+    assert!(!ledger.add_peer("0.0.0.0:3000".parse().unwrap()));
+    assert!(!ledger.add_peer("10.0.0.2:0".parse().unwrap()));
+    assert!(!ledger.add_peer("127.0.0.1:3000".parse().unwrap()));
+    assert!(ledger.get_peers().is_empty());
+  }
+
+  #[test]
+  fn add_peer_accepts_routable_addresses() {
+    let mut ledger = ledger_at("10.0.0.1:3000");
+    let peer = "10.0.0.2:3000".parse().unwrap();
+    assert!(ledger.add_peer(peer));
+    assert_eq!(ledger.get_peers(), HashSet::from([peer]));
+  }
+
+  #[test]
+  fn add_peer_accepts_loopback_peers_of_a_loopback_node() {
+    let mut ledger = ledger_at("127.0.0.1:3000");
+    assert!(ledger.add_peer("127.0.0.1:3001".parse().unwrap()));
+  }
+
+  /// An announcement for `addr` signed by a key made from `seed`.
+  fn announcement(addr: SocketAddr, seed: u8) -> PeerAnnouncement {
+    let (identity, _) = keypair(seed);
+    PeerAnnouncement::new(addr, 1, &identity, DEFAULT_CHAIN_ID)
+  }
+
+  #[test]
+  fn only_so_many_gossiped_peers_are_taken_from_one_peer() {
+    let mut ledger = ledger_at("10.0.0.1:3000");
+    let gossiped_peers = (0..300u16)
+      .map(|i| announcement(SocketAddr::from(([10, 0, 1, 1], 3001 + i)), 4))
+      .collect();
+
+    ledger.add_gossiped_peers(gossiped_peers);
+
+    assert_eq!(ledger.get_peers().len(), MAX_GOSSIPED_PEERS);
+  }
+
+  #[test]
+  fn rejected_gossiped_peers_dont_count_towards_the_cap() {
+    let mut ledger = ledger_at("10.0.0.1:3000");
+    let unroutable =
+      (1..=20u16).map(|port| announcement(SocketAddr::from(([0, 0, 0, 0], port)), 4));
+    let routable = (1..=20u8).map(|i| announcement(SocketAddr::from(([10, 0, 1, i], 3000)), 4));
+
+    ledger.add_gossiped_peers(unroutable.chain(routable).collect());
+
+    assert_eq!(ledger.get_peers().len(), MAX_GOSSIPED_PEERS);
+    assert!(ledger.get_peers().iter().all(|peer| peer.port() == 3000));
+  }
+
+  #[test]
+  fn a_signed_announcement_is_accepted() {
+    let mut ledger = ledger_at("10.0.0.1:3000");
+    let addr = SocketAddr::from(([10, 0, 0, 2], 3000));
+
+    assert_eq!(ledger.accept_announcement(announcement(addr, 4)), Ok(()));
+    assert!(ledger.get_peers().contains(&addr));
+  }
+
+  #[test]
+  fn unsigned_and_forged_announcements_are_rejected() {
+    let mut ledger = ledger_at("10.0.0.1:3000");
+    let addr = SocketAddr::from(([10, 0, 0, 2], 3000));
+    let unsigned = PeerAnnouncement {
+      signature: String::new(),
+      ..announcement(addr, 4)
+    };
+    // A taller height than the one that was signed
+    let tampered = PeerAnnouncement {
+      height: 1_000,
+      ..announcement(addr, 4)
+    };
+    // Signed by one key while claiming to be another
+    let forged = PeerAnnouncement {
+      identity: keypair(5).1,
+      ..announcement(addr, 4)
+    };
+
+    assert_eq!(
+      ledger.accept_announcement(unsigned),
+      Err(AnnounceError::MalformedSignature)
+    );
+    assert_eq!(
+      ledger.accept_announcement(tampered),
+      Err(AnnounceError::SignatureMismatch)
+    );
+    assert_eq!(
+      ledger.accept_announcement(forged),
+      Err(AnnounceError::SignatureMismatch)
+    );
+    assert!(ledger.get_peers().is_empty());
+  }
+
+  #[test]
+  fn an_address_keeps_the_identity_it_first_announced() {
+    let mut ledger = ledger_at("10.0.0.1:3000");
+    let addr = SocketAddr::from(([10, 0, 0, 2], 3000));
+    ledger.accept_announcement(announcement(addr, 4)).unwrap();
+
+    assert_eq!(
+      ledger.accept_announcement(announcement(addr, 5)),
+      Err(AnnounceError::IdentityMismatch)
+    );
+  }
+
+  #[test]
+  fn ipv6_peers_are_kept_and_bracketed_in_urls() {
+    let mut ledger = ledger_at("[2001:db8::1]:3000");
+    let peer: SocketAddr = "[2001:db8::2]:3001".parse().unwrap();
+
+    assert!(ledger.add_peer(peer));
+    assert!(!ledger.add_peer("[::]:3001".parse().unwrap()));
+    assert!(!ledger.add_peer("[::1]:3001".parse().unwrap()));
+
+    assert_eq!(ledger.get_peers(), HashSet::from([peer]));
+    assert_eq!(
+      format!("{}://{}/handshake", ledger.peer_scheme(), peer),
+      "http://[2001:db8::2]:3001/handshake"
+    );
+  }
+
+  #[test]
+  fn transactions_only_verify_on_the_chain_they_were_signed_for() {
+    let (alice, _) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let transaction = Transaction::new(&bob_public, &alice, 10, 1).unwrap();
+
+    assert_eq!(transaction.verify(1), Ok(()));
+    assert_eq!(transaction.verify(2), Err(VerifyError::SignatureMismatch));
+  }
+
+  /// A signed payment of 10 from alice to bob on the default chain.
+  fn signed_payment() -> Transaction {
+    let (alice, _) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    Transaction::new(&bob_public, &alice, 10, DEFAULT_CHAIN_ID).unwrap()
+  }
+
+  /// The high-S twin of a low-S `signature`: the same `r` with `s` replaced
+  /// by the curve order minus `s`, which verifies just the same.
+  fn high_s(signature: &str) -> String {
+    const ORDER: [u8; 32] = [
+      0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+      0xfe, 0xba, 0xae, 0xdc, 0xe6, 0xaf, 0x48, 0xa0, 0x3b, 0xbf, 0xd2, 0x5e, 0x8c, 0xd0, 0x36,
+      0x41, 0x41,
+    ];
+    let mut compact = Signature::from_str(signature).unwrap().serialize_compact();
+    let mut borrow = 0;
+    for i in (0..32).rev() {
+      let difference = ORDER[i] as i16 - compact[32 + i] as i16 - borrow;
+      borrow = (difference < 0) as i16;
+      compact[32 + i] = difference.rem_euclid(256) as u8;
+    }
+    Signature::from_compact(&compact).unwrap().to_string()
+  }
+
+  #[test]
+  fn each_kind_of_bad_transaction_has_its_own_verify_error() {
+    assert_eq!(signed_payment().verify(DEFAULT_CHAIN_ID), Ok(()));
+
+    let mut malformed = signed_payment();
+    malformed.signature = String::from("not a signature");
+    assert_eq!(
+      malformed.verify(DEFAULT_CHAIN_ID),
+      Err(VerifyError::MalformedSignature)
+    );
+
+    let mut non_canonical = signed_payment();
+    non_canonical.signature = high_s(&non_canonical.signature);
+    assert_eq!(
+      non_canonical.verify(DEFAULT_CHAIN_ID),
+      Err(VerifyError::NonCanonicalSignature)
+    );
+
+    let mut mismatched = signed_payment();
+    mismatched.amount += 1;
+    assert_eq!(
+      mismatched.verify(DEFAULT_CHAIN_ID),
+      Err(VerifyError::SignatureMismatch)
+    );
+
+    let mut long_memo = signed_payment();
+    long_memo.memo = Some("x".repeat(MAX_MEMO_BYTES + 1));
+    assert_eq!(
+      long_memo.verify(DEFAULT_CHAIN_ID),
+      Err(VerifyError::MemoTooLong)
+    );
+
+    let mut self_transfer = signed_payment();
+    self_transfer.to = self_transfer.from;
+    assert_eq!(
+      self_transfer.verify(DEFAULT_CHAIN_ID),
+      Err(VerifyError::SelfTransfer)
+    );
+  }
+
+  #[test]
+  fn a_payment_to_its_own_sender_is_never_signed() {
+    let (alice, alice_public) = keypair(1);
+
+    assert!(matches!(
+      Transaction::new(&alice_public, &alice, 10, DEFAULT_CHAIN_ID),
+      Err(SilocoinError::InvalidTransaction(VerifyError::SelfTransfer))
+    ));
+  }
+
+  #[test]
+  fn a_transaction_naming_a_degenerate_key_fails_to_parse() {
+    let serialized = serde_json::to_value(signed_payment()).unwrap();
+    for degenerate in ["00".repeat(33), format!("04{}", "00".repeat(64))] {
+      let mut tampered = serialized.clone();
+      tampered["to"] = degenerate.clone().into();
+
+      assert!(
+        serde_json::from_value::<Transaction>(tampered).is_err(),
+        "{degenerate}"
+      );
+    }
+  }
+
+  #[test]
+  fn the_memo_is_covered_by_the_signature() {
+    let (alice, _) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let transaction = Transaction::with_memo(
+      &bob_public,
+      &alice,
+      10,
+      Some(String::from("rent")),
+      DEFAULT_CHAIN_ID,
+    )
+    .unwrap();
+    assert_eq!(transaction.verify(DEFAULT_CHAIN_ID), Ok(()));
+
+    let mut altered = transaction.clone();
+    altered.memo = Some(String::from("gift"));
+    assert_eq!(
+      altered.verify(DEFAULT_CHAIN_ID),
+      Err(VerifyError::SignatureMismatch)
+    );
+    let mut removed = transaction;
+    removed.memo = None;
+    assert_eq!(
+      removed.verify(DEFAULT_CHAIN_ID),
+      Err(VerifyError::SignatureMismatch)
+    );
+  }
+
+  #[test]
+  fn memos_over_the_limit_are_refused() {
+    let (alice, _) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let memo = |len| Some("x".repeat(len));
+
+    let longest = Transaction::with_memo(&bob_public, &alice, 10, memo(MAX_MEMO_BYTES), 1);
+    let too_long = Transaction::with_memo(&bob_public, &alice, 10, memo(MAX_MEMO_BYTES + 1), 1);
+
+    assert!(longest.is_ok());
+    assert!(matches!(
+      too_long,
+      Err(SilocoinError::InvalidTransaction(VerifyError::MemoTooLong))
+    ));
+  }
+
+  #[test]
+  fn changing_from_after_signing_fails_verification() {
+    let (_, carol_public) = keypair(3);
+    let mut transaction = signed_payment();
+    transaction.from = carol_public;
+
+    assert_eq!(
+      transaction.verify(DEFAULT_CHAIN_ID),
+      Err(VerifyError::SignatureMismatch)
+    );
+  }
+
+  #[test]
+  fn a_chain_with_a_badly_signed_transaction_is_invalid() {
+    let (_, carol_public) = keypair(3);
+    let mut forged = signed_payment();
+    forged.from = carol_public;
+    let mut valid = Blockchain::new(DEFAULT_CHAIN_ID, 1).unwrap();
+    let mut invalid = valid.clone();
+
+    valid
+      .add_block(
+        vec![signed_payment()],
+        MiningOptions::default(),
+        &SystemClock,
+      )
+      .unwrap();
+    // The block is properly mined over the forged transaction
+    invalid
+      .add_block(vec![forged], MiningOptions::default(), &SystemClock)
+      .unwrap();
+
+    assert!(valid.is_valid());
+    assert!(!invalid.is_valid());
+  }
+
+  #[test]
+  fn a_node_refuses_payments_signed_for_another_chain() {
+    let mut ledger = ledger_with(LedgerConfig {
+      chain_id: 2,
+      ..test_config()
+    });
+    let (alice, _) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let transaction = Transaction::new(&bob_public, &alice, 10, 1).unwrap();
+
+    let (placements, job) = ledger.start_payments([(&[transaction][..], false)]);
+
+    assert!(matches!(
+      placements.as_slice(),
+      [Placement::Refused(SilocoinError::InvalidTransaction(
+        VerifyError::SignatureMismatch
+      ))]
+    ));
+    assert!(matches!(job, Ok(None)));
+  }
+
+  #[test]
+  fn payments_waiting_together_share_a_block() {
+    let mut ledger = ledger_with(test_config());
+    let (alice, _) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let (carol, _) = keypair(3);
+    let from_alice = [payment(&bob_public, &alice, 10)];
+    let from_carol = [payment(&bob_public, &carol, 20)];
+
+    let (placements, job) =
+      ledger.start_payments([(&from_alice[..], false), (&from_carol[..], false)]);
+    let mined = job.unwrap().unwrap().mine().unwrap();
+    ledger.finish_mining(mined).unwrap();
+
+    assert!(matches!(
+      placements.as_slice(),
+      [Placement::InBlock, Placement::InBlock]
+    ));
+    assert_eq!(ledger.chain().height(), 2);
+    for transaction in from_alice.iter().chain(&from_carol) {
+      assert!(ledger
+        .chain()
+        .confirmations(transaction.signature())
+        .is_some());
+    }
+  }
+
+  #[test]
+  fn payments_sharing_a_block_cant_overspend_together() {
+    let mut ledger = ledger_with(test_config());
+    let (alice, _) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let (_, carol_public) = keypair(3);
+    let first = [payment(&bob_public, &alice, 60)];
+    let second = [payment(&carol_public, &alice, 60)];
+
+    let (placements, _) = ledger.start_payments([(&first[..], false), (&second[..], false)]);
+
+    assert!(matches!(
+      placements.as_slice(),
+      [
+        Placement::InBlock,
+        Placement::Refused(SilocoinError::InsufficientFunds(_))
+      ]
+    ));
+  }
+
+  #[test]
+  fn a_payment_already_in_the_block_waits_for_the_next() {
+    let mut ledger = ledger_with(test_config());
+    let (alice, _) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let sent = [payment(&bob_public, &alice, 10)];
+
+    let (placements, _) = ledger.start_payments([(&sent[..], false), (&sent[..], false)]);
+
+    assert!(matches!(
+      placements.as_slice(),
+      [Placement::InBlock, Placement::Deferred]
+    ));
+  }
+
+  #[test]
+  fn compute_all_balances_agrees_with_get_balance() {
+    let (alice, alice_public) = keypair(1);
+    let (bob, bob_public) = keypair(2);
+    let (_, carol_public) = keypair(3);
+    let mut ledger = ledger_with(LedgerConfig {
+      genesis_allocation: BTreeMap::from([(carol_public, 50)]),
+      ..test_config()
+    });
+    ledger.send(&bob_public, &alice, 30, None, false).unwrap();
+    ledger.send(&carol_public, &bob, 5, None, false).unwrap();
+
+    let balances = ledger.compute_all_balances();
+
+    assert_eq!(balances.len(), 3);
+    for (public_key, balance) in &balances {
+      assert_eq!(*balance, ledger.get_balance(public_key).unwrap());
+    }
+    assert_eq!(balances[&alice_public], 70);
+    assert_eq!(balances[&bob_public], 125);
+    assert_eq!(balances[&carol_public], 155);
+  }
+
+  #[test]
+  fn cached_balances_follow_the_chain() {
+    let (alice, _) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let mut ledger = ledger_with(test_config());
+    let mut peer = ledger.clone();
+    assert_eq!(ledger.get_balance(&bob_public).unwrap(), 100);
+
+    ledger.send(&bob_public, &alice, 10, None, false).unwrap();
+    assert_eq!(ledger.get_balance(&bob_public).unwrap(), 110);
+
+    // A whole new chain replaces everything the cache was built from
+    peer.send(&bob_public, &alice, 20, None, false).unwrap();
+    peer.send(&bob_public, &alice, 15, None, false).unwrap();
+    ledger.update_blockchain(peer.chain()).unwrap();
+    assert_eq!(ledger.get_balance(&bob_public).unwrap(), 135);
+  }
+
+  #[derive(Serialize, Deserialize, Debug, PartialEq)]
+  struct Account {
+    #[serde(with = "public_key_hex")]
+    key: PublicKey,
+  }
+
+  #[test]
+  fn public_keys_serialize_as_pinned_hex() {
+    let (_, public_key) = keypair(1);
+    let account = Account { key: public_key };
+
+    let json = serde_json::to_string(&account).unwrap();
+
+    assert_eq!(
+      json,
+      r#"{"key":"031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f"}"#
+    );
+    assert_eq!(serde_json::from_str::<Account>(&json).unwrap(), account);
+  }
+
+  #[test]
+  fn public_keys_that_are_not_hex_fail_to_deserialize() {
+    assert!(serde_json::from_str::<Account>(r#"{"key":"not hex"}"#).is_err());
+    assert!(serde_json::from_str::<Account>(r#"{"key":"02"}"#).is_err());
+  }
+
+  #[test]
+  fn nodes_agree_on_genesis_only_with_the_same_allocation() {
+    let (alice, alice_public) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let with_allocation = |amount| {
+      ledger_with(LedgerConfig {
+        genesis_allocation: BTreeMap::from([(alice_public, amount)]),
+        ..test_config()
+      })
+    };
+    let mut ledger = with_allocation(1_000);
+    let mut agreeing = with_allocation(1_000);
+    let diverging = with_allocation(2_000);
+
+    assert_eq!(
+      ledger.chain().genesis_hash(),
+      agreeing.chain().genesis_hash()
+    );
+    assert_ne!(
+      ledger.chain().genesis_hash(),
+      diverging.chain().genesis_hash()
+    );
+    assert!(ledger.chain().shares_genesis_with(agreeing.chain()));
+    assert!(!ledger.chain().shares_genesis_with(diverging.chain()));
+    // A chain grown from the same genesis is taken up as usual
+    agreeing
+      .send(&bob_public, &alice, 500, None, false)
+      .unwrap();
+    ledger.update_blockchain(agreeing.chain()).unwrap();
+    assert_eq!(
+      ledger.get_balance(&alice_public).unwrap(),
+      agreeing.get_balance(&alice_public).unwrap()
+    );
+  }
+
+  #[test]
+  fn a_serialized_chain_validates_on_its_own() {
+    let (alice, alice_public) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let mut ledger = ledger_with(LedgerConfig {
+      difficulty: 6,
+      genesis_allocation: BTreeMap::from([(alice_public, 1_000)]),
+      ..test_config()
+    });
+    ledger.send(&bob_public, &alice, 500, None, false).unwrap();
+    let json = serde_json::to_value(ledger.chain()).unwrap();
+
+    let blockchain: Blockchain = serde_json::from_value(json.clone()).unwrap();
+
+    assert!(blockchain.is_valid());
+    // The rules travel with the chain, so asking for more work than the
+    // blocks were mined to fails
+    let mut harder = json;
+    harder["difficulty"] = json!(64);
+    let harder: Blockchain = serde_json::from_value(harder).unwrap();
+    assert!(!harder.is_valid());
+  }
+
+  #[test]
+  fn chains_contradicting_a_checkpoint_are_refused() {
+    let (alice, _) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let mut canonical = ledger_with(test_config());
+    let mut conflicting = canonical.clone();
+    canonical
+      .send(&bob_public, &alice, 10, None, false)
+      .unwrap();
+    conflicting
+      .send(&bob_public, &alice, 20, None, false)
+      .unwrap();
+    conflicting
+      .send(&bob_public, &alice, 30, None, false)
+      .unwrap();
+    let mut ledger = ledger_with(LedgerConfig {
+      checkpoints: vec![Checkpoint {
+        height: 1,
+        hash: canonical.chain().block_at(1).unwrap().hash().to_string(),
+      }],
+      ..test_config()
+    });
+
+    // Heavier, but not the chain the checkpoint pins
+    let refused = ledger.update_blockchain(conflicting.chain());
+    assert!(matches!(refused, Err(SilocoinError::InvalidChain(..))));
+    let round = sync_round(&ledger, [conflicting.chain()]);
+    assert!(!ledger.finish_sync(round).chain_changed);
+
+    ledger.update_blockchain(canonical.chain()).unwrap();
+    assert_eq!(ledger.chain().tip_hash(), canonical.chain().tip_hash());
+  }
+
+  #[test]
+  fn chains_from_a_different_genesis_are_refused() {
+    let (alice, alice_public) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let mut ledger = ledger_with(test_config());
+    let mut stranger = ledger_with(LedgerConfig {
+      genesis_allocation: BTreeMap::from([(alice_public, 1_000)]),
+      ..test_config()
+    });
+    stranger
+      .send(&bob_public, &alice, 500, None, false)
+      .unwrap();
+
+    let refused = ledger.update_blockchain(stranger.chain());
+
+    assert!(matches!(refused, Err(SilocoinError::InvalidChain(..))));
+    assert_eq!(ledger.chain().height(), 1);
+  }
+
+  /// A block of one payment, mined at `difficulty` with `mining`.
+  fn mined_block(difficulty: u32, mining: MiningOptions) -> Block {
+    let (alice, _) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let transaction = Transaction::new(&bob_public, &alice, 10, DEFAULT_CHAIN_ID).unwrap();
+    Block::new(
+      vec![transaction],
+      String::from("0"),
+      DEFAULT_CHAIN_ID,
+      difficulty,
+      mining,
+      &SystemClock,
+    )
+    .unwrap()
+  }
+
+  #[test]
+  fn leading_zero_bits_counts_across_bytes() {
+    assert_eq!(leading_zero_bits(&[0x80]), 0);
+    assert_eq!(leading_zero_bits(&[0x01]), 7);
+    assert_eq!(leading_zero_bits(&[0x00, 0x0f]), 12);
+    assert_eq!(leading_zero_bits(&[0x00, 0x00]), 16);
+    assert_eq!(leading_zero_bits(&[0x00, 0x80, 0x00]), 8);
+  }
+
+  /// A chain of blocks with one transaction each, timestamped `times`. The
+  /// blocks don't link up, which `recent_tps` doesn't look at.
+  fn chain_timed(times: &[u128]) -> Blockchain {
+    let mut blockchain = Blockchain::new(DEFAULT_CHAIN_ID, 0).unwrap();
+    for &time in times {
+      let mut block = mined_block(0, MiningOptions::default());
+      block.time = time;
+      blockchain.chain.push(block);
+    }
+    blockchain
+  }
+
+  #[test]
+  fn recent_tps_divides_transactions_by_the_time_they_took() {
+    let blockchain = chain_timed(&[1_000, 2_000, 3_000, 5_000]);
+
+    // 3 transactions after the first block over 4 seconds
+    assert_eq!(blockchain.recent_tps(4), 0.75);
+    // 1 transaction over the last 2 seconds
+    assert_eq!(blockchain.recent_tps(2), 0.5);
+    // A window longer than the chain covers every mined block
+    assert_eq!(blockchain.recent_tps(100), 0.75);
+  }
+
+  #[test]
+  fn recent_tps_is_zero_without_time_passing() {
+    assert_eq!(chain_timed(&[]).recent_tps(10), 0.0);
+    assert_eq!(chain_timed(&[1_000]).recent_tps(10), 0.0);
+    assert_eq!(chain_timed(&[1_000, 1_000, 1_000]).recent_tps(10), 0.0);
+  }
+
+  /// Transactions with the longest memo allowed, just over `MAX_BLOCK_WEIGHT`
+  /// bytes of them in all.
+  fn overweight_transactions() -> Vec<Transaction> {
+    let (alice, _) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let memo = "m".repeat(MAX_MEMO_BYTES);
+    let transaction =
+      Transaction::with_memo(&bob_public, &alice, 1, Some(memo), DEFAULT_CHAIN_ID).unwrap();
+    vec![transaction.clone(); MAX_BLOCK_WEIGHT / transaction.size() + 1]
+  }
+
+  #[test]
+  fn an_overweight_block_is_not_mined() {
+    let result = Block::mined(
+      1_700_000_000_000,
+      overweight_transactions(),
+      String::from("0"),
+      DEFAULT_CHAIN_ID,
+      0,
+      MiningOptions::default(),
+      None,
+    );
+
+    assert!(matches!(result, Err(SilocoinError::InvalidChain(_))));
+  }
+
+  #[test]
+  fn an_overweight_block_fails_verification_despite_a_valid_hash() {
+    let mut block = mined_block(0, MiningOptions::default());
+    block.transactions = overweight_transactions();
+    block
+      .mine(DEFAULT_CHAIN_ID, MiningOptions::default())
+      .unwrap();
+
+    assert!(block.weight() > MAX_BLOCK_WEIGHT);
+    assert_eq!(block.hash, block.calculate_hash(DEFAULT_CHAIN_ID));
+    assert!(!block.verify(DEFAULT_CHAIN_ID));
+  }
+
+  #[test]
+  fn mined_blocks_meet_their_bit_target() {
+    for difficulty in [0, 1, 5, 8, 11] {
+      let block = mined_block(difficulty, MiningOptions::default());
+      let digest = from_hex(block.hash()).unwrap();
+      assert!(leading_zero_bits(&digest) >= difficulty);
+      assert!(block.verify_hash());
+    }
+  }
+
+  #[test]
+  fn mining_at_an_absurd_difficulty_times_out() {
+    let timeout = Duration::from_millis(50);
+    let mut block = mined_block(0, MiningOptions::default());
+    block.difficulty = 256;
+    let mining = MiningOptions {
+      threads: 2,
+      timeout: Some(timeout),
+      ..MiningOptions::default()
+    };
+
+    let start = Instant::now();
+    let result = block.mine(DEFAULT_CHAIN_ID, mining);
+
+    assert_eq!(result, Err(MiningError::TimedOut(timeout)));
+    assert!(start.elapsed() < Duration::from_secs(5));
+  }
+
+  #[test]
+  fn a_hash_short_of_the_target_fails_verification() {
+    let mut block = mined_block(4, MiningOptions::default());
+    let digest = from_hex(block.hash()).unwrap();
+    block.difficulty = leading_zero_bits(&digest) + 1;
+    assert!(!block.verify_hash());
+  }
+
+  #[test]
+  fn a_fixed_clock_gives_blocks_the_same_timestamp_and_hash() {
+    let clock = clock::FixedClock(1_700_000_000_000);
+    let (alice, _) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let mine = || {
+      let transaction = Transaction::new(&bob_public, &alice, 10, DEFAULT_CHAIN_ID).unwrap();
+      Block::new(
+        vec![transaction],
+        String::from("0"),
+        DEFAULT_CHAIN_ID,
+        8,
+        MiningOptions::default(),
+        &clock,
+      )
+      .unwrap()
+    };
+
+    let (first, second) = (mine(), mine());
+
+    assert_eq!(first.timestamp(), 1_700_000_000_000);
+    assert_eq!(first.hash(), second.hash());
+  }
+
+  #[test]
+  fn a_ledger_mines_with_its_configured_clock() {
+    let (alice, _) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let mut ledger = ledger_with(test_config());
+    let now = ledger.chain().block_at(0).unwrap().timestamp() + 60_000;
+    ledger.config.clock = Arc::new(clock::FixedClock(now));
+
+    ledger.send(&bob_public, &alice, 10, None, false).unwrap();
+
+    assert_eq!(ledger.chain().block_at(1).unwrap().timestamp(), now);
+  }
+
+  /// A clock set before the Unix epoch.
+  #[derive(Debug)]
+  struct BrokenClock;
+
+  impl Clock for BrokenClock {
+    fn now_millis(&self) -> Result<u128, SilocoinError> {
+      Err(SilocoinError::ClockBeforeEpoch)
+    }
+  }
+
+  #[test]
+  fn a_broken_clock_fails_mining_instead_of_panicking() {
+    let (alice, _) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let transaction = Transaction::new(&bob_public, &alice, 10, DEFAULT_CHAIN_ID).unwrap();
+
+    let mined = Block::new(
+      vec![transaction],
+      String::from("0"),
+      DEFAULT_CHAIN_ID,
+      1,
+      MiningOptions::default(),
+      &BrokenClock,
+    );
+
+    assert!(matches!(mined, Err(SilocoinError::ClockBeforeEpoch)));
+  }
+
+  #[test]
+  fn blocks_mined_on_several_threads_verify() {
+    for threads in [2, 4, 8] {
+      let block = mined_block(
+        12,
+        MiningOptions {
+          threads,
+          ..MiningOptions::default()
+        },
+      );
+      assert!(block.verify_hash());
+    }
+  }
+
+  #[test]
+  fn block_hashes_are_pinned_to_their_fields() {
+    let block = Block {
+      time: 1_700_000_000_000,
+      transactions: vec![signed_payment()],
+      allocation: BTreeMap::new(),
+      prev_block_hash: String::from("0"),
+      difficulty: 0,
+      nonce: 42,
+      miner: None,
+      miner_signature: None,
+      hash: String::new(),
+    };
+
+    // Changes here change every block hash, so they need a protocol bump
+    assert_eq!(
+      block.calculate_hash(DEFAULT_CHAIN_ID),
+      "d62923a9defaada9d606606e8e6ac926ec4615b40fd16317cef03176796b2dfe"
+    );
+  }
+
+  #[test]
+  fn hashes_are_lowercase_hex_of_the_whole_digest() {
+    assert_eq!(to_hex(&[0x00, 0x0a, 0xff]), "000aff");
+    let block = mined_block(8, MiningOptions::default());
+    assert_eq!(block.hash().len(), 64);
+    assert!(block
+      .hash()
+      .chars()
+      .all(|char| char.is_ascii_digit() || ('a'..='f').contains(&char)));
+  }
+
+  #[test]
+  fn mined_hashes_have_a_leading_zero_nibble_per_four_bits() {
+    for (difficulty, zeros) in [(4, "0"), (8, "00"), (12, "000")] {
+      let block = mined_block(difficulty, MiningOptions::default());
+      assert!(block.hash().starts_with(zeros), "{}", block.hash());
+    }
+  }
+
+  /// Whether `transaction` is waiting in `ledger`'s mempool.
+  /// A sync round starting from `ledger`'s tip in which one peer held each
+  /// of `chains`.
+  fn sync_round<'a>(
+    ledger: &Ledger,
+    chains: impl IntoIterator<Item = &'a Blockchain>,
+  ) -> SyncRound {
+    let mut votes = HashMap::new();
+    for chain in chains {
+      *votes.entry(chain.clone()).or_insert(0) += 1;
+    }
+    SyncRound {
+      height: ledger.chain.height(),
+      tip_hash: ledger.chain.tip_hash(),
+      peers_contacted: votes.values().sum(),
+      matching_peers: 0,
+      votes,
+      gossiped_peers: vec![],
+      metrics: Metrics::default(),
+    }
+  }
+
+  #[test]
+  fn nodes_break_ties_between_equal_chains_the_same_way() {
+    let (alice, _) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let mut first = ledger_with(test_config());
+    let mut second = first.clone();
+    let mut third = first.clone();
+    first.send(&bob_public, &alice, 10, None, false).unwrap();
+    second.send(&bob_public, &alice, 20, None, false).unwrap();
+    let (first_chain, second_chain) = (first.chain().clone(), second.chain().clone());
+    let lowest_tip = first_chain.tip_hash().min(second_chain.tip_hash());
+
+    // Each holds its own chain against the other's, and a third node sees
+    // one vote for each
+    first.finish_sync(sync_round(&first, [&second_chain]));
+    second.finish_sync(sync_round(&second, [&first_chain]));
+    third.finish_sync(sync_round(&third, [&first_chain, &second_chain]));
+
+    for ledger in [&first, &second, &third] {
+      assert_eq!(ledger.chain().tip_hash(), lowest_tip);
+    }
+  }
+
+  #[test]
+  fn a_tie_goes_to_the_chain_with_more_trusted_blocks() {
+    let (alice, _) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let (first_miner, first_miner_public) = keypair(5);
+    let (second_miner, second_miner_public) = keypair(6);
+    let genesis_only = ledger_with(test_config());
+    let mut first = genesis_only.clone();
+    first.config.identity_key = first_miner;
+    let mut second = genesis_only.clone();
+    second.config.identity_key = second_miner;
+    first.send(&bob_public, &alice, 10, None, false).unwrap();
+    second.send(&bob_public, &alice, 20, None, false).unwrap();
+    let (first_chain, second_chain) = (first.chain().clone(), second.chain().clone());
+
+    // Both chains have the same work and one vote each, so only trust can
+    // split them, whichever way their tip hashes compare
+    for (trusted, expected) in [
+      (first_miner_public, &first_chain),
+      (second_miner_public, &second_chain),
+    ] {
+      let mut ledger = genesis_only.clone();
+      ledger.config.trusted_miners = HashSet::from([trusted]);
+
+      ledger.finish_sync(sync_round(&ledger, [&first_chain, &second_chain]));
+
+      assert_eq!(ledger.chain().tip_hash(), expected.tip_hash());
+    }
+  }
+
+  #[test]
+  fn more_trusted_blocks_beat_more_votes() {
+    let (alice, _) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let (trusted_miner, trusted_miner_public) = keypair(5);
+    let (other_miner, _) = keypair(6);
+    let genesis_only = ledger_with(test_config());
+    let mut trusted = genesis_only.clone();
+    trusted.config.identity_key = trusted_miner;
+    let mut other = genesis_only.clone();
+    other.config.identity_key = other_miner;
+    trusted.send(&bob_public, &alice, 10, None, false).unwrap();
+    other.send(&bob_public, &alice, 20, None, false).unwrap();
+    let mut ledger = genesis_only.clone();
+    ledger.config.trusted_miners = HashSet::from([trusted_miner_public]);
+
+    ledger.finish_sync(sync_round(
+      &ledger,
+      [trusted.chain(), other.chain(), other.chain(), other.chain()],
+    ));
+
+    assert_eq!(ledger.chain().tip_hash(), trusted.chain().tip_hash());
+  }
+
+  fn is_pending(ledger: &Ledger, transaction: &Transaction) -> bool {
+    ledger
+      .pending_transactions
+      .iter()
+      .any(|pending| pending.transaction == *transaction)
+  }
+
+  /// Submits a payment of `amount` to dave from the key made from `seed`,
+  /// returning the transaction.
+  fn submit_from(ledger: &mut Ledger, seed: u8, amount: u64) -> Transaction {
+    let (from, _) = keypair(seed);
+    let (_, dave_public) = keypair(4);
+    ledger
+      .submit(payment(&dave_public, &from, amount), false)
+      .unwrap();
+    Transaction::new(&dave_public, &from, amount, DEFAULT_CHAIN_ID).unwrap()
+  }
+
+  #[test]
+  fn a_full_mempool_evicts_its_oldest_transaction() {
+    let mut ledger = ledger_with(LedgerConfig {
+      max_mempool_size: 2,
+      ..test_config()
+    });
+    let oldest = submit_from(&mut ledger, 1, 10);
+    let older = submit_from(&mut ledger, 2, 10);
+
+    let newest = submit_from(&mut ledger, 3, 10);
+
+    assert!(!is_pending(&ledger, &oldest));
+    assert!(is_pending(&ledger, &older));
+    assert!(is_pending(&ledger, &newest));
+  }
+
+  #[test]
+  fn pending_transactions_expire_after_their_ttl() {
+    let received_at = 1_700_000_000_000;
+    let mut ledger = ledger_with(LedgerConfig {
+      mempool_ttl: Duration::from_secs(60),
+      clock: Arc::new(clock::FixedClock(received_at)),
+      ..test_config()
+    });
+    let transaction = submit_from(&mut ledger, 1, 10);
+
+    ledger.config.clock = Arc::new(clock::FixedClock(received_at + 60_000));
+    ledger.expire_pending();
+    assert!(is_pending(&ledger, &transaction));
+
+    ledger.config.clock = Arc::new(clock::FixedClock(received_at + 60_001));
+    ledger.expire_pending();
+    assert!(!is_pending(&ledger, &transaction));
+  }
+
+  #[test]
+  fn a_reorg_returns_orphaned_transactions_to_the_mempool() {
+    let (alice, _) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let (carol, _) = keypair(3);
+    let mut ledger = ledger_with(test_config());
+    let mut peer = ledger.clone();
+    ledger.send(&bob_public, &alice, 10, None, false).unwrap();
+    let orphaned = ledger.chain().block_at(1).unwrap().transactions()[0].clone();
+    peer.send(&bob_public, &carol, 5, None, false).unwrap();
+    peer.send(&bob_public, &carol, 6, None, false).unwrap();
+
+    ledger.update_blockchain(peer.chain()).unwrap();
+
+    assert!(ledger
+      .chain()
+      .find_transaction(orphaned.signature())
+      .is_none());
+    assert!(is_pending(&ledger, &orphaned));
+  }
+
+  #[test]
+  fn a_reorg_leaves_transactions_the_new_chain_has_out_of_the_mempool() {
+    let (alice, _) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let mut ledger = ledger_with(test_config());
+    let mut peer = ledger.clone();
+    ledger.send(&bob_public, &alice, 10, None, false).unwrap();
+    let mined = ledger.chain().block_at(1).unwrap().transactions()[0].clone();
+    // The peer mines the same payment, in a block of its own, then another
+    peer.pay(vec![mined.clone()], false).unwrap();
+    peer.send(&bob_public, &alice, 5, None, false).unwrap();
+
+    ledger.update_blockchain(peer.chain()).unwrap();
+
+    assert!(!is_pending(&ledger, &mined));
+  }
+
+  #[test]
+  fn a_corrupted_local_chain_is_replaced_by_a_valid_peer_chain() {
+    let (alice, _) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let mut ledger = ledger_with(test_config());
+    let mut peer = ledger.clone();
+    for amount in [10, 20] {
+      ledger
+        .send(&bob_public, &alice, amount, None, false)
+        .unwrap();
+    }
+    peer.send(&bob_public, &alice, 30, None, false).unwrap();
+    // Longer than the peer's chain, but no longer signed by alice
+    ledger.chain.chain[1].transactions[0].amount = 1000;
+    ledger.chain_valid = ledger.validate_chain(&ledger.chain).is_ok();
+    let round = sync_round(&ledger, [peer.chain()]);
+
+    let summary = ledger.finish_sync(round);
+
+    assert!(summary.chain_changed);
+    assert_eq!(ledger.chain().tip_hash(), peer.chain().tip_hash());
+  }
+
+  #[test]
+  fn balances_are_the_same_after_pruning() {
+    let (alice, alice_public) = keypair(1);
+    let (bob, bob_public) = keypair(2);
+    let (_, carol_public) = keypair(3);
+    let mut ledger = ledger_with(test_config());
+    for amount in 1..=5 {
+      ledger
+        .send(&bob_public, &alice, amount, None, false)
+        .unwrap();
+      ledger
+        .send(&carol_public, &bob, amount * 2, None, false)
+        .unwrap();
+    }
+    let unpruned = ledger.clone();
+
+    ledger.prune_before(4).unwrap();
+
+    assert_eq!(ledger.chain().len(), unpruned.chain().len() - 4);
+    for public_key in [alice_public, bob_public, carol_public] {
+      assert_eq!(
+        ledger.get_balance(&public_key).unwrap(),
+        unpruned.get_balance(&public_key).unwrap()
+      );
+    }
+  }
+
+  #[test]
+  fn the_newest_blocks_are_never_pruned() {
+    let (alice, _) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let mut ledger = ledger_with(test_config());
+    for amount in 1..=PRUNE_CONFIRMATION_DEPTH as u64 {
+      ledger
+        .send(&bob_public, &alice, amount, None, false)
+        .unwrap();
+    }
+
+    assert!(matches!(
+      ledger.prune_before(2),
+      Err(SilocoinError::PruneTooRecent)
+    ));
+    assert!(ledger.prune_before(1).is_ok());
+  }
+
+  #[test]
+  fn a_submitted_payment_comes_off_the_available_balance() {
+    let (alice, alice_public) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let mut ledger = ledger_with(test_config());
+
+    ledger
+      .submit(payment(&bob_public, &alice, 30), false)
+      .unwrap();
+
+    assert_eq!(ledger.get_balance(&alice_public).unwrap(), 100);
+    assert_eq!(ledger.get_available_balance(&alice_public).unwrap(), 70);
+    // Only what's left after the pending payment can be spent
+    assert!(matches!(
+      ledger.submit(payment(&bob_public, &alice, 80), false),
+      Err(SilocoinError::InsufficientFunds(_))
+    ));
+    assert!(ledger
+      .submit(payment(&bob_public, &alice, 70), false)
+      .is_ok());
+  }
+
+  #[test]
+  fn a_new_tip_cancels_mining_in_progress() {
+    let (alice, _) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let (carol, _) = keypair(3);
+    let mut ledger = ledger_with(test_config());
+    ledger
+      .submit(payment(&bob_public, &alice, 10), false)
+      .unwrap();
+    let mut job = ledger.start_mining().unwrap().unwrap();
+    // Never found, so only cancelling (or the timeout) stops the search
+    job.difficulty = 256;
+    job.mining.timeout = Some(Duration::from_secs(10));
+    let mining = std::thread::spawn(move || job.mine());
+
+    ledger.send(&bob_public, &carol, 20, None, false).unwrap();
+
+    assert!(matches!(
+      mining.join().unwrap(),
+      Err(SilocoinError::Mining(MiningError::Cancelled))
+    ));
+  }
+
+  #[test]
+  fn bad_keys_and_mnemonics_have_their_own_errors() {
+    assert!(matches!(
+      generate_keypair_from_seed([0; 32]),
+      Err(SilocoinError::InvalidKey(_))
+    ));
+    assert!(matches!(
+      keypair_from_mnemonic("not a mnemonic"),
+      Err(SilocoinError::InvalidMnemonic(_))
+    ));
+  }
+
+  #[test]
+  fn a_read_only_ledger_refuses_payments_as_read_only() {
+    let (alice, _) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let mut ledger = ledger_with(LedgerConfig {
+      read_only: true,
+      ..test_config()
+    });
+
+    assert!(matches!(
+      ledger.submit(payment(&bob_public, &alice, 10), false),
+      Err(SilocoinError::ReadOnly)
+    ));
+  }
+
+  #[test]
+  fn a_lighter_chain_is_refused_as_not_heavier() {
+    let (alice, _) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let mut ledger = ledger_with(test_config());
+    let genesis_only = ledger.get_blockchain();
+    ledger.send(&bob_public, &alice, 10, None, false).unwrap();
+
+    assert!(matches!(
+      ledger.update_blockchain(&genesis_only),
+      Err(SilocoinError::NotHeavier)
+    ));
+  }
+
+  #[test]
+  fn undecodable_bytes_are_a_serialization_error() {
+    for format in [wire::WireFormat::Json, wire::WireFormat::MsgPack] {
+      assert!(matches!(
+        format.decode::<Blockchain>(b"\xc1 not a chain"),
+        Err(SilocoinError::Serialization(_))
+      ));
+    }
+  }
+
+  #[test]
+  fn a_sender_at_the_pending_cap_is_refused_until_some_confirm() {
+    let (alice, _) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let (carol, _) = keypair(3);
+    let mut ledger = ledger_with(LedgerConfig {
+      max_pending_per_sender: 3,
+      ..test_config()
+    });
+    for amount in 1..=3 {
+      ledger
+        .submit(payment(&bob_public, &alice, amount), false)
+        .unwrap();
+    }
+
+    assert!(matches!(
+      ledger.submit(payment(&bob_public, &alice, 4), false),
+      Err(SilocoinError::TooManyPending(3))
+    ));
+    // Other senders aren't held back by alice's
+    assert!(ledger
+      .submit(payment(&bob_public, &carol, 4), false)
+      .is_ok());
+
+    let job = ledger.start_mining().unwrap().unwrap();
+    ledger.finish_mining(job.mine().unwrap()).unwrap();
+    assert!(ledger
+      .submit(payment(&bob_public, &alice, 4), false)
+      .is_ok());
+  }
+
+  #[test]
+  fn insufficient_funds_reports_the_shortfall() {
+    let (alice, _) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let mut ledger = ledger_with(test_config());
+    ledger
+      .submit(payment(&bob_public, &alice, 30), false)
+      .unwrap();
+
+    let Err(SilocoinError::InsufficientFunds(err)) =
+      ledger.can_send(&bob_public, &alice, 85, false)
+    else {
+      panic!("alice can't afford 85 with 30 pending");
+    };
+
+    assert_eq!(
+      err,
+      InsufficientFunds {
+        required: 85,
+        available: 70,
+        shortfall: 15,
+      }
+    );
+    assert_eq!(
+      err.to_string(),
+      "insufficient funds for transaction, needs 85 but only 70 is available, 15 short"
+    );
+  }
+
+  #[test]
+  fn a_batch_total_past_u64_max_is_refused() {
+    let (alice, _) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let ledger = ledger_with(test_config());
+
+    let result = ledger.can_send_batch(&alice, &[(bob_public, u64::MAX), (bob_public, 1)], false);
+
+    assert!(matches!(result, Err(SilocoinError::PaymentRefused(_))));
+  }
+
+  /// A ledger refusing payments of more than half the sender's balance
+  /// unless they're forced.
+  fn half_balance_ledger() -> Ledger {
+    ledger_with(LedgerConfig {
+      max_send_fraction: Some(0.5),
+      ..test_config()
+    })
+  }
+
+  #[test]
+  fn a_payment_within_the_max_send_fraction_is_sent() {
+    let (alice, _) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let mut ledger = half_balance_ledger();
+
+    let sent = ledger.send(&bob_public, &alice, 50, None, false);
+
+    assert!(sent.is_ok());
+  }
+
+  #[test]
+  fn a_payment_over_the_max_send_fraction_is_refused() {
+    let (alice, alice_public) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let mut ledger = half_balance_ledger();
+
+    let refused = ledger.send(&bob_public, &alice, 51, None, false);
+
+    assert!(matches!(refused, Err(SilocoinError::PaymentRefused(..))));
+    assert_eq!(ledger.get_balance(&alice_public).unwrap(), 100);
+  }
+
+  #[test]
+  fn a_forced_payment_ignores_the_max_send_fraction() {
+    let (alice, alice_public) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let mut ledger = half_balance_ledger();
+
+    ledger.send(&bob_public, &alice, 90, None, true).unwrap();
+
+    assert_eq!(ledger.get_balance(&alice_public).unwrap(), 10);
+  }
+
+  /// A payment of `amount` from alice to bob mined on `ledger`'s tip,
+  /// timestamped `time`.
+  fn block_on_tip_at(ledger: &Ledger, amount: u64, time: u128) -> Block {
+    let (alice, _) = keypair(1);
+    let (_, bob_public) = keypair(2);
+    let transaction = Transaction::new(&bob_public, &alice, amount, DEFAULT_CHAIN_ID).unwrap();
+    Block::new(
+      vec![transaction],
+      ledger.chain().tip_hash(),
+      DEFAULT_CHAIN_ID,
+      ledger.chain().difficulty,
+      MiningOptions::default(),
+      &clock::FixedClock(time),
+    )
+    .unwrap()
+  }
+
+  #[test]
+  fn blocks_dated_too_far_ahead_are_refused() {
+    let mut ledger = ledger_with(test_config());
+    let now = SystemClock.now_millis().unwrap();
+    let skew = DEFAULT_MAX_CLOCK_SKEW.as_millis();
+    let future = block_on_tip_at(&ledger, 10, now + skew + 60_000);
+
+    assert_eq!(
+      ledger.append_block(future.clone()),
+      Err(AppendBlockError::BadTimestamp)
+    );
+    let mut chain = ledger.chain().clone();
+    chain.chain.push(future);
+    assert!(matches!(
+      ledger.update_blockchain(&chain),
+      Err(SilocoinError::InvalidChain(..))
+    ));
+    assert_eq!(ledger.chain().height(), 1);
+
+    // Within the allowed skew is fine
+    let ahead = block_on_tip_at(&ledger, 20, now + skew / 2);
+    assert_eq!(ledger.append_block(ahead), Ok(()));
+  }
+
+  #[test]
+  fn blocks_dated_before_their_parent_are_refused() {
+    let mut ledger = ledger_with(test_config());
+    let now = SystemClock.now_millis().unwrap();
+    ledger
+      .append_block(block_on_tip_at(&ledger, 10, now))
+      .unwrap();
+
+    let earlier = block_on_tip_at(&ledger, 20, now - 1);
+
+    assert_eq!(
+      ledger.append_block(earlier),
+      Err(AppendBlockError::BadTimestamp)
+    );
+    assert_eq!(ledger.chain().height(), 2);
+    // The same time as the parent is allowed
+    assert_eq!(
+      ledger.append_block(block_on_tip_at(&ledger, 30, now)),
+      Ok(())
+    );
+  }
+
+  #[test]
+  fn minted_coins_come_from_no_account() {
+    let (_, alice_public) = keypair(1);
+    let ledger = ledger_with(LedgerConfig {
+      genesis_allocation: BTreeMap::from([(alice_public, 1_000)]),
+      ..test_config()
+    });
+
+    let balances = ledger.compute_all_balances();
+
+    // The genesis allocation is the only way coins are minted, and nothing
+    // is debited for it
+    assert_eq!(balances, HashMap::from([(alice_public, 1_100)]));
   }
 }
@@ -1,237 +1,206 @@
 use std::{
-  collections::HashSet,
-  env::Args,
-  net::{SocketAddr, SocketAddrV4},
-  str::FromStr,
-  sync::Arc,
+  net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
   time::Duration,
 };
 
-use axum::{
-  body::Body,
-  extract::{ConnectInfo, Path, RawPathParams, State},
-  http::StatusCode,
-  response::{IntoResponse, Response},
-  routing::{get, patch, post, Route},
-  Json, Router,
+use axum_server::tls_rustls::RustlsConfig;
+use socket2::{Domain, Protocol, Socket, TcpKeepalive, Type};
+use tanishqoin_api::{
+  app::{self, build_app, Node},
+  config,
+  storage::DataDir,
+  wire::WireFormat,
+  writer::ChainWriter,
+  Ledger, LedgerConfig, DEFAULT_CHAIN_ID, DEFAULT_DIFFICULTY, DEFAULT_MAX_CLOCK_SKEW,
+  DEFAULT_MAX_MEMPOOL_SIZE, DEFAULT_MAX_PENDING_PER_SENDER, DEFAULT_MEMPOOL_TTL,
 };
-use secp256k1::{PublicKey, SecretKey};
-use serde::{Deserialize, Serialize};
-use tanishqoin_api::{generate_keypair, Blockchain, Ledger};
-use tokio::{net::TcpListener, sync::Mutex, time};
-
-#[derive(Clone)]
-struct AppState {
-  ledger: Ledger,
-}
-
-impl AppState {
-  fn new() -> anyhow::Result<AppState> {
-    Ok(AppState {
-      ledger: Ledger::new(
-        HashSet::from([SocketAddr::from_str("127.0.0.1:3000")?]),
-        my_addr(),
-      )?,
-    })
-  }
-}
-
-pub fn my_addr() -> SocketAddr {
-  let args: Vec<String> = std::env::args().collect();
-  let port = args.get(1).unwrap_or(&String::from("3000")).clone();
-  SocketAddr::from_str(&format!("127.0.0.1:{}", port)).unwrap()
+use tokio::{net::TcpListener, time};
+
+/// The address we announce to peers: the host we listen on, or loopback in
+/// the same IP family when listening on every interface.
+pub fn my_addr(host: IpAddr, port: &str) -> anyhow::Result<SocketAddr> {
+  let host = match host {
+    IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+    IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+    host => host,
+  };
+  Ok(SocketAddr::new(host, port.parse()?))
+}
+
+/// Listens on `host` and `port` with TCP keep-alive enabled, which every
+/// accepted connection inherits.
+fn bind(
+  host: IpAddr,
+  port: &str,
+  server_config: &config::ServerConfig,
+) -> anyhow::Result<TcpListener> {
+  let addr = SocketAddr::new(host, port.parse()?);
+  let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+  socket.set_reuse_address(true)?;
+  socket.set_tcp_keepalive(&TcpKeepalive::new().with_time(server_config.tcp_keepalive))?;
+  socket.set_nonblocking(true)?;
+  socket.bind(&addr.into())?;
+  socket.listen(1024)?;
+  Ok(TcpListener::from_std(socket.into())?)
+}
+
+/// Switches the node to the chain saved in `data_dir`, if any, which the
+/// chain writer checks first.
+async fn restore_chain(data_dir: &DataDir, writer: &ChainWriter) -> anyhow::Result<()> {
+  let Some(blockchain) = data_dir.load_chain()? else {
+    return Ok(());
+  };
+  Ok(writer.update_blockchain(blockchain).await?)
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-  let args: Vec<String> = std::env::args().collect();
-  let port = args.get(1).unwrap_or(&String::from("3000")).clone();
+  let args: Vec<String> = std::env::args().skip(1).collect();
+  let file_config = config::FileConfig::load(&args)?;
+  let port = match config::positional_args(&args).first() {
+    Some(port) => port.to_string(),
+    None => file_config.port.unwrap_or(3000).to_string(),
+  };
 
   tracing_subscriber::fmt::init();
 
-  let state = Arc::new(Mutex::new(AppState::new()?));
+  let data_dir = DataDir::open(config::data_dir(&args, &port, &file_config))?;
+  println!("Using data directory {}", data_dir.root().display());
+  let mut peers = config::initial_peers(&args)?;
+  peers.extend(data_dir.load_peers()?);
+
+  let tls_files = config::tls_files(&args)?;
+  if tls_files.is_some() {
+    rustls::crypto::ring::default_provider()
+      .install_default()
+      .expect("no other TLS crypto provider is installed");
+  }
+  let mut ledger_config = LedgerConfig {
+    verify_peers: std::env::var("SILO_VERIFY_PEERS").is_ok_and(|value| value == "1"),
+    chain_id: std::env::var("SILO_CHAIN_ID")
+      .ok()
+      .and_then(|value| value.parse().ok())
+      .or(file_config.chain_id)
+      .unwrap_or(DEFAULT_CHAIN_ID),
+    difficulty: file_config.difficulty.unwrap_or(DEFAULT_DIFFICULTY),
+    mining_threads: std::env::var("SILO_MINING_THREADS")
+      .ok()
+      .and_then(|value| value.parse().ok())
+      .unwrap_or(1),
+    mining_timeout: std::env::var("SILO_MINING_TIMEOUT_SECS")
+      .ok()
+      .and_then(|value| value.parse().ok())
+      .map(Duration::from_secs),
+    network_secret: config::network_secret(),
+    checkpoints: config::checkpoints()?,
+    mine_empty_blocks: std::env::var("SILO_MINE_EMPTY_BLOCKS").is_ok_and(|value| value == "1"),
+    read_only: config::has_flag(&args, "--read-only"),
+    identity_key: data_dir.load_or_create_identity()?,
+    wire_format: std::env::var("SILO_WIRE_FORMAT")
+      .ok()
+      .and_then(|value| value.parse().ok())
+      .unwrap_or(WireFormat::MsgPack),
+    max_send_fraction: std::env::var("SILO_MAX_SEND_FRACTION")
+      .ok()
+      .and_then(|value| value.parse().ok()),
+    max_clock_skew: std::env::var("SILO_MAX_CLOCK_SKEW_SECS")
+      .ok()
+      .and_then(|value| value.parse().ok())
+      .map_or(DEFAULT_MAX_CLOCK_SKEW, Duration::from_secs),
+    max_mempool_size: std::env::var("SILO_MAX_MEMPOOL_SIZE")
+      .ok()
+      .and_then(|value| value.parse().ok())
+      .unwrap_or(DEFAULT_MAX_MEMPOOL_SIZE),
+    max_pending_per_sender: std::env::var("SILO_MAX_PENDING_PER_SENDER")
+      .ok()
+      .and_then(|value| value.parse().ok())
+      .unwrap_or(DEFAULT_MAX_PENDING_PER_SENDER),
+    mempool_ttl: std::env::var("SILO_MEMPOOL_TTL_SECS")
+      .ok()
+      .and_then(|value| value.parse().ok())
+      .map_or(DEFAULT_MEMPOOL_TTL, Duration::from_secs),
+    // A node serving HTTPS is on a network that does, so its peers will be
+    // too
+    peer_tls: tls_files.is_some() || std::env::var("SILO_PEER_TLS").is_ok_and(|value| value == "1"),
+    peer_ca: config::peer_ca()?,
+    trusted_miners: config::trusted_miners()?,
+    max_reorg_depth: std::env::var("SILO_MAX_REORG_DEPTH")
+      .ok()
+      .and_then(|value| value.parse().ok()),
+    max_body_bytes: config::max_body_bytes(),
+    ..LedgerConfig::default()
+  };
+  // An agreed allocation replaces the free starting balance everyone
+  // otherwise gets
+  if let Some(allocation) = config::genesis_allocation(&args)? {
+    ledger_config.genesis_allocation = allocation;
+    ledger_config.initial_balance = 0;
+  }
+
+  let bind_host = file_config.bind_host.unwrap_or(IpAddr::from([0, 0, 0, 0]));
+  let mut ledger = Ledger::new(peers, my_addr(bind_host, &port)?, ledger_config)?;
+  let bootstraps = config::bootstrap_peers(&args)?;
+  if !bootstraps.is_empty() && ledger.bootstrap(&bootstraps).await.is_none() {
+    tracing::warn!("no bootstrap peer answered, retrying them on every sync");
+  }
 
-  let app = Router::new()
-    .route("/", get(root))
-    .nest(
-      "/wallet",
-      Router::new()
-        .route("/create", post(create_keypair))
-        .route("/balance/:public_key", get(get_balance))
-        .route("/send", post(send)),
-    )
-    .nest(
-      "/peers",
-      Router::new()
-        .route("/", get(get_peers))
-        .route("/:addr", post(add_peer)),
-    )
-    .nest(
-      "/chain",
-      Router::new()
-        .route("/", get(get_blockchain))
-        .route("/", patch(update_blockchain)),
-    )
-    .with_state(state.clone())
+  let server_config = config::ServerConfig::from_env();
+  let node = Node::new(ledger, server_config.write_queue_size);
+  if let Err(err) = restore_chain(&data_dir, &node.writer).await {
+    if !config::has_flag(&args, "--resync-invalid-chain") {
+      return Err(err.context(
+        "saved chain is invalid, pass --resync-invalid-chain to start over and re-sync from peers",
+      ));
+    }
+    let moved_to = data_dir.quarantine_chain()?;
+    tracing::warn!(
+      err = format!("{err:#}"),
+      moved_to = %moved_to.display(),
+      "saved chain is invalid, starting over from genesis"
+    );
+  }
+  let app = app::with_server_limits(build_app(node.clone()), &server_config, node.chain.clone())
     .into_make_service_with_connect_info::<SocketAddr>();
 
+  if config::has_flag(&args, "--mine") && !config::has_flag(&args, "--read-only") {
+    tokio::spawn(app::mine_on_interval(node.clone(), config::mine_interval()));
+  }
+
   tokio::spawn(async move {
-    let mut interval = time::interval(Duration::from_secs(10));
+    let mut interval = time::interval(Duration::from_secs(
+      file_config.sync_interval_secs.unwrap_or(10),
+    ));
     loop {
       interval.tick().await;
-      state.lock().await.ledger.sync().await;
+      app::sync(&node).await;
+      // A frozen chain file is being copied, so it's left alone until then
+      if node.is_frozen() {
+        continue;
+      }
+      let ledger = node.chain.get();
+      if let Err(err) = data_dir.save_chain(ledger.chain()) {
+        tracing::error!(%err, "failed to save chain");
+      }
+      if let Err(err) = data_dir.save_peers(&ledger.get_peers()) {
+        tracing::error!(%err, "failed to save peers");
+      }
     }
   });
 
-  let listener = TcpListener::bind(format!("0.0.0.0:{}", port))
-    .await
-    .unwrap();
-  println!("Listening on port {port}");
-  axum::serve(listener, app).await?;
-
-  Ok(())
-}
-
-async fn root() -> Response {
-  (StatusCode::OK).into_response()
-}
-
-#[derive(Serialize, Deserialize)]
-struct CreateKeyPairResponse {
-  secret_key: String,
-  public_key: String,
-}
-
-async fn create_keypair() -> Response {
-  let Ok((secret_key, public_key)) = generate_keypair() else {
-    return (
-      StatusCode::INTERNAL_SERVER_ERROR,
-      String::from("could not generate private key"),
-    )
-      .into_response();
-  };
-  (
-    StatusCode::OK,
-    Json(CreateKeyPairResponse {
-      secret_key: secret_key.display_secret().to_string(),
-      public_key: public_key.to_string(),
-    }),
-  )
-    .into_response()
-}
-
-#[derive(Serialize, Deserialize)]
-struct GetBalanceParams {
-  public_key: String,
-}
-
-#[derive(Serialize, Deserialize)]
-struct GetBalanceResponse {
-  balance: i64,
-}
-
-#[axum::debug_handler]
-async fn get_balance(
-  Path(params): Path<GetBalanceParams>,
-  State(state): State<Arc<Mutex<AppState>>>,
-) -> Response {
-  let Ok(public_key) = PublicKey::from_str(&params.public_key) else {
-    return (
-      StatusCode::BAD_REQUEST,
-      String::from("could not parse public key"),
-    )
-      .into_response();
-  };
-  let Ok(balance) = state.lock().await.ledger.get_balance(&public_key) else {
-    return (
-      StatusCode::INTERNAL_SERVER_ERROR,
-      String::from("could not get balance"),
-    )
-      .into_response();
-  };
-  (StatusCode::OK, Json(GetBalanceResponse { balance })).into_response()
-}
-
-#[derive(Serialize, Deserialize)]
-struct SendBody {
-  to_public_key: String,
-  from_secret_key: String,
-  amount: u64,
-}
-
-async fn send(
-  State(app_state): State<Arc<Mutex<AppState>>>,
-  Json(params): Json<SendBody>,
-) -> Response {
-  let Ok(to_public_key) = PublicKey::from_str(&params.to_public_key) else {
-    return (
-      StatusCode::BAD_REQUEST,
-      String::from("invalid public key for sending to"),
-    )
-      .into_response();
-  };
-  let Ok(from_secret_key) = SecretKey::from_str(&params.from_secret_key) else {
-    return (
-      StatusCode::BAD_REQUEST,
-      String::from("invalid private key for sending from"),
-    )
-      .into_response();
-  };
-  match app_state
-    .lock()
-    .await
-    .ledger
-    .send(&to_public_key, &from_secret_key, params.amount)
-    .await
-  {
-    Ok(..) => (StatusCode::OK).into_response(),
-    Err(..) => (StatusCode::INTERNAL_SERVER_ERROR).into_response(),
+  let listener = bind(bind_host, &port, &server_config)?;
+  match tls_files {
+    Some((cert, key)) => {
+      let tls_config = RustlsConfig::from_pem_file(cert, key).await?;
+      println!("Listening on port {port} over HTTPS");
+      axum_server::from_tcp_rustls(listener.into_std()?, tls_config)
+        .serve(app)
+        .await?;
+    }
+    None => {
+      println!("Listening on port {port}");
+      axum::serve(listener, app).await?;
+    }
   }
-}
-
-#[derive(Deserialize)]
-struct AddPeerPath {
-  addr: SocketAddr,
-}
-
-async fn add_peer(
-  State(app_state): State<Arc<Mutex<AppState>>>,
-  Path(path): Path<AddPeerPath>,
-) -> Response {
-  app_state.lock().await.ledger.add_peer(path.addr);
-  (StatusCode::OK).into_response()
-}
 
-async fn get_blockchain(State(app_state): State<Arc<Mutex<AppState>>>) -> Response {
-  (
-    StatusCode::OK,
-    Json(app_state.lock().await.ledger.get_blockchain()),
-  )
-    .into_response()
-}
-
-#[derive(Deserialize, Serialize)]
-struct UpdateBlockchainBody {
-  blockchain: Blockchain,
-}
-
-async fn update_blockchain(
-  State(app_state): State<Arc<Mutex<AppState>>>,
-  Json(body): Json<UpdateBlockchainBody>,
-) -> Response {
-  app_state
-    .lock()
-    .await
-    .ledger
-    .update_blockchain(&body.blockchain)
-    .await;
-  (StatusCode::OK).into_response()
-}
-
-async fn get_peers(State(app_state): State<Arc<Mutex<AppState>>>) -> Response {
-  (
-    StatusCode::OK,
-    Json(app_state.lock().await.ledger.get_peers()),
-  )
-    .into_response()
+  Ok(())
 }
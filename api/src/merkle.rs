@@ -0,0 +1,157 @@
+//! Merkle trees over a block's transactions, so a light client can check a
+//! transaction is in a block from a handful of hashes rather than the whole
+//! block.
+//!
+//! Leaves are the SHA-256 of a transaction's `Transaction::encode` bytes, and
+//! each parent is the SHA-256 of its two children. A level with an odd number of nodes pairs
+//! its last node with itself. Leaves and parents are hashed with different
+//! prefixes so a parent can't pass for a leaf. Block hashes commit to the
+//! root, so a proof checked against a block header's root shows the
+//! transaction is in the block with that hash.
+
+use sha2::{Digest, Sha256};
+
+use crate::{from_hex, to_hex, Transaction};
+
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+
+/// The leaf for `transaction`.
+pub fn leaf_hash(transaction: &Transaction) -> [u8; 32] {
+  let mut hasher = Sha256::new();
+  hasher.update([LEAF_PREFIX]);
+  hasher.update(transaction.encode());
+  hasher.finalize().into()
+}
+
+fn node_hash(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+  let mut hasher = Sha256::new();
+  hasher.update([NODE_PREFIX]);
+  hasher.update(left);
+  hasher.update(right);
+  hasher.finalize().into()
+}
+
+/// Every level of the tree over `leaves`, from the leaves up to the root.
+fn levels(leaves: Vec<[u8; 32]>) -> Vec<Vec<[u8; 32]>> {
+  let mut levels = vec![leaves];
+  while let Some(level) = levels.last().filter(|level| level.len() > 1) {
+    let parents = level
+      .chunks(2)
+      .map(|pair| node_hash(&pair[0], pair.last().unwrap_or(&pair[0])))
+      .collect();
+    levels.push(parents);
+  }
+  levels
+}
+
+/// The root of the tree over `leaves`. A tree with no leaves has the SHA-256
+/// of nothing as its root.
+pub fn root(leaves: Vec<[u8; 32]>) -> [u8; 32] {
+  match levels(leaves).last().and_then(|level| level.first()) {
+    Some(root) => *root,
+    None => Sha256::digest([]).into(),
+  }
+}
+
+/// The hex siblings on the path from leaf `index` up to the root, lowest
+/// first, or none if there's no such leaf.
+pub fn proof(leaves: Vec<[u8; 32]>, index: usize) -> Vec<String> {
+  if index >= leaves.len() {
+    return Vec::new();
+  }
+  let levels = levels(leaves);
+  let mut position = index;
+  let mut siblings = Vec::new();
+  for level in &levels[..levels.len() - 1] {
+    let sibling = level.get(position ^ 1).unwrap_or(&level[position]);
+    siblings.push(to_hex(sibling));
+    position /= 2;
+  }
+  siblings
+}
+
+/// Whether `proof` shows `transaction` is leaf `index` of the tree with the
+/// hex `root`.
+pub fn verify_merkle_proof(
+  transaction: &Transaction,
+  index: usize,
+  proof: &[String],
+  root: &str,
+) -> bool {
+  let mut hash = leaf_hash(transaction);
+  let mut position = index;
+  for sibling in proof {
+    let Some(sibling) = from_hex(sibling).and_then(|bytes| <[u8; 32]>::try_from(bytes).ok()) else {
+      return false;
+    };
+    hash = if position.is_multiple_of(2) {
+      node_hash(&hash, &sibling)
+    } else {
+      node_hash(&sibling, &hash)
+    };
+    position /= 2;
+  }
+  // Anything left over means the proof was too short for the index
+  position == 0 && to_hex(&hash) == root
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{generate_keypair_from_seed, DEFAULT_CHAIN_ID};
+
+  /// `count` distinct payments, with their leaves.
+  fn transactions(count: u64) -> (Vec<Transaction>, Vec<[u8; 32]>) {
+    let (alice, _) = generate_keypair_from_seed([1; 32]).unwrap();
+    let (_, bob_public) = generate_keypair_from_seed([2; 32]).unwrap();
+    let transactions: Vec<Transaction> = (1..=count)
+      .map(|amount| Transaction::new(&bob_public, &alice, amount, DEFAULT_CHAIN_ID).unwrap())
+      .collect();
+    let leaves = transactions.iter().map(leaf_hash).collect();
+    (transactions, leaves)
+  }
+
+  #[test]
+  fn every_leaf_s_proof_verifies_against_the_root() {
+    for count in 1..=7 {
+      let (transactions, leaves) = transactions(count);
+      let root = to_hex(&root(leaves.clone()));
+      for (index, transaction) in transactions.iter().enumerate() {
+        let proof = proof(leaves.clone(), index);
+        assert!(
+          verify_merkle_proof(transaction, index, &proof, &root),
+          "leaf {index} of {count}"
+        );
+      }
+    }
+  }
+
+  #[test]
+  fn a_tampered_proof_fails() {
+    let (transactions, leaves) = transactions(5);
+    let root = to_hex(&root(leaves.clone()));
+    let proof = proof(leaves.clone(), 2);
+
+    let mut tampered = proof.clone();
+    tampered[1] = to_hex(&[0; 32]);
+    assert!(!verify_merkle_proof(&transactions[2], 2, &tampered, &root));
+    // The right proof for a different transaction or position
+    assert!(!verify_merkle_proof(&transactions[3], 2, &proof, &root));
+    assert!(!verify_merkle_proof(&transactions[2], 3, &proof, &root));
+    // A proof cut short of the root
+    assert!(!verify_merkle_proof(
+      &transactions[2],
+      2,
+      &proof[..proof.len() - 1],
+      &root
+    ));
+  }
+
+  #[test]
+  fn there_is_no_proof_for_a_missing_leaf() {
+    let (_, leaves) = transactions(3);
+
+    assert!(proof(leaves, 3).is_empty());
+  }
+}
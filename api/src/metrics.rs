@@ -0,0 +1,110 @@
+use std::{fmt::Write, time::Duration};
+
+/// Counters tracked by a node over its lifetime, exported in the Prometheus
+/// text exposition format.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+  blocks_mined: u64,
+  total_mine_time: Duration,
+  sync_successes: u64,
+  sync_failures: u64,
+  chain_downloads: u64,
+}
+
+impl Metrics {
+  pub fn record_block_mined(&mut self, mine_time: Duration) {
+    self.blocks_mined += 1;
+    self.total_mine_time += mine_time;
+  }
+  pub fn record_sync(&mut self, success: bool) {
+    if success {
+      self.sync_successes += 1;
+    } else {
+      self.sync_failures += 1;
+    }
+  }
+  /// Counts a peer's chain being downloaded because it differed from ours.
+  pub fn record_chain_download(&mut self) {
+    self.chain_downloads += 1;
+  }
+  /// Adds `other`'s counters to these.
+  pub fn merge(&mut self, other: &Metrics) {
+    self.blocks_mined += other.blocks_mined;
+    self.total_mine_time += other.total_mine_time;
+    self.sync_successes += other.sync_successes;
+    self.sync_failures += other.sync_failures;
+    self.chain_downloads += other.chain_downloads;
+  }
+  pub fn chain_downloads(&self) -> u64 {
+    self.chain_downloads
+  }
+  pub fn blocks_mined(&self) -> u64 {
+    self.blocks_mined
+  }
+  pub fn average_mine_time(&self) -> Duration {
+    if self.blocks_mined == 0 {
+      return Duration::ZERO;
+    }
+    self.total_mine_time / self.blocks_mined as u32
+  }
+  /// Renders these counters alongside the given gauges.
+  pub fn render(&self, chain_height: usize, peer_count: usize, mempool_size: usize) -> String {
+    let mut output = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+      // Writing to a `String` can't fail
+      let _ = write!(
+        output,
+        "# HELP silocoin_{name} {help}\n# TYPE silocoin_{name} {kind}\nsilocoin_{name} {value}\n"
+      );
+    };
+    metric(
+      "chain_height",
+      "gauge",
+      "Number of blocks in the local chain.",
+      chain_height.to_string(),
+    );
+    metric(
+      "peer_count",
+      "gauge",
+      "Number of known peers.",
+      peer_count.to_string(),
+    );
+    metric(
+      "mempool_size",
+      "gauge",
+      "Number of pending transactions.",
+      mempool_size.to_string(),
+    );
+    metric(
+      "blocks_mined_total",
+      "counter",
+      "Number of blocks mined by this node.",
+      self.blocks_mined.to_string(),
+    );
+    metric(
+      "sync_successes_total",
+      "counter",
+      "Number of successful peer syncs.",
+      self.sync_successes.to_string(),
+    );
+    metric(
+      "sync_failures_total",
+      "counter",
+      "Number of failed peer syncs.",
+      self.sync_failures.to_string(),
+    );
+    metric(
+      "chain_downloads_total",
+      "counter",
+      "Number of peer chains downloaded because they differed from ours.",
+      self.chain_downloads.to_string(),
+    );
+    metric(
+      "average_mine_time_seconds",
+      "gauge",
+      "Average time taken to mine a block.",
+      self.average_mine_time().as_secs_f64().to_string(),
+    );
+    output
+  }
+}
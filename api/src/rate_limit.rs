@@ -0,0 +1,89 @@
+use std::{
+  collections::HashMap,
+  net::{IpAddr, SocketAddr},
+  sync::{Arc, Mutex},
+  time::Instant,
+};
+
+use axum::{
+  extract::{ConnectInfo, Request, State},
+  http::StatusCode,
+  middleware::Next,
+  response::{IntoResponse, Response},
+};
+
+use crate::{app::ApiError, writer::ChainView};
+
+/// Buckets are only swept for idle clients once there are this many.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+struct Bucket {
+  tokens: f64,
+  last_refill: Instant,
+}
+
+/// A token bucket per client IP. Each request takes a token, and tokens
+/// refill at `rate` per second up to `burst`.
+pub struct RateLimiter {
+  rate: f64,
+  burst: f64,
+  buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+  pub fn new(rate: f64, burst: f64) -> Self {
+    RateLimiter {
+      rate,
+      burst,
+      buckets: Mutex::new(HashMap::new()),
+    }
+  }
+  /// Takes a token for `ip`, returning whether there was one to take.
+  pub fn try_acquire(&self, ip: IpAddr) -> bool {
+    let now = Instant::now();
+    let mut buckets = self
+      .buckets
+      .lock()
+      .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if buckets.len() >= MAX_TRACKED_CLIENTS {
+      // A client with a full bucket is indistinguishable from a new one
+      buckets.retain(|_, bucket| {
+        bucket.tokens + now.duration_since(bucket.last_refill).as_secs_f64() * self.rate
+          < self.burst
+      });
+    }
+    let bucket = buckets.entry(ip).or_insert(Bucket {
+      tokens: self.burst,
+      last_refill: now,
+    });
+    let refill = now.duration_since(bucket.last_refill).as_secs_f64() * self.rate;
+    bucket.tokens = (bucket.tokens + refill).min(self.burst);
+    bucket.last_refill = now;
+    if bucket.tokens < 1.0 {
+      return false;
+    }
+    bucket.tokens -= 1.0;
+    true
+  }
+}
+
+/// Middleware answering `429` to clients that have run out of tokens. Known
+/// peers are exempt, so sync and broadcasts between nodes aren't throttled;
+/// nodes sharing a machine share an IP, so locally this exempts every client
+/// once any local peer is known.
+pub async fn rate_limit(
+  State((limiter, chain)): State<(Arc<RateLimiter>, ChainView)>,
+  ConnectInfo(addr): ConnectInfo<SocketAddr>,
+  request: Request,
+  next: Next,
+) -> Response {
+  if !limiter.try_acquire(addr.ip()) && !chain.get().is_peer_ip(addr.ip()) {
+    return ApiError::new(
+      StatusCode::TOO_MANY_REQUESTS,
+      "rate_limited",
+      "rate limit exceeded",
+    )
+    .into_response();
+  }
+  next.run(request).await
+}
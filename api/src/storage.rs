@@ -0,0 +1,180 @@
+use std::{
+  collections::HashSet,
+  net::SocketAddr,
+  path::{Path, PathBuf},
+  str::FromStr,
+};
+
+use secp256k1::SecretKey;
+
+use crate::{config, Blockchain, SilocoinError};
+
+/// The directory a node keeps its chain and peers in between runs.
+#[derive(Debug, Clone)]
+pub struct DataDir {
+  root: PathBuf,
+}
+
+impl DataDir {
+  /// Opens `root`, creating it (and any missing parents) first.
+  pub fn open(root: impl Into<PathBuf>) -> Result<Self, SilocoinError> {
+    let root = root.into();
+    std::fs::create_dir_all(&root)?;
+    Ok(DataDir { root })
+  }
+  pub fn root(&self) -> &Path {
+    &self.root
+  }
+  pub fn chain_path(&self) -> PathBuf {
+    self.root.join("chain.json")
+  }
+  /// Peers are stored one per line, the same format `--peers` reads.
+  pub fn peers_path(&self) -> PathBuf {
+    self.root.join("peers.txt")
+  }
+  /// The node's identity key, stored as hex.
+  pub fn identity_path(&self) -> PathBuf {
+    self.root.join("identity.key")
+  }
+  /// The node's identity key, generating and saving one on first run so a
+  /// node keeps the identity peers registered it under across restarts.
+  pub fn load_or_create_identity(&self) -> Result<SecretKey, SilocoinError> {
+    match std::fs::read_to_string(self.identity_path()) {
+      Ok(hex) => Ok(SecretKey::from_str(hex.trim())?),
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+        let identity_key = SecretKey::new(&mut rand::thread_rng());
+        write_atomically(
+          &self.identity_path(),
+          identity_key.display_secret().to_string().as_bytes(),
+        )?;
+        Ok(identity_key)
+      }
+      Err(err) => Err(err.into()),
+    }
+  }
+  /// The saved chain, or `None` if nothing has been saved yet.
+  pub fn load_chain(&self) -> Result<Option<Blockchain>, SilocoinError> {
+    match std::fs::read(self.chain_path()) {
+      Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+      Err(err) => Err(err.into()),
+    }
+  }
+  /// Moves the saved chain aside to `chain.json.invalid`, keeping it for
+  /// inspection, and returns where it went.
+  pub fn quarantine_chain(&self) -> Result<PathBuf, SilocoinError> {
+    let quarantine_path = self.root.join("chain.json.invalid");
+    std::fs::rename(self.chain_path(), &quarantine_path)?;
+    Ok(quarantine_path)
+  }
+  pub fn save_chain(&self, blockchain: &Blockchain) -> Result<(), SilocoinError> {
+    write_atomically(&self.chain_path(), &serde_json::to_vec(blockchain)?)
+  }
+  /// The saved peers, or none if nothing has been saved yet.
+  pub fn load_peers(&self) -> Result<HashSet<SocketAddr>, SilocoinError> {
+    match std::fs::read_to_string(self.peers_path()) {
+      Ok(input) => Ok(config::parse_peers(&input)),
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(HashSet::new()),
+      Err(err) => Err(err.into()),
+    }
+  }
+  pub fn save_peers(&self, peers: &HashSet<SocketAddr>) -> Result<(), SilocoinError> {
+    let contents: String = peers.iter().map(|peer| format!("{}\n", peer)).collect();
+    write_atomically(&self.peers_path(), contents.as_bytes())
+  }
+}
+
+/// Writes to a temporary file first so a crash mid-write can't leave a
+/// truncated file behind.
+fn write_atomically(path: &Path, contents: &[u8]) -> Result<(), SilocoinError> {
+  let temp_path = path.with_extension("tmp");
+  std::fs::write(&temp_path, contents)?;
+  std::fs::rename(&temp_path, path)?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// A directory under the system temp dir, named for `test`, that doesn't
+  /// exist yet.
+  fn fresh_root(test: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("silo-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    root
+  }
+
+  #[test]
+  fn open_creates_the_directory_and_its_parents() {
+    let root = fresh_root("open");
+    let nested = root.join("a").join("b");
+
+    let data_dir = DataDir::open(&nested).unwrap();
+
+    assert!(nested.is_dir());
+    assert_eq!(data_dir.root(), nested);
+    std::fs::remove_dir_all(&root).unwrap();
+  }
+
+  #[test]
+  fn files_live_under_the_root() {
+    let root = fresh_root("paths");
+    let data_dir = DataDir::open(&root).unwrap();
+    let peers = HashSet::from(["10.0.0.2:3000".parse().unwrap()]);
+    let blockchain = Blockchain::new(crate::DEFAULT_CHAIN_ID, 1).unwrap();
+
+    data_dir.save_peers(&peers).unwrap();
+    data_dir.save_chain(&blockchain).unwrap();
+    data_dir.load_or_create_identity().unwrap();
+
+    assert_eq!(data_dir.chain_path(), root.join("chain.json"));
+    assert_eq!(data_dir.peers_path(), root.join("peers.txt"));
+    assert_eq!(data_dir.identity_path(), root.join("identity.key"));
+    for path in [
+      data_dir.chain_path(),
+      data_dir.peers_path(),
+      data_dir.identity_path(),
+    ] {
+      assert!(path.is_file(), "{} wasn't written", path.display());
+    }
+    assert_eq!(data_dir.load_peers().unwrap(), peers);
+    assert_eq!(data_dir.load_chain().unwrap(), Some(blockchain));
+    std::fs::remove_dir_all(&root).unwrap();
+  }
+
+  #[test]
+  fn a_corrupt_chain_fails_to_load_and_can_be_set_aside() {
+    let root = fresh_root("corrupt");
+    let data_dir = DataDir::open(&root).unwrap();
+    std::fs::write(data_dir.chain_path(), "{\"chain\": [").unwrap();
+
+    assert!(data_dir.load_chain().is_err());
+    let moved_to = data_dir.quarantine_chain().unwrap();
+
+    assert_eq!(moved_to, root.join("chain.json.invalid"));
+    assert_eq!(std::fs::read_to_string(&moved_to).unwrap(), "{\"chain\": [");
+    // The next start finds no chain and begins again from genesis
+    assert_eq!(data_dir.load_chain().unwrap(), None);
+    std::fs::remove_dir_all(&root).unwrap();
+  }
+
+  #[test]
+  fn a_tampered_chain_loads_but_names_the_broken_block() {
+    let root = fresh_root("tampered");
+    let data_dir = DataDir::open(&root).unwrap();
+    let blockchain = Blockchain::new(crate::DEFAULT_CHAIN_ID, 1).unwrap();
+    let mut saved = serde_json::to_value(&blockchain).unwrap();
+    saved["chain"][0]["prev_block_hash"] = "tampered".into();
+    std::fs::write(data_dir.chain_path(), saved.to_string()).unwrap();
+
+    let loaded = data_dir.load_chain().unwrap().unwrap();
+
+    assert_eq!(
+      loaded.validate().unwrap_err().to_string(),
+      SilocoinError::InvalidChain("block 0 doesn't link to the block before it".to_string())
+        .to_string()
+    );
+    std::fs::remove_dir_all(&root).unwrap();
+  }
+}
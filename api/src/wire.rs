@@ -0,0 +1,160 @@
+//! Lets the chain and block endpoints speak MessagePack as well as JSON.
+//! JSON stays the default; a whole chain is much smaller as MessagePack, so
+//! peers ask for it when syncing.
+
+use std::convert::Infallible;
+
+use axum::{
+  async_trait,
+  body::Bytes,
+  extract::{FromRequest, FromRequestParts, Request},
+  http::{header, request::Parts, HeaderMap, StatusCode},
+  response::{IntoResponse, Response},
+  Json,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{app::ApiError, SilocoinError};
+
+pub const MSGPACK_CONTENT_TYPE: &str = "application/msgpack";
+
+/// An encoding for request and response bodies.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WireFormat {
+  #[default]
+  Json,
+  MsgPack,
+}
+
+impl WireFormat {
+  /// The format an `Accept` header asks for: MessagePack if it's listed at
+  /// all, JSON otherwise.
+  pub fn accepted_by(headers: &HeaderMap) -> WireFormat {
+    WireFormat::named_by(headers, header::ACCEPT)
+  }
+  /// The format a body is in, going by its `Content-Type`.
+  pub fn of_body(headers: &HeaderMap) -> WireFormat {
+    WireFormat::named_by(headers, header::CONTENT_TYPE)
+  }
+  fn named_by(headers: &HeaderMap, name: header::HeaderName) -> WireFormat {
+    let names_msgpack = headers
+      .get_all(name)
+      .iter()
+      .filter_map(|value| value.to_str().ok())
+      .flat_map(|value| value.split(','))
+      .any(|media_type| media_type.trim().starts_with(MSGPACK_CONTENT_TYPE));
+    if names_msgpack {
+      WireFormat::MsgPack
+    } else {
+      WireFormat::Json
+    }
+  }
+  /// The `Accept` header asking a peer for this format. Peers that only
+  /// speak JSON ignore it and answer in JSON.
+  pub fn accept_header(self) -> &'static str {
+    match self {
+      WireFormat::Json => "application/json",
+      WireFormat::MsgPack => "application/msgpack, application/json;q=0.5",
+    }
+  }
+  pub fn content_type(self) -> &'static str {
+    match self {
+      WireFormat::Json => "application/json",
+      WireFormat::MsgPack => MSGPACK_CONTENT_TYPE,
+    }
+  }
+  pub fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>, SilocoinError> {
+    match self {
+      WireFormat::Json => Ok(serde_json::to_vec(value)?),
+      // Field names are kept so optional fields can be left out, as in JSON
+      WireFormat::MsgPack => {
+        rmp_serde::to_vec_named(value).map_err(|err| SilocoinError::Serialization(err.to_string()))
+      }
+    }
+  }
+  pub fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, SilocoinError> {
+    match self {
+      WireFormat::Json => Ok(serde_json::from_slice(bytes)?),
+      WireFormat::MsgPack => {
+        rmp_serde::from_slice(bytes).map_err(|err| SilocoinError::Serialization(err.to_string()))
+      }
+    }
+  }
+}
+
+impl std::str::FromStr for WireFormat {
+  type Err = anyhow::Error;
+
+  fn from_str(name: &str) -> anyhow::Result<Self> {
+    match name {
+      "json" => Ok(WireFormat::Json),
+      "msgpack" => Ok(WireFormat::MsgPack),
+      _ => Err(anyhow::Error::msg(format!("unknown wire format {}", name))),
+    }
+  }
+}
+
+/// Reads a peer's response in whichever format it answered with.
+pub async fn read_response<T: DeserializeOwned>(
+  response: reqwest::Response,
+) -> Result<T, SilocoinError> {
+  let format = WireFormat::of_body(response.headers());
+  let bytes = response.bytes().await?;
+  format.decode(&bytes)
+}
+
+/// The format a request's `Accept` header asks the response to be in.
+pub struct Negotiated(pub WireFormat);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Negotiated {
+  type Rejection = Infallible;
+
+  async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+    Ok(Negotiated(WireFormat::accepted_by(&parts.headers)))
+  }
+}
+
+/// A response body encoded in a negotiated format.
+pub struct Wire<T>(pub WireFormat, pub T);
+
+impl<T: Serialize> IntoResponse for Wire<T> {
+  fn into_response(self) -> Response {
+    let Wire(format, value) = self;
+    match format.encode(&value) {
+      Ok(bytes) => ([(header::CONTENT_TYPE, format.content_type())], bytes).into_response(),
+      Err(err) => ApiError::new(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "encoding_failed",
+        err.to_string(),
+      )
+      .into_response(),
+    }
+  }
+}
+
+/// A request body in JSON or MessagePack, going by its `Content-Type`.
+pub struct WireBody<T>(pub T);
+
+#[async_trait]
+impl<T: DeserializeOwned, S: Send + Sync> FromRequest<S> for WireBody<T> {
+  type Rejection = ApiError;
+
+  async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+    if WireFormat::of_body(request.headers()) == WireFormat::Json {
+      let Json(body) = Json::<T>::from_request(request, state).await?;
+      return Ok(WireBody(body));
+    }
+    let bytes = Bytes::from_request(request, state).await?;
+    WireFormat::MsgPack
+      .decode(&bytes)
+      .map(WireBody)
+      .map_err(|_| {
+        ApiError::new(
+          StatusCode::BAD_REQUEST,
+          "invalid_body",
+          "request body isn't valid MessagePack with the expected fields",
+        )
+      })
+  }
+}
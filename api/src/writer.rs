@@ -0,0 +1,385 @@
+//! Funnels every change to the ledger through a single task that owns it, so
+//! changes are applied one at a time in the order they're queued. Reads are
+//! served from a `ChainView` the task republishes after each change, so they
+//! never wait on a write. Payments waiting while a block is mined are mined
+//! together into the next one, off the task on a blocking thread.
+
+use std::{
+  net::SocketAddr,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, RwLock,
+  },
+};
+
+use tokio::{
+  sync::{mpsc, oneshot},
+  task::{self, JoinHandle},
+};
+
+use crate::{
+  AnnounceError, AppendBlockError, AppendBlocksError, Block, Blockchain, Ledger, MinedBlock,
+  MiningError, MiningJob, PeerAnnouncement, Placement, SendReceipt, SilocoinError, SyncRound,
+  SyncSummary, Transaction,
+};
+
+/// The ledger as of the last change the writer applied.
+#[derive(Debug, Clone)]
+pub struct ChainView(Arc<RwLock<Arc<Ledger>>>);
+
+impl ChainView {
+  fn new(ledger: &Ledger) -> Self {
+    ChainView(Arc::new(RwLock::new(Arc::new(ledger.clone()))))
+  }
+  /// The latest snapshot. It isn't updated in place, so it can be held for
+  /// as long as needed without holding up the writer.
+  pub fn get(&self) -> Arc<Ledger> {
+    self.0.read().expect("chain view lock poisoned").clone()
+  }
+  fn publish(&self, ledger: &Ledger) {
+    *self.0.write().expect("chain view lock poisoned") = Arc::new(ledger.clone());
+  }
+}
+
+/// Already signed transactions from one sender, waiting to be mined, with
+/// where to send the receipt.
+struct Payment {
+  transactions: Vec<Transaction>,
+  force: bool,
+  reply: oneshot::Sender<Result<SendReceipt, SilocoinError>>,
+}
+
+/// A change to the ledger, with where to send its outcome.
+enum Command {
+  AppendBlock(Block, oneshot::Sender<Result<(), AppendBlockError>>),
+  AppendBlocks(Vec<Block>, oneshot::Sender<Result<(), AppendBlocksError>>),
+  UpdateBlockchain(Blockchain, oneshot::Sender<Result<(), SilocoinError>>),
+  Pay(Payment),
+  Submit(
+    Transaction,
+    bool,
+    oneshot::Sender<Result<String, SilocoinError>>,
+  ),
+  StartMining(oneshot::Sender<Result<Option<MiningJob>, SilocoinError>>),
+  FinishMining(
+    MinedBlock,
+    oneshot::Sender<Result<Option<SendReceipt>, SilocoinError>>,
+  ),
+  StartSync(oneshot::Sender<Option<Ledger>>),
+  FinishSync(SyncRound, oneshot::Sender<Option<SyncSummary>>),
+  AcceptAnnouncement(PeerAnnouncement, oneshot::Sender<Result<(), AnnounceError>>),
+  AddPeer(SocketAddr, oneshot::Sender<bool>),
+}
+
+/// Queues changes for the writer task started by `spawn`. Each method waits
+/// for its change to be applied and returns the outcome, the same as the
+/// `Ledger` method it stands in for.
+#[derive(Debug, Clone)]
+pub struct ChainWriter {
+  commands: mpsc::Sender<Command>,
+}
+
+impl ChainWriter {
+  async fn run<T>(&self, command: impl FnOnce(oneshot::Sender<T>) -> Command) -> T {
+    let (reply, outcome) = oneshot::channel();
+    self
+      .commands
+      .send(command(reply))
+      .await
+      .unwrap_or_else(|_| panic!("chain writer stopped"));
+    outcome.await.expect("chain writer dropped a command")
+  }
+  pub async fn append_block(&self, block: Block) -> Result<(), AppendBlockError> {
+    self.run(|reply| Command::AppendBlock(block, reply)).await
+  }
+  pub async fn append_blocks(&self, blocks: Vec<Block>) -> Result<(), AppendBlocksError> {
+    self.run(|reply| Command::AppendBlocks(blocks, reply)).await
+  }
+  pub async fn update_blockchain(&self, blockchain: Blockchain) -> Result<(), SilocoinError> {
+    self
+      .run(|reply| Command::UpdateBlockchain(blockchain, reply))
+      .await
+  }
+  /// Mines already signed `transactions`, all from one sender, into the
+  /// chain, as `Ledger::send_batch` does. They share a block with whatever
+  /// other payments are waiting when it's started, and are mined again if
+  /// our tip moves on before it's found. Fails with `SilocoinError::Frozen`
+  /// if the node is frozen before the block is appended.
+  pub async fn pay(
+    &self,
+    transactions: Vec<Transaction>,
+    force: bool,
+  ) -> Result<SendReceipt, SilocoinError> {
+    self
+      .run(|reply| {
+        Command::Pay(Payment {
+          transactions,
+          force,
+          reply,
+        })
+      })
+      .await
+  }
+  /// Leaves an already signed payment in the mempool, see `Ledger::submit`.
+  pub async fn submit(
+    &self,
+    transaction: Transaction,
+    force: bool,
+  ) -> Result<String, SilocoinError> {
+    self
+      .run(|reply| Command::Submit(transaction, force, reply))
+      .await
+  }
+  pub async fn start_mining(&self) -> Result<Option<MiningJob>, SilocoinError> {
+    self.run(Command::StartMining).await
+  }
+  /// Appends a block mined from a `MiningJob`, or drops it if the node is
+  /// frozen, in which case it returns `Ok(None)`.
+  pub async fn finish_mining(
+    &self,
+    mined: MinedBlock,
+  ) -> Result<Option<SendReceipt>, SilocoinError> {
+    self.run(|reply| Command::FinishMining(mined, reply)).await
+  }
+  /// Evicts failing peers and returns a copy of the ledger to poll them
+  /// from, or `None` if the node is frozen.
+  pub async fn start_sync(&self) -> Option<Ledger> {
+    self.run(Command::StartSync).await
+  }
+  /// Applies a sync round, or nothing if the node is frozen, in which case
+  /// it returns `None`.
+  pub async fn finish_sync(&self, round: SyncRound) -> Option<SyncSummary> {
+    self.run(|reply| Command::FinishSync(round, reply)).await
+  }
+  pub async fn accept_announcement(
+    &self,
+    announcement: PeerAnnouncement,
+  ) -> Result<(), AnnounceError> {
+    self
+      .run(|reply| Command::AcceptAnnouncement(announcement, reply))
+      .await
+  }
+  pub async fn add_peer(&self, addr: SocketAddr) -> bool {
+    self.run(|reply| Command::AddPeer(addr, reply)).await
+  }
+}
+
+/// A block of payments being mined.
+struct Mining {
+  job: JoinHandle<Result<MinedBlock, SilocoinError>>,
+  payments: Vec<Payment>,
+}
+
+/// The task behind `ChainWriter`, and everything only it touches.
+struct Writer {
+  ledger: Ledger,
+  view: ChainView,
+  frozen: Arc<AtomicBool>,
+  /// Payments waiting for the next block.
+  queued: Vec<Payment>,
+  mining: Option<Mining>,
+}
+
+impl Writer {
+  async fn run(mut self, mut commands: mpsc::Receiver<Command>) {
+    loop {
+      tokio::select! {
+        command = commands.recv() => match command {
+          Some(command) => self.apply(command),
+          None => return,
+        },
+        mined = async { (&mut self.mining.as_mut().expect("mining").job).await },
+          if self.mining.is_some() => self.finish_paying(mined.expect("mining task panicked")),
+      }
+      // Take everything already queued before starting a block, so payments
+      // sent together are mined together
+      while let Ok(command) = commands.try_recv() {
+        self.apply(command);
+      }
+      self.start_paying();
+    }
+  }
+  fn frozen(&self) -> bool {
+    self.frozen.load(Ordering::Relaxed)
+  }
+  /// Republishes the ledger and sends `outcome`. It's published first, so
+  /// callers see their change as soon as they hear back. A caller that
+  /// stopped waiting doesn't need the outcome.
+  fn reply<T>(&self, reply: oneshot::Sender<T>, outcome: T) {
+    self.view.publish(&self.ledger);
+    let _ = reply.send(outcome);
+  }
+  fn apply(&mut self, command: Command) {
+    match command {
+      Command::AppendBlock(block, reply) => {
+        let outcome = self.ledger.append_block(block);
+        self.reply(reply, outcome);
+      }
+      Command::AppendBlocks(blocks, reply) => {
+        let outcome = self.ledger.append_blocks(blocks);
+        self.reply(reply, outcome);
+      }
+      Command::UpdateBlockchain(blockchain, reply) => {
+        let outcome = self.ledger.update_blockchain(&blockchain);
+        self.reply(reply, outcome);
+      }
+      Command::Pay(payment) => {
+        if self.frozen() {
+          let _ = payment.reply.send(Err(SilocoinError::Frozen));
+        } else {
+          self.queued.push(payment);
+        }
+      }
+      Command::Submit(transaction, force, reply) => {
+        let outcome = self.ledger.submit(transaction, force);
+        self.reply(reply, outcome);
+      }
+      Command::StartMining(reply) => {
+        let outcome = self.ledger.start_mining();
+        self.reply(reply, outcome);
+      }
+      Command::FinishMining(mined, reply) => {
+        let outcome = if self.frozen() {
+          Ok(None)
+        } else {
+          self.ledger.finish_mining(mined).map(Some)
+        };
+        self.reply(reply, outcome);
+      }
+      Command::StartSync(reply) => {
+        let outcome = (!self.frozen()).then(|| self.ledger.sync_snapshot());
+        self.reply(reply, outcome);
+      }
+      Command::FinishSync(round, reply) => {
+        let outcome = (!self.frozen()).then(|| self.ledger.finish_sync(round));
+        self.reply(reply, outcome);
+      }
+      Command::AcceptAnnouncement(announcement, reply) => {
+        let outcome = self.ledger.accept_announcement(announcement);
+        self.reply(reply, outcome);
+      }
+      Command::AddPeer(addr, reply) => {
+        let outcome = self.ledger.add_peer(addr);
+        self.reply(reply, outcome);
+      }
+    }
+  }
+  /// Starts mining the queued payments into a block, unless one is already
+  /// being mined.
+  fn start_paying(&mut self) {
+    if self.mining.is_some() || self.queued.is_empty() {
+      return;
+    }
+    let queued = std::mem::take(&mut self.queued);
+    if self.frozen() {
+      for payment in queued {
+        let _ = payment.reply.send(Err(SilocoinError::Frozen));
+      }
+      return;
+    }
+    let (placements, job) = self.ledger.start_payments(
+      queued
+        .iter()
+        .map(|payment| (payment.transactions.as_slice(), payment.force)),
+    );
+    let mut payments = Vec::new();
+    for (payment, placement) in queued.into_iter().zip(placements) {
+      match placement {
+        Placement::InBlock => payments.push(payment),
+        Placement::Deferred => self.queued.push(payment),
+        Placement::Refused(err) => {
+          let _ = payment.reply.send(Err(err));
+        }
+      }
+    }
+    match job {
+      Ok(Some(job)) => {
+        self.mining = Some(Mining {
+          job: task::spawn_blocking(|| job.mine()),
+          payments,
+        });
+      }
+      Ok(None) => {}
+      Err(err) => {
+        for payment in payments {
+          let _ = payment.reply.send(Err(copy_block_error(&err)));
+        }
+      }
+    }
+  }
+  /// Appends the block of payments just mined, and sends each payment its
+  /// receipt.
+  fn finish_paying(&mut self, mined: Result<MinedBlock, SilocoinError>) {
+    let Some(Mining { payments, .. }) = self.mining.take() else {
+      return;
+    };
+    let receipt = match mined {
+      Ok(..) if self.frozen() => Err(SilocoinError::Frozen),
+      Ok(mined) => self.ledger.finish_mining(mined),
+      Err(err) => Err(err),
+    };
+    match receipt {
+      Ok(receipt) => {
+        self.view.publish(&self.ledger);
+        // Each payment only hears about its own transactions
+        for payment in payments {
+          let signatures = payment
+            .transactions
+            .iter()
+            .map(|transaction| transaction.signature().to_string())
+            .collect();
+          let _ = payment.reply.send(Ok(SendReceipt {
+            signatures,
+            ..receipt.clone()
+          }));
+        }
+      }
+      // Our tip moved on while we mined, so mine them again on the new one,
+      // ahead of payments queued since
+      Err(SilocoinError::Mining(MiningError::Cancelled)) => {
+        let queued_since = std::mem::replace(&mut self.queued, payments);
+        self.queued.extend(queued_since);
+      }
+      Err(err) => {
+        for payment in payments {
+          let _ = payment.reply.send(Err(copy_block_error(&err)));
+        }
+      }
+    }
+  }
+}
+
+/// A copy of `err`, which failed a whole block of payments, for each of
+/// them. Starting, mining or appending a block can only fail in ways that
+/// are cheap to copy, bar the odd clock or I/O error that's passed on as
+/// text.
+fn copy_block_error(err: &SilocoinError) -> SilocoinError {
+  match err {
+    SilocoinError::Mining(err) => SilocoinError::Mining(*err),
+    SilocoinError::Frozen => SilocoinError::Frozen,
+    SilocoinError::ReadOnly => SilocoinError::ReadOnly,
+    err => SilocoinError::InvalidChain(err.to_string()),
+  }
+}
+
+/// Starts the task that owns `ledger` and applies every change to it. The
+/// returned view follows its changes. Nothing changes while `frozen` is set.
+/// At most `queue_size` changes wait for the task; callers queueing more wait
+/// for room.
+pub fn spawn(
+  ledger: Ledger,
+  frozen: Arc<AtomicBool>,
+  queue_size: usize,
+) -> (ChainWriter, ChainView) {
+  let (commands, queue) = mpsc::channel(queue_size);
+  let view = ChainView::new(&ledger);
+  let writer = Writer {
+    ledger,
+    view: view.clone(),
+    frozen,
+    queued: Vec::new(),
+    mining: None,
+  };
+  tokio::spawn(writer.run(queue));
+  (ChainWriter { commands }, view)
+}
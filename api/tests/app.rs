@@ -0,0 +1,251 @@
+//! The router `build_app` puts together.
+
+mod common;
+
+use std::{
+  collections::HashSet,
+  net::{Ipv4Addr, SocketAddr},
+  time::Duration,
+};
+
+use axum::{
+  body::Body,
+  extract::connect_info::MockConnectInfo,
+  http::{header, Method, Request, StatusCode},
+  routing::get,
+  Router,
+};
+use common::{keypair, payment, test_config, TestNode};
+use serde_json::{json, Value};
+use tanishqoin_api::{
+  app::{self, build_app},
+  config::{ServerConfig, DEFAULT_MAX_BODY_BYTES},
+  LedgerConfig, DEFAULT_CHAIN_ID, PROTOCOL_VERSION,
+};
+use tower::ServiceExt;
+
+#[tokio::test]
+async fn build_app_serves_the_root() {
+  let node = TestNode::standalone().await;
+
+  let (status, _) = node.get("/").await;
+
+  assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn build_app_answers_unknown_routes_with_not_found() {
+  let node = TestNode::standalone().await;
+
+  let (status, _) = node.get("/no/such/route").await;
+
+  assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+/// `method`s `uri` with a `content_type` body one byte over the default
+/// body limit.
+async fn send_oversized(
+  node: &TestNode,
+  method: Method,
+  uri: &str,
+  content_type: &str,
+) -> StatusCode {
+  let body = vec![b' '; DEFAULT_MAX_BODY_BYTES + 1];
+  let request = Request::builder()
+    .method(method)
+    .uri(uri)
+    .header(header::CONTENT_TYPE, content_type)
+    .body(Body::from(body))
+    .unwrap();
+  node.raw_request(request).await.0
+}
+
+#[tokio::test]
+async fn oversized_bodies_are_refused() {
+  let node = TestNode::standalone().await;
+  for (method, uri, content_type) in [
+    (Method::PATCH, "/chain", "application/json"),
+    (Method::PATCH, "/chain", "application/msgpack"),
+    (Method::POST, "/chain/block", "application/json"),
+    (Method::POST, "/chain/blocks", "application/json"),
+    (Method::POST, "/wallet/send", "application/json"),
+    (Method::POST, "/wallet/submit", "application/json"),
+    (Method::POST, "/wallet/send-batch", "application/json"),
+  ] {
+    let status = send_oversized(&node, method, uri, content_type).await;
+    assert_eq!(
+      status,
+      StatusCode::PAYLOAD_TOO_LARGE,
+      "{uri} {content_type}"
+    );
+  }
+}
+
+#[tokio::test]
+async fn the_body_limit_comes_from_the_node_config() {
+  let config = LedgerConfig {
+    max_body_bytes: 1024,
+    ..test_config()
+  };
+  let node = TestNode::start(common::listener().await, HashSet::new(), config).await;
+  let (alice, _) = keypair(1);
+  let (_, bob_public) = keypair(2);
+  let mut body = payment(&alice, &bob_public, 10);
+  body["memo"] = json!("x".repeat(1024));
+
+  let (status, _) = node.post("/wallet/send", body).await;
+
+  assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn info_reports_the_package_version() {
+  let node = TestNode::standalone().await;
+
+  let (status, info) = node.get("/info").await;
+
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(info["version"], env!("CARGO_PKG_VERSION"));
+  assert_eq!(info["protocol_version"], PROTOCOL_VERSION);
+  assert_eq!(info["chain_id"], DEFAULT_CHAIN_ID);
+  assert_eq!(info["difficulty"], common::TEST_DIFFICULTY);
+}
+
+/// Sends `GET /slow` through the server limits around a route that takes a
+/// second to answer, and returns the status.
+async fn slow_request_status(server_config: &ServerConfig) -> StatusCode {
+  let node = TestNode::standalone().await;
+  let slow = Router::new().route(
+    "/slow",
+    get(|| async {
+      tokio::time::sleep(Duration::from_secs(1)).await;
+      "done"
+    }),
+  );
+  app::with_server_limits(slow, server_config, node.node.chain.clone())
+    .layer(MockConnectInfo(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))))
+    .oneshot(Request::get("/slow").body(Body::empty()).unwrap())
+    .await
+    .unwrap()
+    .status()
+}
+
+#[tokio::test]
+async fn a_request_past_the_timeout_is_cut_off() {
+  let server_config = ServerConfig {
+    request_timeout: Duration::from_millis(100),
+    ..ServerConfig::from_env()
+  };
+
+  assert_eq!(
+    slow_request_status(&server_config).await,
+    StatusCode::REQUEST_TIMEOUT
+  );
+}
+
+#[tokio::test]
+async fn a_request_within_the_timeout_is_answered() {
+  let server_config = ServerConfig {
+    request_timeout: Duration::from_secs(5),
+    ..ServerConfig::from_env()
+  };
+
+  assert_eq!(slow_request_status(&server_config).await, StatusCode::OK);
+}
+
+/// Sends `count` `GET /` requests from one client through the server limits,
+/// allowing a burst of two and then one request a minute, and returns the
+/// statuses.
+async fn statuses_past_the_burst(node: &TestNode, count: usize) -> Vec<StatusCode> {
+  let server_config = ServerConfig {
+    rate_limit_per_sec: 1.0 / 60.0,
+    rate_limit_burst: 2.0,
+    ..ServerConfig::from_env()
+  };
+  let app = app::with_server_limits(
+    build_app(node.node.clone()),
+    &server_config,
+    node.node.chain.clone(),
+  )
+  .layer(MockConnectInfo(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))));
+  let mut statuses = Vec::with_capacity(count);
+  for _ in 0..count {
+    let response = app
+      .clone()
+      .oneshot(Request::get("/").body(Body::empty()).unwrap())
+      .await
+      .unwrap();
+    statuses.push(response.status());
+  }
+  statuses
+}
+
+#[tokio::test]
+async fn a_client_past_its_rate_is_told_to_back_off() {
+  let node = TestNode::standalone().await;
+
+  let statuses = statuses_past_the_burst(&node, 3).await;
+
+  assert_eq!(
+    statuses,
+    [
+      StatusCode::OK,
+      StatusCode::OK,
+      StatusCode::TOO_MANY_REQUESTS
+    ]
+  );
+}
+
+#[tokio::test]
+async fn known_peers_are_not_rate_limited() {
+  let node = TestNode::standalone().await;
+  let peer = TestNode::standalone().await;
+  node.add_peer(&peer).await;
+
+  let statuses = statuses_past_the_burst(&node, 3).await;
+
+  assert!(statuses.iter().all(|status| *status == StatusCode::OK));
+}
+
+/// `PATCH`es `/chain` with `body` sent as `content_type`, and returns the
+/// status and the body read as JSON.
+async fn patch_chain(node: &TestNode, body: &str, content_type: &str) -> (StatusCode, Value) {
+  let request = Request::patch("/chain")
+    .header(header::CONTENT_TYPE, content_type)
+    .body(Body::from(body.to_owned()))
+    .unwrap();
+  let (status, bytes) = node.raw_request(request).await;
+  (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+#[tokio::test]
+async fn unparseable_chains_are_a_structured_bad_request() {
+  let node = TestNode::standalone().await;
+  for (body, content_type, error) in [
+    (
+      "{ not json",
+      "application/json",
+      "request body isn't valid JSON",
+    ),
+    (
+      r#"{ "chain": [] }"#,
+      "application/json",
+      "request body doesn't have the expected fields",
+    ),
+    (
+      r#"{ "blockchain": {} }"#,
+      "text/plain",
+      "request body must be sent as application/json",
+    ),
+  ] {
+    let (status, response) = patch_chain(&node, body, content_type).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+    assert_eq!(
+      response,
+      json!({ "error": error, "code": "invalid_body" }),
+      "{body}"
+    );
+  }
+  assert_eq!(node.chain().height(), 1);
+}
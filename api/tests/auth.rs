@@ -0,0 +1,144 @@
+//! Peer requests signed with a shared network secret.
+
+mod common;
+
+use std::{collections::HashSet, net::SocketAddr, sync::Arc};
+
+use axum::{
+  middleware,
+  routing::{get, post},
+  Router,
+};
+use common::{listener, serve, test_config, TestNode};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use tanishqoin_api::{auth, config::DEFAULT_MAX_BODY_BYTES, LedgerConfig};
+
+/// Serves `GET /handshake` and `POST /chain/block` behind the network
+/// secret check, with `secret` configured.
+async fn guarded_peer(secret: Option<&str>) -> SocketAddr {
+  let secret: Option<Arc<str>> = secret.map(Arc::from);
+  serve(
+    Router::new()
+      .route("/handshake", get(|| async { "hello" }))
+      .route("/chain/block", post(|body: String| async move { body }))
+      .route_layer(middleware::from_fn_with_state(
+        (secret, DEFAULT_MAX_BODY_BYTES),
+        auth::require_network_secret,
+      )),
+  )
+  .await
+}
+
+/// Sends `request`, signed with `secret` if there is one, and returns the
+/// status and the body read as JSON, or `Null` if it isn't JSON.
+async fn send(request: reqwest::RequestBuilder, secret: Option<&str>) -> (StatusCode, Value) {
+  let response = auth::send_signed(request, secret).await.unwrap();
+  let status = response.status();
+  (status, response.json().await.unwrap_or(Value::Null))
+}
+
+#[tokio::test]
+async fn requests_signed_with_the_network_secret_are_accepted() {
+  let peer = guarded_peer(Some("network secret")).await;
+  let client = reqwest::Client::new();
+
+  let (status, _) = send(
+    client.get(format!("http://{peer}/handshake")),
+    Some("network secret"),
+  )
+  .await;
+  assert_eq!(status, StatusCode::OK);
+
+  let (status, echoed) = send(
+    client
+      .post(format!("http://{peer}/chain/block"))
+      .json(&json!({ "block": "signed" })),
+    Some("network secret"),
+  )
+  .await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(echoed, json!({ "block": "signed" }));
+}
+
+#[tokio::test]
+async fn unsigned_requests_are_rejected() {
+  let peer = guarded_peer(Some("network secret")).await;
+
+  let (status, body) = send(
+    reqwest::Client::new().get(format!("http://{peer}/handshake")),
+    None,
+  )
+  .await;
+
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+  assert_eq!(body["code"], "missing_signature");
+}
+
+#[tokio::test]
+async fn requests_signed_with_another_secret_are_rejected() {
+  let peer = guarded_peer(Some("network secret")).await;
+
+  let (status, body) = send(
+    reqwest::Client::new().get(format!("http://{peer}/handshake")),
+    Some("some other secret"),
+  )
+  .await;
+
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+  assert_eq!(body["code"], "invalid_signature");
+}
+
+/// Starts a node configured with `secret` as its network secret.
+async fn node_with_secret(secret: &str) -> TestNode {
+  let config = LedgerConfig {
+    network_secret: Some(secret.to_string()),
+    ..test_config()
+  };
+  TestNode::start(listener().await, HashSet::new(), config).await
+}
+
+#[tokio::test]
+async fn a_node_accepts_peer_requests_signed_with_its_configured_secret() {
+  let node = node_with_secret("network secret").await;
+
+  let (status, handshake) = send(
+    reqwest::Client::new().get(format!("http://{}/handshake", node.addr)),
+    Some("network secret"),
+  )
+  .await;
+
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(handshake["height"], 1);
+}
+
+#[tokio::test]
+async fn a_node_rejects_peer_requests_not_signed_with_its_configured_secret() {
+  let node = node_with_secret("network secret").await;
+  let client = reqwest::Client::new();
+
+  let (status, body) = send(client.get(format!("http://{}/handshake", node.addr)), None).await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+  assert_eq!(body["code"], "missing_signature");
+
+  let (status, body) = send(
+    client.get(format!("http://{}/handshake", node.addr)),
+    Some("some other secret"),
+  )
+  .await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+  assert_eq!(body["code"], "invalid_signature");
+}
+
+#[tokio::test]
+async fn without_a_secret_the_network_is_open() {
+  let peer = guarded_peer(None).await;
+
+  let (status, _) = send(
+    reqwest::Client::new().get(format!("http://{peer}/handshake")),
+    None,
+  )
+  .await;
+
+  assert_eq!(status, StatusCode::OK);
+}
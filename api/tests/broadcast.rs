@@ -0,0 +1,94 @@
+//! Blocks a node pushes to its peers.
+
+mod common;
+
+use std::{
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+  },
+  time::Duration,
+};
+
+use axum::{http::StatusCode, routing::post, Json, Router};
+use common::{keypair, serve, tip_hash, TestNode};
+use serde_json::{json, Value};
+
+#[tokio::test]
+async fn a_peer_that_fails_once_still_receives_the_broadcast() {
+  let attempts = Arc::new(AtomicUsize::new(0));
+  let received = Arc::new(Mutex::new(None));
+  let (counted, delivered) = (attempts.clone(), received.clone());
+  let peer = serve(Router::new().route(
+    "/chain/block",
+    post(move |Json(body): Json<Value>| {
+      // Unavailable the first time, then takes the block
+      let status = if counted.fetch_add(1, Ordering::SeqCst) == 0 {
+        StatusCode::SERVICE_UNAVAILABLE
+      } else {
+        *delivered.lock().unwrap() = body["block"]["hash"].as_str().map(String::from);
+        StatusCode::OK
+      };
+      async move { status }
+    }),
+  ))
+  .await;
+  let node = TestNode::standalone().await;
+  assert!(node.node.writer.add_peer(peer).await);
+  let (alice, _) = keypair(1);
+  let (_, bob_public) = keypair(2);
+
+  let (status, _) = node.send(&alice, &bob_public, 10).await;
+  assert_eq!(status, StatusCode::OK);
+
+  tokio::time::timeout(Duration::from_secs(5), async {
+    while received.lock().unwrap().is_none() {
+      tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+  })
+  .await
+  .expect("peer never received the block");
+  assert_eq!(attempts.load(Ordering::SeqCst), 2);
+  assert_eq!(
+    received.lock().unwrap().as_deref(),
+    Some(tip_hash(&node.chain()).as_str())
+  );
+}
+
+#[tokio::test]
+async fn a_block_received_twice_is_passed_on_once() {
+  let relayed = Arc::new(AtomicUsize::new(0));
+  let counted = relayed.clone();
+  let peer = serve(Router::new().route(
+    "/chain/block",
+    post(move || {
+      counted.fetch_add(1, Ordering::SeqCst);
+      async { StatusCode::OK }
+    }),
+  ))
+  .await;
+  let source = TestNode::standalone().await;
+  let (alice, _) = keypair(1);
+  let (_, bob_public) = keypair(2);
+  let (status, _) = source.send(&alice, &bob_public, 10).await;
+  assert_eq!(status, StatusCode::OK);
+  let block = json!({ "block": source.chain().block_at(1).unwrap() });
+  let node = TestNode::standalone().await;
+  assert!(node.node.writer.add_peer(peer).await);
+
+  for _ in 0..2 {
+    let (status, _) = node.post("/chain/block", block.clone()).await;
+    assert_eq!(status, StatusCode::OK);
+  }
+
+  tokio::time::timeout(Duration::from_secs(5), async {
+    while relayed.load(Ordering::SeqCst) == 0 {
+      tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+  })
+  .await
+  .expect("the block was never passed on");
+  // Give a second broadcast time to arrive, were there one
+  tokio::time::sleep(Duration::from_millis(300)).await;
+  assert_eq!(relayed.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,333 @@
+//! The routes serving the chain and what's in it.
+
+mod common;
+
+use axum::{
+  body::Body,
+  http::{header, Request, StatusCode},
+};
+use common::{keypair, tip_hash, TestNode};
+use serde_json::{json, Value};
+use tanishqoin_api::{merkle, wire::WireFormat, Blockchain, Transaction, DEFAULT_CHAIN_ID};
+
+#[tokio::test]
+async fn a_sent_transaction_can_be_looked_up_by_signature() {
+  let node = TestNode::standalone().await;
+  let (alice, alice_public) = keypair(1);
+  let (_, bob_public) = keypair(2);
+  // Signatures are deterministic, so this is the transaction the node signs
+  let transaction = Transaction::new(&bob_public, &alice, 10, DEFAULT_CHAIN_ID).unwrap();
+  let (status, _) = node.send(&alice, &bob_public, 10).await;
+  assert_eq!(status, StatusCode::OK);
+
+  let (status, found) = node
+    .get(&format!("/chain/tx/{}", transaction.signature()))
+    .await;
+
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(found["height"], 1);
+  assert_eq!(found["transaction"]["from"], alice_public.to_string());
+  assert_eq!(found["transaction"]["to"], bob_public.to_string());
+  assert_eq!(found["transaction"]["amount"], 10);
+}
+
+#[tokio::test]
+async fn looking_up_an_unknown_signature_is_not_found() {
+  let node = TestNode::standalone().await;
+
+  let (status, body) = node.get("/chain/tx/not-a-signature").await;
+
+  assert_eq!(status, StatusCode::NOT_FOUND);
+  assert_eq!(body["code"], "transaction_not_found");
+}
+
+/// A node whose chain is three blocks high.
+async fn three_block_node() -> TestNode {
+  let node = TestNode::standalone().await;
+  let (alice, _) = keypair(1);
+  let (_, bob_public) = keypair(2);
+  for amount in [10, 20] {
+    let (status, _) = node.send(&alice, &bob_public, amount).await;
+    assert_eq!(status, StatusCode::OK);
+  }
+  node
+}
+
+/// The hashes of the blocks in a range response.
+fn hashes(blocks: &Value) -> Vec<&str> {
+  blocks
+    .as_array()
+    .expect("a range is a list of blocks")
+    .iter()
+    .map(|block| block["hash"].as_str().expect("blocks have a hash"))
+    .collect()
+}
+
+#[tokio::test]
+async fn a_range_returns_the_blocks_between_two_heights() {
+  let node = three_block_node().await;
+  let chain = node.chain();
+
+  let (status, blocks) = node.get("/chain/range?from=1&to=3").await;
+
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(
+    hashes(&blocks),
+    [
+      chain.block_at(1).unwrap().hash(),
+      chain.block_at(2).unwrap().hash()
+    ]
+  );
+}
+
+#[tokio::test]
+async fn a_range_is_clamped_to_the_chain() {
+  let node = three_block_node().await;
+  let chain = node.chain();
+
+  let (status, past_the_tip) = node.get("/chain/range?from=2&to=100").await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(hashes(&past_the_tip), [chain.block_at(2).unwrap().hash()]);
+
+  let (status, beyond) = node.get("/chain/range?from=10&to=20").await;
+  assert_eq!(status, StatusCode::OK);
+  assert!(hashes(&beyond).is_empty());
+}
+
+#[tokio::test]
+async fn height_counts_the_blocks_mined() {
+  let node = TestNode::standalone().await;
+  let (status, fresh) = node.get("/chain/height").await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(fresh, json!({ "height": 1 }));
+
+  let (alice, _) = keypair(1);
+  let (_, bob_public) = keypair(2);
+  for amount in [10, 20, 30] {
+    let (status, _) = node.send(&alice, &bob_public, amount).await;
+    assert_eq!(status, StatusCode::OK);
+  }
+
+  let (status, height) = node.get("/chain/height").await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(height, json!({ "height": 4 }));
+  assert_eq!(node.chain().height(), 4);
+}
+
+#[tokio::test]
+async fn order_desc_lists_the_tip_first() {
+  let node = three_block_node().await;
+  let newest_first: Vec<String> = node
+    .chain()
+    .iter()
+    .rev()
+    .map(|block| block.hash().to_string())
+    .collect();
+
+  let (status, blockchain) = node.get("/chain?order=desc").await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(hashes(&blockchain["chain"]), newest_first);
+
+  let (status, range) = node.get("/chain/range?from=1&to=3&order=desc").await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(hashes(&range), newest_first[..2]);
+}
+
+#[tokio::test]
+async fn blocks_can_be_read_through_their_accessors() {
+  let node = three_block_node().await;
+  let chain = node.chain();
+  let (_, blocks) = node.get("/chain/range?from=0&to=3").await;
+
+  let read: Vec<&str> = chain.iter().map(|block| block.hash()).collect();
+
+  assert_eq!(read, hashes(&blocks));
+  for (parent, block) in chain.iter().zip(chain.iter().skip(1)) {
+    assert_eq!(block.prev_hash(), parent.hash());
+    assert!(block.timestamp() >= parent.timestamp());
+    assert_eq!(block.transactions().len(), 1);
+  }
+}
+
+#[tokio::test]
+async fn iterating_a_chain_yields_len_blocks() {
+  let node = three_block_node().await;
+  let chain = node.chain();
+
+  let mut count = 0;
+  for _ in &chain {
+    count += 1;
+  }
+
+  assert_eq!(count, chain.len());
+  assert_eq!(chain.iter().count(), 3);
+  assert!(!chain.is_empty());
+}
+
+#[tokio::test]
+async fn a_transaction_with_two_blocks_on_top_has_three_confirmations() {
+  let node = three_block_node().await;
+  let (alice, _) = keypair(1);
+  let (_, bob_public) = keypair(2);
+  let (status, _) = node.send(&alice, &bob_public, 30).await;
+  assert_eq!(status, StatusCode::OK);
+  // The first payment, now under two more blocks
+  let transaction = Transaction::new(&bob_public, &alice, 10, DEFAULT_CHAIN_ID).unwrap();
+
+  let (status, body) = node
+    .get(&format!(
+      "/chain/tx/{}/confirmations",
+      transaction.signature()
+    ))
+    .await;
+
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(body["confirmations"], 3);
+}
+
+#[tokio::test]
+async fn confirmations_of_an_unknown_transaction_are_not_found() {
+  let node = TestNode::standalone().await;
+
+  let (status, _) = node.get("/chain/tx/not-a-signature/confirmations").await;
+
+  assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn a_block_that_doesnt_build_on_the_tip_is_a_conflict() {
+  let source = three_block_node().await;
+  let node = TestNode::standalone().await;
+  let block = source.chain().block_at(2).unwrap().clone();
+
+  let (status, body) = node.post("/chain/block", json!({ "block": block })).await;
+
+  assert_eq!(status, StatusCode::CONFLICT);
+  assert_eq!(body["code"], "block_does_not_connect");
+  assert_eq!(node.chain().height(), 1);
+}
+
+#[tokio::test]
+async fn an_invalid_block_is_a_bad_request() {
+  let source = three_block_node().await;
+  let node = TestNode::standalone().await;
+  let mut block = json!({ "block": source.chain().block_at(1).unwrap() });
+  let nonce = block["block"]["nonce"].as_u64().unwrap();
+  block["block"]["nonce"] = json!(nonce + 1);
+
+  let (status, body) = node.post("/chain/block", block).await;
+
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+  assert_eq!(body["code"], "invalid_block");
+  assert_eq!(node.chain().height(), 1);
+}
+
+#[tokio::test]
+async fn a_block_building_on_the_tip_is_appended() {
+  let source = three_block_node().await;
+  let node = TestNode::standalone().await;
+  let block = source.chain().block_at(1).unwrap().clone();
+
+  let (status, _) = node.post("/chain/block", json!({ "block": block })).await;
+
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(tip_hash(&node.chain()), block.hash());
+}
+
+#[tokio::test]
+async fn a_run_of_blocks_building_on_the_tip_is_appended() {
+  let source = three_block_node().await;
+  let node = TestNode::standalone().await;
+  let blocks: Vec<_> = source.chain().iter().skip(1).cloned().collect();
+
+  let (status, _) = node
+    .post("/chain/blocks", json!({ "blocks": blocks }))
+    .await;
+
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(tip_hash(&node.chain()), tip_hash(&source.chain()));
+}
+
+#[tokio::test]
+async fn a_run_of_blocks_broken_midway_appends_nothing() {
+  let source = three_block_node().await;
+  let fork = TestNode::standalone().await;
+  let (alice, _) = keypair(1);
+  let (_, bob_public) = keypair(2);
+  for amount in [30, 40] {
+    let (status, _) = fork.send(&alice, &bob_public, amount).await;
+    assert_eq!(status, StatusCode::OK);
+  }
+  let node = TestNode::standalone().await;
+  // The fork's second block builds on the fork's first, not the source's
+  let blocks = [
+    source.chain().block_at(1).unwrap().clone(),
+    fork.chain().block_at(2).unwrap().clone(),
+  ];
+
+  let (status, body) = node
+    .post("/chain/blocks", json!({ "blocks": blocks }))
+    .await;
+
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+  assert_eq!(body["code"], "invalid_block");
+  assert_eq!(node.chain().height(), 1);
+}
+
+/// `GET /chain` in `format`, decoded.
+async fn chain_as(node: &TestNode, format: WireFormat) -> Blockchain {
+  let request = Request::get("/chain")
+    .header(header::ACCEPT, format.accept_header())
+    .body(Body::empty())
+    .unwrap();
+  let (status, bytes) = node.raw_request(request).await;
+  assert_eq!(status, StatusCode::OK);
+  format
+    .decode(&bytes)
+    .expect("body is in the format asked for")
+}
+
+#[tokio::test]
+async fn a_chain_reads_the_same_as_msgpack_and_json() {
+  let node = three_block_node().await;
+
+  let msgpack = chain_as(&node, WireFormat::MsgPack).await;
+  let json = chain_as(&node, WireFormat::Json).await;
+
+  assert_eq!(msgpack, json);
+  assert_eq!(msgpack, node.chain());
+  let encoded = WireFormat::MsgPack.encode(&msgpack).unwrap();
+  assert_eq!(
+    WireFormat::MsgPack.decode::<Blockchain>(&encoded).unwrap(),
+    json
+  );
+}
+
+#[tokio::test]
+async fn a_served_merkle_proof_verifies_against_the_block_s_root() {
+  let node = TestNode::standalone().await;
+  let (alice, _) = keypair(1);
+  let (_, bob_public) = keypair(2);
+  let transaction = Transaction::new(&bob_public, &alice, 10, DEFAULT_CHAIN_ID).unwrap();
+  let (status, _) = node.send(&alice, &bob_public, 10).await;
+  assert_eq!(status, StatusCode::OK);
+
+  let (status, served) = node
+    .get(&format!("/chain/tx/{}/proof", transaction.signature()))
+    .await;
+
+  assert_eq!(status, StatusCode::OK);
+  let proof: Vec<String> = serde_json::from_value(served["proof"].clone()).unwrap();
+  let root = served["merkle_root"].as_str().unwrap();
+  let index = served["index"].as_u64().unwrap() as usize;
+  assert_eq!(root, node.chain().block_at(1).unwrap().merkle_root());
+  assert!(merkle::verify_merkle_proof(
+    &transaction,
+    index,
+    &proof,
+    root
+  ));
+  // A payment that was never mined doesn't verify with the same proof
+  let unmined = Transaction::new(&bob_public, &alice, 11, DEFAULT_CHAIN_ID).unwrap();
+  assert!(!merkle::verify_merkle_proof(&unmined, index, &proof, root));
+}
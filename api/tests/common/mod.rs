@@ -0,0 +1,224 @@
+//! Runs nodes in-process for the integration tests. Each node serves the
+//! real router on an ephemeral loopback port, so nodes reach each other over
+//! HTTP exactly as they would across machines, while tests drive them
+//! directly through `TestNode`.
+
+// Each test binary only uses some of these helpers
+#![allow(dead_code)]
+
+use std::{
+  collections::HashSet,
+  net::{Ipv4Addr, SocketAddr},
+};
+
+use axum::{
+  body::{self, Body, Bytes},
+  extract::connect_info::MockConnectInfo,
+  http::{header, Method, Request, StatusCode},
+  Router,
+};
+use secp256k1::{PublicKey, SecretKey};
+use serde_json::{json, Value};
+use tanishqoin_api::{
+  app::{self, build_app, Node},
+  generate_keypair_from_seed,
+  metrics::Metrics,
+  Blockchain, Ledger, LedgerConfig, SyncSummary,
+};
+use tokio::net::TcpListener;
+use tower::ServiceExt;
+
+/// Few enough bits that every block mines in well under a millisecond.
+pub const TEST_DIFFICULTY: u32 = 2;
+
+pub struct TestNode {
+  pub addr: SocketAddr,
+  pub node: Node,
+}
+
+/// The config every test node starts from.
+pub fn test_config() -> LedgerConfig {
+  LedgerConfig {
+    difficulty: TEST_DIFFICULTY,
+    ..LedgerConfig::default()
+  }
+}
+
+/// The same keypair every run for the same `seed`.
+pub fn keypair(seed: u8) -> (SecretKey, PublicKey) {
+  generate_keypair_from_seed([seed; 32]).expect("seed is a valid secret key")
+}
+
+/// Binds a listener on an ephemeral loopback port.
+pub async fn listener() -> TcpListener {
+  TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+    .await
+    .expect("failed to bind an ephemeral port")
+}
+
+impl TestNode {
+  /// Starts a node with `config` that knows `peers`, serving on `listener`.
+  pub async fn start(
+    listener: TcpListener,
+    peers: HashSet<SocketAddr>,
+    config: LedgerConfig,
+  ) -> Self {
+    let addr = listener.local_addr().expect("listener has an address");
+    let ledger = Ledger::new(peers, addr, config).expect("failed to create ledger");
+    TestNode::serve(listener, ledger)
+  }
+  /// Starts a node around `ledger`, which must be for `listener`'s address,
+  /// serving on `listener`.
+  pub fn serve(listener: TcpListener, ledger: Ledger) -> Self {
+    let addr = listener.local_addr().expect("listener has an address");
+    let node = TestNode::wrap(ledger);
+    let app = build_app(node.clone()).into_make_service_with_connect_info::<SocketAddr>();
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    TestNode { addr, node }
+  }
+  /// Starts a node with the test config and no peers.
+  pub async fn standalone() -> Self {
+    TestNode::start(listener().await, HashSet::new(), test_config()).await
+  }
+  /// A node around `ledger` with its own writer, as `main` builds one.
+  pub fn wrap(ledger: Ledger) -> Node {
+    Node::new(ledger, 16)
+  }
+  /// Sends a request straight to the node's router, as a client on this
+  /// machine, and returns the status and the body read as JSON, or `Null`
+  /// if it isn't JSON.
+  pub async fn request(
+    &self,
+    method: Method,
+    uri: &str,
+    body: Option<Value>,
+  ) -> (StatusCode, Value) {
+    let request = Request::builder().method(method).uri(uri);
+    let request = match body {
+      Some(body) => request
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string())),
+      None => request.body(Body::empty()),
+    }
+    .expect("request is well formed");
+    let (status, bytes) = self.raw_request(request).await;
+    (
+      status,
+      serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+    )
+  }
+  /// Sends `request` straight to the node's router, as a client on this
+  /// machine, and returns the status and the raw body.
+  pub async fn raw_request(&self, request: Request<Body>) -> (StatusCode, Bytes) {
+    let response = build_app(self.node.clone())
+      .layer(MockConnectInfo(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))))
+      .oneshot(request)
+      .await
+      .expect("router is infallible");
+    let status = response.status();
+    let bytes = body::to_bytes(response.into_body(), usize::MAX)
+      .await
+      .expect("failed to read response body");
+    (status, bytes)
+  }
+  /// `GET`s `uri` and returns the body as text.
+  pub async fn get_text(&self, uri: &str) -> (StatusCode, String) {
+    let request = Request::get(uri)
+      .body(Body::empty())
+      .expect("request is well formed");
+    let (status, bytes) = self.raw_request(request).await;
+    (status, String::from_utf8_lossy(&bytes).into_owned())
+  }
+  pub async fn get(&self, uri: &str) -> (StatusCode, Value) {
+    self.request(Method::GET, uri, None).await
+  }
+  pub async fn post(&self, uri: &str, body: Value) -> (StatusCode, Value) {
+    self.request(Method::POST, uri, Some(body)).await
+  }
+  /// Pays `amount` from `from` to `to` through `POST /wallet/send`.
+  pub async fn send(&self, from: &SecretKey, to: &PublicKey, amount: u64) -> (StatusCode, Value) {
+    self.post("/wallet/send", payment(from, to, amount)).await
+  }
+  /// Adds `peer` to the node's peers, as a sync would on hearing of it.
+  pub async fn add_peer(&self, peer: &TestNode) {
+    assert!(self.node.writer.add_peer(peer.addr).await);
+  }
+  /// Runs one sync round against the node's peers.
+  pub async fn sync(&self) -> Option<SyncSummary> {
+    app::sync(&self.node).await
+  }
+  pub fn chain(&self) -> Blockchain {
+    self.node.chain.get().chain().clone()
+  }
+  pub fn peers(&self) -> HashSet<SocketAddr> {
+    self.node.chain.get().get_peers()
+  }
+  pub fn metrics(&self) -> Metrics {
+    self.node.chain.get().metrics().clone()
+  }
+}
+
+/// Serves `router` on an ephemeral loopback port, standing in for a peer
+/// that behaves however a test needs.
+pub async fn serve(router: Router) -> SocketAddr {
+  let listener = listener().await;
+  let addr = listener.local_addr().expect("listener has an address");
+  tokio::spawn(async move { axum::serve(listener, router).await });
+  addr
+}
+
+/// The body of a payment of `amount` from `from` to `to`, as the wallet
+/// payment routes take it.
+pub fn payment(from: &SecretKey, to: &PublicKey, amount: u64) -> Value {
+  json!({
+    "to_public_key": to.to_string(),
+    "from_secret_key": from.display_secret().to_string(),
+    "amount": amount,
+  })
+}
+
+/// Starts `size` nodes that each know every other node as a peer.
+pub async fn spawn_network(size: usize) -> Vec<TestNode> {
+  let mut listeners = Vec::with_capacity(size);
+  for _ in 0..size {
+    listeners.push(listener().await);
+  }
+  let addrs: Vec<SocketAddr> = listeners
+    .iter()
+    .map(|listener| listener.local_addr().expect("listener has an address"))
+    .collect();
+  let mut nodes = Vec::with_capacity(size);
+  for listener in listeners {
+    let addr = listener.local_addr().expect("listener has an address");
+    let peers = addrs.iter().copied().filter(|peer| *peer != addr).collect();
+    nodes.push(TestNode::start(listener, peers, test_config()).await);
+  }
+  nodes
+}
+
+/// Syncs every node in turn, `rounds` times over.
+pub async fn sync_all(nodes: &[TestNode], rounds: usize) {
+  for _ in 0..rounds {
+    for node in nodes {
+      node.sync().await;
+    }
+  }
+}
+
+/// The hash of `blockchain`'s last block.
+pub fn tip_hash(blockchain: &Blockchain) -> String {
+  blockchain
+    .block_at(blockchain.height() - 1)
+    .expect("the tip is never pruned")
+    .hash()
+    .to_string()
+}
+
+/// Whether every node has the same tip at the same height.
+pub fn converged(nodes: &[TestNode]) -> bool {
+  let first = nodes[0].chain();
+  nodes.iter().all(|node| {
+    let chain = node.chain();
+    chain.height() == first.height() && tip_hash(&chain) == tip_hash(&first)
+  })
+}
@@ -0,0 +1,72 @@
+//! Nodes paid through different peers end up on the same chain.
+
+mod common;
+
+use axum::http::StatusCode;
+use common::{converged, keypair, spawn_network, sync_all, TestNode};
+
+#[tokio::test]
+async fn nodes_converge_on_payments_made_through_different_peers() {
+  let nodes = spawn_network(3).await;
+  let (alice, alice_public) = keypair(1);
+  let (bob, bob_public) = keypair(2);
+
+  let payments = [
+    (&alice, &bob_public, 10),
+    (&bob, &alice_public, 5),
+    (&alice, &bob_public, 1),
+  ];
+  for (node, (from, to, amount)) in nodes.iter().zip(payments) {
+    let (status, _) = node.send(from, to, amount).await;
+    assert_eq!(status, StatusCode::OK);
+    sync_all(&nodes, 1).await;
+  }
+  sync_all(&nodes, 2).await;
+
+  assert!(converged(&nodes));
+  assert_eq!(nodes[0].chain().height(), 4);
+  for node in &nodes {
+    let (status, balance) = node.get(&format!("/wallet/balance/{}", bob_public)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(balance["balance"], 106);
+  }
+}
+
+#[tokio::test]
+async fn forked_nodes_agree_on_one_chain() {
+  let nodes = [TestNode::standalone().await, TestNode::standalone().await];
+  let (alice, _) = keypair(1);
+  let (_, bob_public) = keypair(2);
+  // Unaware of each other, both mine a block onto genesis
+  for (node, amount) in nodes.iter().zip([10, 20]) {
+    let (status, _) = node.send(&alice, &bob_public, amount).await;
+    assert_eq!(status, StatusCode::OK);
+  }
+  assert!(!converged(&nodes));
+
+  nodes[0].add_peer(&nodes[1]).await;
+  nodes[1].add_peer(&nodes[0]).await;
+  sync_all(&nodes, 2).await;
+
+  assert!(converged(&nodes));
+  assert_eq!(nodes[0].chain().height(), 2);
+}
+
+#[tokio::test]
+async fn a_node_that_falls_behind_catches_up() {
+  let nodes = [TestNode::standalone().await, TestNode::standalone().await];
+  let (alice, _) = keypair(1);
+  let (_, bob_public) = keypair(2);
+  for _ in 0..3 {
+    let (status, _) = nodes[0].send(&alice, &bob_public, 1).await;
+    assert_eq!(status, StatusCode::OK);
+  }
+  assert_eq!(nodes[1].chain().height(), 1);
+
+  nodes[1].add_peer(&nodes[0]).await;
+  let summary = nodes[1].sync().await.expect("node isn't frozen");
+
+  assert!(summary.chain_changed);
+  assert!(converged(&nodes));
+  assert_eq!(nodes[1].chain().height(), 4);
+}
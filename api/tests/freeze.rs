@@ -0,0 +1,99 @@
+//! Freezing a node with `POST /admin/freeze` while its chain is backed up.
+
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{keypair, payment, TestNode};
+use serde_json::json;
+
+/// Freezes `node` as a local operator would.
+async fn freeze(node: &TestNode) {
+  let (status, body) = node.post("/admin/freeze", json!({})).await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(body["frozen"], true);
+}
+
+#[tokio::test]
+async fn mutating_routes_are_unavailable_while_frozen() {
+  let node = TestNode::standalone().await;
+  let source = TestNode::standalone().await;
+  let (alice, _) = keypair(1);
+  let (_, bob_public) = keypair(2);
+  let (status, _) = source.send(&alice, &bob_public, 10).await;
+  assert_eq!(status, StatusCode::OK);
+  let block = source.chain().block_at(1).unwrap().clone();
+  freeze(&node).await;
+
+  for (method, uri, body) in [
+    (
+      Method::POST,
+      "/wallet/send",
+      payment(&alice, &bob_public, 20),
+    ),
+    (
+      Method::POST,
+      "/wallet/submit",
+      payment(&alice, &bob_public, 20),
+    ),
+    (
+      Method::POST,
+      "/wallet/send-batch",
+      json!({
+        "from_secret_key": alice.display_secret().to_string(),
+        "recipients": [{ "to_public_key": bob_public.to_string(), "amount": 20 }],
+      }),
+    ),
+    (
+      Method::PATCH,
+      "/chain",
+      json!({ "blockchain": source.chain() }),
+    ),
+    (Method::POST, "/chain/block", json!({ "block": block })),
+    (Method::POST, "/chain/blocks", json!({ "blocks": [block] })),
+    (Method::POST, "/sync", json!({})),
+  ] {
+    let (status, response) = node.request(method, uri, Some(body)).await;
+
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE, "{uri}");
+    assert_eq!(response["code"], "frozen", "{uri}");
+  }
+  assert_eq!(node.chain().height(), 1);
+}
+
+#[tokio::test]
+async fn sync_applies_nothing_while_frozen() {
+  let node = TestNode::standalone().await;
+  let source = TestNode::standalone().await;
+  let (alice, _) = keypair(1);
+  let (_, bob_public) = keypair(2);
+  let (status, _) = source.send(&alice, &bob_public, 10).await;
+  assert_eq!(status, StatusCode::OK);
+  node.add_peer(&source).await;
+  freeze(&node).await;
+
+  assert_eq!(node.sync().await, None);
+  assert_eq!(node.chain().height(), 1);
+}
+
+#[tokio::test]
+async fn unfreezing_lets_payments_through_again() {
+  let node = TestNode::standalone().await;
+  let (alice, _) = keypair(1);
+  let (_, bob_public) = keypair(2);
+  freeze(&node).await;
+
+  let (status, body) = node.post("/admin/unfreeze", json!({})).await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(body["frozen"], false);
+
+  let (status, _) = node.send(&alice, &bob_public, 10).await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(node.chain().height(), 2);
+}
+
+#[tokio::test]
+async fn a_node_starts_unfrozen() {
+  let node = TestNode::standalone().await;
+
+  assert!(!node.node.is_frozen());
+}
@@ -0,0 +1,33 @@
+//! The Prometheus metrics a node exports.
+
+mod common;
+
+use axum::http::StatusCode;
+use common::{keypair, TestNode};
+
+/// The value `metrics` gives the metric `name`.
+fn metric(metrics: &str, name: &str) -> f64 {
+  metrics
+    .lines()
+    .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+    .unwrap_or_else(|| panic!("no {name} metric in {metrics}"))
+    .parse()
+    .unwrap()
+}
+
+#[tokio::test]
+async fn mining_a_block_increments_blocks_mined() {
+  let node = TestNode::standalone().await;
+  let (alice, _) = keypair(1);
+  let (_, bob_public) = keypair(2);
+  let (status, before) = node.get_text("/metrics").await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(metric(&before, "silocoin_blocks_mined_total"), 0.0);
+
+  let (status, _) = node.send(&alice, &bob_public, 10).await;
+  assert_eq!(status, StatusCode::OK);
+
+  let (_, after) = node.get_text("/metrics").await;
+  assert_eq!(metric(&after, "silocoin_blocks_mined_total"), 1.0);
+  assert_eq!(metric(&after, "silocoin_chain_height"), 2.0);
+}
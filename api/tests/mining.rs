@@ -0,0 +1,55 @@
+//! Nodes mining the mempool on their own, as `--mine` runs them.
+
+mod common;
+
+use std::time::Duration;
+
+use axum::http::StatusCode;
+use common::{keypair, payment, TestNode};
+use tanishqoin_api::app;
+
+#[tokio::test]
+async fn the_interval_miner_mines_submitted_transactions() {
+  let node = TestNode::standalone().await;
+  let (alice, _) = keypair(1);
+  let (_, bob_public) = keypair(2);
+  let (status, submitted) = node
+    .post("/wallet/submit", payment(&alice, &bob_public, 10))
+    .await;
+  assert_eq!(status, StatusCode::ACCEPTED);
+  assert_eq!(node.chain().height(), 1);
+
+  tokio::spawn(app::mine_on_interval(
+    node.node.clone(),
+    Duration::from_millis(50),
+  ));
+
+  tokio::time::timeout(Duration::from_secs(5), async {
+    while node.chain().height() < 2 {
+      tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+  })
+  .await
+  .expect("the submitted transaction was never mined");
+  let signature = submitted["signature"].as_str().unwrap();
+  assert_eq!(
+    node
+      .chain()
+      .find_transaction(signature)
+      .map(|(height, _)| height),
+    Some(1)
+  );
+}
+
+#[tokio::test]
+async fn the_interval_miner_skips_an_empty_mempool() {
+  let node = TestNode::standalone().await;
+
+  tokio::spawn(app::mine_on_interval(
+    node.node.clone(),
+    Duration::from_millis(50),
+  ));
+  tokio::time::sleep(Duration::from_millis(300)).await;
+
+  assert_eq!(node.chain().height(), 1);
+}
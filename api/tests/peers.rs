@@ -0,0 +1,150 @@
+//! Peers announcing themselves to a node.
+
+mod common;
+
+use std::{collections::HashSet, net::Ipv6Addr, time::Duration};
+
+use axum::http::StatusCode;
+use common::{keypair, listener, test_config, TestNode};
+use secp256k1::SecretKey;
+use tanishqoin_api::{Ledger, LedgerConfig, PeerAnnouncement, DEFAULT_CHAIN_ID};
+use tokio::net::TcpListener;
+
+/// A node that checks announced addresses answer before adding them.
+async fn verifying_node() -> TestNode {
+  let config = LedgerConfig {
+    verify_peers: true,
+    ..test_config()
+  };
+  TestNode::start(listener().await, HashSet::new(), config).await
+}
+
+#[tokio::test]
+async fn an_unreachable_announced_address_is_not_added() {
+  let node = verifying_node().await;
+  // Nothing listens on the port once its listener is dropped
+  let addr = listener().await.local_addr().unwrap();
+  let announcement = PeerAnnouncement::new(
+    addr,
+    1,
+    &SecretKey::from_slice(&[3; 32]).unwrap(),
+    DEFAULT_CHAIN_ID,
+  );
+
+  let (status, _) = node
+    .post(
+      &format!("/peers/{addr}"),
+      serde_json::to_value(&announcement).unwrap(),
+    )
+    .await;
+  assert_eq!(status, StatusCode::ACCEPTED);
+  tokio::time::sleep(Duration::from_millis(500)).await;
+
+  assert!(node.peers().is_empty());
+}
+
+#[tokio::test]
+async fn a_reachable_announced_address_is_added() {
+  let node = verifying_node().await;
+  let peer = TestNode::standalone().await;
+  let announcement = peer.node.chain.get().announcement();
+
+  let (status, _) = node
+    .post(
+      &format!("/peers/{}", peer.addr),
+      serde_json::to_value(&announcement).unwrap(),
+    )
+    .await;
+  assert_eq!(status, StatusCode::ACCEPTED);
+
+  tokio::time::timeout(Duration::from_secs(5), async {
+    while !node.peers().contains(&peer.addr) {
+      tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+  })
+  .await
+  .expect("peer was never added");
+}
+
+/// A node listening on the IPv6 loopback address.
+async fn ipv6_node() -> TestNode {
+  let listener = TcpListener::bind((Ipv6Addr::LOCALHOST, 0))
+    .await
+    .expect("failed to bind an IPv6 port");
+  TestNode::start(listener, HashSet::new(), test_config()).await
+}
+
+#[tokio::test]
+async fn nodes_sync_with_ipv6_peers() {
+  let node = ipv6_node().await;
+  let peer = ipv6_node().await;
+  let (alice, _) = keypair(1);
+  let (_, bob_public) = keypair(2);
+  let (status, _) = peer.send(&alice, &bob_public, 10).await;
+  assert_eq!(status, StatusCode::OK);
+  node.add_peer(&peer).await;
+
+  // Requests to the peer only reach it if its address is bracketed in URLs
+  let summary = node.sync().await.expect("node isn't frozen");
+
+  assert!(peer.addr.is_ipv6());
+  assert!(node.peers().contains(&peer.addr));
+  assert!(summary.chain_changed);
+  assert_eq!(node.chain().height(), 2);
+}
+
+#[tokio::test]
+async fn debug_peers_counts_a_failing_peer_s_failures() {
+  let node = TestNode::standalone().await;
+  let healthy = TestNode::standalone().await;
+  // Nothing listens on the port once its listener is dropped
+  let down = listener().await.local_addr().unwrap();
+  node.add_peer(&healthy).await;
+  assert!(node.node.writer.add_peer(down).await);
+
+  node.sync().await.expect("node isn't frozen");
+  let (status, health) = node.get("/debug/peers").await;
+
+  assert_eq!(status, StatusCode::OK);
+  let down = &health[down.to_string()];
+  assert_eq!(down["consecutive_failures"], 1);
+  assert!(down["last_success"].is_null());
+  let healthy = &health[healthy.addr.to_string()];
+  assert_eq!(healthy["consecutive_failures"], 0);
+  assert!(healthy["last_success"].is_u64());
+  assert_eq!(healthy["last_height"], 1);
+}
+
+#[tokio::test]
+async fn bootstrapping_fails_over_to_the_next_bootstrap() {
+  let bootstrap = TestNode::standalone().await;
+  let gossiped = TestNode::standalone().await;
+  let announcement = gossiped.node.chain.get().announcement();
+  bootstrap
+    .node
+    .writer
+    .accept_announcement(announcement)
+    .await
+    .unwrap();
+  // Nothing listens on the port once its listener is dropped
+  let down = listener().await.local_addr().unwrap();
+  let node_listener = listener().await;
+  let addr = node_listener.local_addr().unwrap();
+  let mut ledger = Ledger::new(HashSet::new(), addr, test_config()).unwrap();
+
+  // Bootstrapped before it's served, as `main` does
+  let seeded_from = ledger.bootstrap(&[down, bootstrap.addr]).await;
+  let node = TestNode::serve(node_listener, ledger);
+
+  assert_eq!(seeded_from, Some(bootstrap.addr));
+  assert_eq!(node.peers(), HashSet::from([bootstrap.addr, gossiped.addr]));
+  let (status, results) = node.get("/debug/bootstrap").await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(
+    results,
+    serde_json::json!({
+      down.to_string(): false,
+      bootstrap.addr.to_string(): true,
+    })
+  );
+}
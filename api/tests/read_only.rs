@@ -0,0 +1,96 @@
+//! Nodes run with `--read-only`, which serve queries but take no changes.
+
+mod common;
+
+use std::collections::HashSet;
+
+use axum::http::{Method, StatusCode};
+use common::{keypair, listener, payment, test_config, TestNode};
+use serde_json::json;
+use tanishqoin_api::LedgerConfig;
+
+async fn read_only_node() -> TestNode {
+  let config = LedgerConfig {
+    read_only: true,
+    ..test_config()
+  };
+  TestNode::start(listener().await, HashSet::new(), config).await
+}
+
+#[tokio::test]
+async fn mutating_routes_are_forbidden() {
+  let node = read_only_node().await;
+  let source = TestNode::standalone().await;
+  let (alice, _) = keypair(1);
+  let (_, bob_public) = keypair(2);
+  let (status, _) = source.send(&alice, &bob_public, 10).await;
+  assert_eq!(status, StatusCode::OK);
+  let block = source.chain().block_at(1).unwrap().clone();
+
+  for (method, uri, body) in [
+    (
+      Method::POST,
+      "/wallet/send",
+      payment(&alice, &bob_public, 20),
+    ),
+    (
+      Method::POST,
+      "/wallet/submit",
+      payment(&alice, &bob_public, 20),
+    ),
+    (
+      Method::POST,
+      "/wallet/send-batch",
+      json!({
+        "from_secret_key": alice.display_secret().to_string(),
+        "recipients": [{ "to_public_key": bob_public.to_string(), "amount": 20 }],
+      }),
+    ),
+    (
+      Method::PATCH,
+      "/chain",
+      json!({ "blockchain": source.chain() }),
+    ),
+    (Method::POST, "/chain/block", json!({ "block": block })),
+    (Method::POST, "/chain/blocks", json!({ "blocks": [block] })),
+  ] {
+    let (status, response) = node.request(method, uri, Some(body)).await;
+
+    assert_eq!(status, StatusCode::FORBIDDEN, "{uri}");
+    assert_eq!(response["code"], "read_only", "{uri}");
+  }
+  assert_eq!(node.chain().height(), 1);
+}
+
+#[tokio::test]
+async fn queries_are_still_answered() {
+  let node = read_only_node().await;
+  let (_, alice_public) = keypair(1);
+
+  for uri in [
+    String::from("/"),
+    String::from("/chain"),
+    String::from("/info"),
+    format!("/wallet/balance/{alice_public}"),
+  ] {
+    let (status, _) = node.get(&uri).await;
+
+    assert_eq!(status, StatusCode::OK, "{uri}");
+  }
+}
+
+#[tokio::test]
+async fn sync_still_takes_the_network_chain() {
+  let node = read_only_node().await;
+  let source = TestNode::standalone().await;
+  let (alice, _) = keypair(1);
+  let (_, bob_public) = keypair(2);
+  let (status, _) = source.send(&alice, &bob_public, 10).await;
+  assert_eq!(status, StatusCode::OK);
+  node.add_peer(&source).await;
+
+  let summary = node.sync().await.expect("node isn't frozen");
+
+  assert!(summary.chain_changed);
+  assert_eq!(node.chain().height(), 2);
+}
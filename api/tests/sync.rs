@@ -0,0 +1,401 @@
+//! Syncing a node with its peers.
+
+mod common;
+
+use std::{
+  collections::{BTreeMap, HashMap, HashSet},
+  net::SocketAddr,
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+  },
+  time::Duration,
+};
+
+use axum::{
+  extract::{ConnectInfo, Query},
+  http::StatusCode,
+  routing::{get, post},
+  Json, Router,
+};
+use common::{keypair, listener, serve, test_config, TestNode};
+use tanishqoin_api::{app, Handshake, LedgerConfig, Order};
+
+#[tokio::test]
+async fn sync_skips_downloading_a_peer_chain_with_our_tip() {
+  let nodes = [TestNode::standalone().await, TestNode::standalone().await];
+  nodes[1].add_peer(&nodes[0]).await;
+
+  let summary = nodes[1].sync().await.expect("node isn't frozen");
+
+  assert!(!summary.chain_changed);
+  let metrics = nodes[1].metrics();
+  assert_eq!(metrics.chain_downloads(), 0);
+}
+
+#[tokio::test]
+async fn sync_downloads_a_peer_chain_with_a_different_tip() {
+  let nodes = [TestNode::standalone().await, TestNode::standalone().await];
+  let (alice, _) = keypair(1);
+  let (_, bob_public) = keypair(2);
+  let (status, _) = nodes[0].send(&alice, &bob_public, 10).await;
+  assert_eq!(status, StatusCode::OK);
+  nodes[1].add_peer(&nodes[0]).await;
+
+  let summary = nodes[1].sync().await.expect("node isn't frozen");
+
+  assert!(summary.chain_changed);
+  assert_eq!(nodes[1].metrics().chain_downloads(), 1);
+}
+
+#[tokio::test]
+async fn handshake_reports_our_height_and_tip() {
+  let node = TestNode::standalone().await;
+
+  let (status, handshake) = node.get("/handshake").await;
+
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(handshake["height"], 1);
+  assert_eq!(handshake["tip_hash"], common::tip_hash(&node.chain()));
+  assert_eq!(handshake["addr"], node.addr.to_string());
+}
+
+#[tokio::test]
+async fn sync_ignores_a_peer_with_a_different_genesis() {
+  let (alice, alice_public) = keypair(1);
+  let (_, bob_public) = keypair(2);
+  let stranger_config = LedgerConfig {
+    genesis_allocation: BTreeMap::from([(alice_public, 1_000)]),
+    ..test_config()
+  };
+  let stranger = TestNode::start(listener().await, HashSet::new(), stranger_config).await;
+  let node = TestNode::standalone().await;
+  let (status, _) = stranger.send(&alice, &bob_public, 500).await;
+  assert_eq!(status, StatusCode::OK);
+  node.add_peer(&stranger).await;
+
+  let summary = node.sync().await.expect("node isn't frozen");
+
+  assert!(!summary.chain_changed);
+  assert_eq!(node.chain().height(), 1);
+}
+
+/// Syncs `node` with a stand-in peer that answers with `source`'s handshake,
+/// changed by `tweak`, and serves `source`'s chain. Returns how many requests
+/// the node made to it beyond the handshake and its announcement.
+async fn requests_beyond_handshake(
+  node: &TestNode,
+  source: &TestNode,
+  tweak: impl FnOnce(&mut Handshake),
+) -> usize {
+  let mut handshake = source.node.chain.get().handshake();
+  tweak(&mut handshake);
+  let chain = source.chain();
+  let requests = Arc::new(AtomicUsize::new(0));
+  let counted = requests.clone();
+  let peer = serve(
+    Router::new()
+      .route(
+        "/handshake",
+        get(move || {
+          let handshake = handshake.clone();
+          async move { Json(handshake) }
+        }),
+      )
+      .route("/peers/:addr", post(|| async { StatusCode::OK }))
+      .fallback(move || {
+        counted.fetch_add(1, Ordering::SeqCst);
+        let chain = chain.clone();
+        async move { Json(chain) }
+      }),
+  )
+  .await;
+  assert!(node.node.writer.add_peer(peer).await);
+
+  node.sync().await.expect("node isn't frozen");
+
+  requests.load(Ordering::SeqCst)
+}
+
+#[tokio::test]
+async fn sync_downloads_nothing_from_a_peer_on_another_protocol_version() {
+  let (alice, _) = keypair(1);
+  let (_, bob_public) = keypair(2);
+  let node = TestNode::standalone().await;
+  let source = TestNode::standalone().await;
+  let (status, _) = source.send(&alice, &bob_public, 10).await;
+  assert_eq!(status, StatusCode::OK);
+
+  let requests =
+    requests_beyond_handshake(&node, &source, |handshake| handshake.protocol_version += 1).await;
+
+  assert_eq!(requests, 0);
+  // The same peer on our version is downloaded from
+  assert!(requests_beyond_handshake(&node, &source, |_| {}).await > 0);
+}
+
+#[tokio::test]
+async fn sync_downloads_nothing_from_peers_on_our_tip() {
+  let node = TestNode::standalone().await;
+  // A fresh node is on the same genesis block, so the same tip
+  let source = TestNode::standalone().await;
+
+  assert_eq!(requests_beyond_handshake(&node, &source, |_| {}).await, 0);
+  assert_eq!(node.metrics().chain_downloads(), 0);
+}
+
+#[tokio::test]
+async fn a_node_two_blocks_behind_downloads_only_those_two_blocks() {
+  let (alice, _) = keypair(1);
+  let (_, bob_public) = keypair(2);
+  let source = TestNode::standalone().await;
+  let (status, _) = source.send(&alice, &bob_public, 10).await;
+  assert_eq!(status, StatusCode::OK);
+  let node = TestNode::standalone().await;
+  node
+    .node
+    .writer
+    .update_blockchain(source.chain())
+    .await
+    .expect("the source chain is valid");
+  for amount in [20, 30] {
+    let (status, _) = source.send(&alice, &bob_public, amount).await;
+    assert_eq!(status, StatusCode::OK);
+  }
+  // Serves the source's chain, counting the blocks handed out in ranges and
+  // any full downloads
+  let blocks_served = Arc::new(AtomicUsize::new(0));
+  let full_downloads = Arc::new(AtomicUsize::new(0));
+  let (handshake_node, chain) = (source.node.clone(), source.node.chain.clone());
+  let (headers_chain, range_chain) = (chain.clone(), chain.clone());
+  let (served, downloaded) = (blocks_served.clone(), full_downloads.clone());
+  let peer = serve(
+    Router::new()
+      .route(
+        "/handshake",
+        get(move || {
+          let node = handshake_node.clone();
+          async move { Json(node.chain.get().handshake()) }
+        }),
+      )
+      .route(
+        "/chain/headers",
+        get(move || {
+          let headers = headers_chain.get().chain().headers();
+          async move { Json(headers) }
+        }),
+      )
+      .route(
+        "/chain/range",
+        get(move |Query(range): Query<HashMap<String, usize>>| {
+          let blocks = range_chain
+            .get()
+            .chain()
+            .range(range["from"], range["to"], Order::Asc);
+          served.fetch_add(blocks.len(), Ordering::SeqCst);
+          async move { Json(blocks) }
+        }),
+      )
+      .route(
+        "/chain",
+        get(move || {
+          downloaded.fetch_add(1, Ordering::SeqCst);
+          let blockchain = chain.get().chain().clone();
+          async move { Json(blockchain) }
+        }),
+      )
+      .route("/peers/:addr", post(|| async { StatusCode::OK })),
+  )
+  .await;
+  assert!(node.node.writer.add_peer(peer).await);
+
+  let summary = node.sync().await.expect("node isn't frozen");
+
+  assert!(summary.chain_changed);
+  assert_eq!(node.chain().height(), 4);
+  assert_eq!(blocks_served.load(Ordering::SeqCst), 2);
+  assert_eq!(full_downloads.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn balances_can_be_read_while_a_sync_waits_on_a_slow_peer() {
+  let node = TestNode::standalone().await;
+  let peer = serve(Router::new().route(
+    "/handshake",
+    get(|| async {
+      tokio::time::sleep(Duration::from_secs(2)).await;
+      StatusCode::SERVICE_UNAVAILABLE
+    }),
+  ))
+  .await;
+  assert!(node.node.writer.add_peer(peer).await);
+  let (_, alice_public) = keypair(1);
+  let syncing = tokio::spawn({
+    let node = node.node.clone();
+    async move { app::sync(&node).await }
+  });
+  tokio::time::sleep(Duration::from_millis(100)).await;
+
+  let (status, body) = tokio::time::timeout(
+    Duration::from_millis(500),
+    node.get(&format!("/wallet/balance/{alice_public}")),
+  )
+  .await
+  .expect("balance query waited on the sync");
+
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(body["balance"], 100);
+  assert!(!syncing.is_finished());
+  syncing.await.unwrap();
+}
+
+#[tokio::test]
+async fn post_sync_converges_a_behind_node_in_one_call() {
+  let nodes = [TestNode::standalone().await, TestNode::standalone().await];
+  let (alice, _) = keypair(1);
+  let (_, bob_public) = keypair(2);
+  for amount in [10, 20] {
+    let (status, _) = nodes[0].send(&alice, &bob_public, amount).await;
+    assert_eq!(status, StatusCode::OK);
+  }
+  nodes[1].add_peer(&nodes[0]).await;
+
+  let (status, summary) = nodes[1].post("/sync", serde_json::json!({})).await;
+
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(summary["peers_contacted"], 1);
+  assert_eq!(summary["chain_changed"], true);
+  assert_eq!(summary["height"], 3);
+  assert!(common::converged(&nodes));
+}
+
+#[tokio::test]
+async fn post_sync_refuses_while_a_sync_is_running() {
+  let node = TestNode::standalone().await;
+  let _running = node.node.syncing.lock().await;
+
+  let (status, body) = node.post("/sync", serde_json::json!({})).await;
+
+  assert_eq!(status, StatusCode::CONFLICT);
+  assert_eq!(body["code"], "sync_in_progress");
+}
+
+/// A node that won't roll back more than `max_reorg_depth` blocks, with two
+/// blocks of its own mined.
+async fn two_block_node(max_reorg_depth: usize) -> TestNode {
+  let config = LedgerConfig {
+    max_reorg_depth: Some(max_reorg_depth),
+    ..test_config()
+  };
+  let node = TestNode::start(listener().await, HashSet::new(), config).await;
+  let (alice, _) = keypair(1);
+  let (_, bob_public) = keypair(2);
+  for amount in [10, 20] {
+    let (status, _) = node.send(&alice, &bob_public, amount).await;
+    assert_eq!(status, StatusCode::OK);
+  }
+  node
+}
+
+/// A node with three blocks of its own mined, none shared with
+/// `two_block_node`'s.
+async fn three_block_fork() -> TestNode {
+  let node = TestNode::standalone().await;
+  let (carol, _) = keypair(3);
+  let (_, dave_public) = keypair(4);
+  for amount in [1, 2, 3] {
+    let (status, _) = node.send(&carol, &dave_public, amount).await;
+    assert_eq!(status, StatusCode::OK);
+  }
+  node
+}
+
+#[tokio::test]
+async fn sync_keeps_the_local_chain_over_a_reorg_deeper_than_allowed() {
+  let node = two_block_node(1).await;
+  let fork = three_block_fork().await;
+  let local_tip = common::tip_hash(&node.chain());
+  node.add_peer(&fork).await;
+
+  let summary = node.sync().await.expect("node isn't frozen");
+
+  assert!(!summary.chain_changed);
+  assert_eq!(node.chain().height(), 3);
+  assert_eq!(common::tip_hash(&node.chain()), local_tip);
+}
+
+#[tokio::test]
+async fn sync_takes_a_reorg_within_the_allowed_depth() {
+  let node = two_block_node(2).await;
+  let fork = three_block_fork().await;
+  node.add_peer(&fork).await;
+
+  let summary = node.sync().await.expect("node isn't frozen");
+
+  assert!(summary.chain_changed);
+  assert_eq!(
+    common::tip_hash(&node.chain()),
+    common::tip_hash(&fork.chain())
+  );
+}
+
+#[tokio::test]
+async fn repeated_syncs_reuse_one_connection_to_a_peer() {
+  let node = TestNode::standalone().await;
+  let handshake = TestNode::standalone().await.node.chain.get().handshake();
+  // The client end of every connection the peer is sent requests over
+  let connections = Arc::new(Mutex::new(HashSet::new()));
+  let (handshake_connections, announce_connections) = (connections.clone(), connections.clone());
+  let router = Router::new()
+    .route(
+      "/handshake",
+      get(move |ConnectInfo(client): ConnectInfo<SocketAddr>| {
+        handshake_connections.lock().unwrap().insert(client);
+        let handshake = handshake.clone();
+        async move { Json(handshake) }
+      }),
+    )
+    .route(
+      "/peers/:addr",
+      post(move |ConnectInfo(client): ConnectInfo<SocketAddr>| {
+        announce_connections.lock().unwrap().insert(client);
+        async { StatusCode::OK }
+      }),
+    );
+  let peer_listener = listener().await;
+  let peer = peer_listener.local_addr().unwrap();
+  tokio::spawn(async move {
+    axum::serve(
+      peer_listener,
+      router.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+  });
+  assert!(node.node.writer.add_peer(peer).await);
+
+  for _ in 0..5 {
+    node.sync().await.expect("node isn't frozen");
+  }
+
+  assert_eq!(connections.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn sync_gives_up_on_a_peer_that_never_answers() {
+  let node = TestNode::standalone().await;
+  let peer = serve(
+    Router::new()
+      .route("/handshake", get(std::future::pending::<StatusCode>))
+      .route("/peers/:addr", post(std::future::pending::<StatusCode>)),
+  )
+  .await;
+  assert!(node.node.writer.add_peer(peer).await);
+
+  // Four seconds each for the announcement and the handshake
+  let summary = tokio::time::timeout(Duration::from_secs(12), node.sync())
+    .await
+    .expect("sync waited on the peer forever")
+    .expect("node isn't frozen");
+
+  assert!(!summary.chain_changed);
+}
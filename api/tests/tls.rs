@@ -0,0 +1,85 @@
+//! Nodes serving HTTPS with `--tls-cert` and `--tls-key`.
+
+mod common;
+
+use std::{collections::HashSet, net::SocketAddr};
+
+use axum::http::StatusCode;
+use axum_server::tls_rustls::RustlsConfig;
+use common::{keypair, test_config, TestNode};
+use tanishqoin_api::{app::build_app, Ledger, LedgerConfig};
+
+/// A self-signed certificate for `127.0.0.1` and `localhost`, and its key.
+const CERT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/localhost.pem");
+const KEY: &str = concat!(
+  env!("CARGO_MANIFEST_DIR"),
+  "/tests/fixtures/localhost-key.pem"
+);
+
+/// The test config for a network of HTTPS nodes that trust `CERT`.
+fn https_config() -> LedgerConfig {
+  let cert = std::fs::read(CERT).expect("failed to read the test certificate");
+  LedgerConfig {
+    peer_tls: true,
+    peer_ca: Some(reqwest::Certificate::from_pem(&cert).expect("certificate is PEM")),
+    ..test_config()
+  }
+}
+
+/// Starts a node serving HTTPS with `CERT`, as `main` does given
+/// `--tls-cert` and `--tls-key`.
+async fn https_node() -> TestNode {
+  // Every test in this file installs it, so only the first succeeds
+  let _ = rustls::crypto::ring::default_provider().install_default();
+  let tls_config = RustlsConfig::from_pem_file(CERT, KEY)
+    .await
+    .expect("failed to load the test certificate");
+  let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("failed to bind a port");
+  let addr = listener.local_addr().expect("listener has an address");
+  let ledger = Ledger::new(HashSet::new(), addr, https_config()).expect("failed to create ledger");
+  let node = TestNode::wrap(ledger);
+  let app = build_app(node.clone()).into_make_service_with_connect_info::<SocketAddr>();
+  let server = axum_server::from_tcp_rustls(listener, tls_config);
+  tokio::spawn(async move { server.serve(app).await });
+  TestNode { addr, node }
+}
+
+#[tokio::test]
+async fn a_node_serves_requests_over_https() {
+  let node = https_node().await;
+  let cert = std::fs::read(CERT).unwrap();
+  let client = reqwest::Client::builder()
+    .add_root_certificate(reqwest::Certificate::from_pem(&cert).unwrap())
+    .build()
+    .unwrap();
+
+  let response = client
+    .get(format!("https://{}/info", node.addr))
+    .send()
+    .await
+    .expect("HTTPS request failed");
+
+  assert_eq!(response.status(), StatusCode::OK);
+  // Plain HTTP isn't served alongside it
+  assert!(client
+    .get(format!("http://{}/info", node.addr))
+    .send()
+    .await
+    .is_err());
+}
+
+#[tokio::test]
+async fn nodes_sync_over_https() {
+  let node = https_node().await;
+  let peer = https_node().await;
+  let (alice, _) = keypair(1);
+  let (_, bob_public) = keypair(2);
+  let (status, _) = peer.send(&alice, &bob_public, 10).await;
+  assert_eq!(status, StatusCode::OK);
+  node.add_peer(&peer).await;
+
+  let summary = node.sync().await.expect("node isn't frozen");
+
+  assert!(summary.chain_changed);
+  assert_eq!(node.chain().height(), 2);
+}
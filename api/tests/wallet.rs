@@ -0,0 +1,391 @@
+//! The wallet routes clients pay and check balances through.
+
+mod common;
+
+use std::collections::HashMap;
+
+use axum::http::{Method, StatusCode};
+use common::{keypair, payment, TestNode};
+use secp256k1::{PublicKey, SecretKey};
+use serde_json::{json, Value};
+
+#[tokio::test]
+async fn dry_run_accepts_an_affordable_payment_without_mining_it() {
+  let node = TestNode::standalone().await;
+  let (alice, _) = keypair(1);
+  let (_, bob_public) = keypair(2);
+
+  let (status, _) = node
+    .post("/wallet/send/dry-run", payment(&alice, &bob_public, 40))
+    .await;
+
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(node.chain().height(), 1);
+}
+
+#[tokio::test]
+async fn dry_run_refuses_a_payment_over_the_balance() {
+  let node = TestNode::standalone().await;
+  let (alice, _) = keypair(1);
+  let (_, bob_public) = keypair(2);
+
+  let (status, body) = node
+    .post("/wallet/send/dry-run", payment(&alice, &bob_public, 150))
+    .await;
+
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+  assert_eq!(body["code"], "insufficient_funds");
+  assert_eq!(node.chain().height(), 1);
+}
+
+#[tokio::test]
+async fn send_returns_the_block_it_was_mined_into() {
+  let node = TestNode::standalone().await;
+  let (alice, _) = keypair(1);
+  let (_, bob_public) = keypair(2);
+
+  let (status, receipt) = node.send(&alice, &bob_public, 10).await;
+
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(receipt["height"], 1);
+  assert_eq!(receipt["block_hash"], common::tip_hash(&node.chain()));
+}
+
+#[tokio::test]
+async fn balances_are_looked_up_together() {
+  let node = TestNode::standalone().await;
+  let (alice, alice_public) = keypair(1);
+  let (_, bob_public) = keypair(2);
+  let (_, carol_public) = keypair(3);
+  let (status, _) = node.send(&alice, &bob_public, 10).await;
+  assert_eq!(status, StatusCode::OK);
+
+  let (status, balances) = node
+    .post(
+      "/wallet/balances",
+      json!([
+        alice_public.to_string(),
+        bob_public.to_string(),
+        carol_public.to_string(),
+      ]),
+    )
+    .await;
+
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(balances[alice_public.to_string()], 90);
+  assert_eq!(balances[bob_public.to_string()], 110);
+  assert_eq!(balances[carol_public.to_string()], 100);
+}
+
+/// A `POST /wallet/send-batch` body paying each of `recipients` from `from`.
+fn batch(from: &SecretKey, recipients: &[(&PublicKey, u64)]) -> Value {
+  json!({
+    "from_secret_key": from.display_secret().to_string(),
+    "recipients": recipients
+      .iter()
+      .map(|(to, amount)| json!({ "to_public_key": to.to_string(), "amount": amount }))
+      .collect::<Vec<_>>(),
+  })
+}
+
+#[tokio::test]
+async fn a_batch_is_mined_into_one_block() {
+  let node = TestNode::standalone().await;
+  let (alice, _) = keypair(1);
+  let (_, bob_public) = keypair(2);
+  let (_, carol_public) = keypair(3);
+
+  let (status, receipt) = node
+    .post(
+      "/wallet/send-batch",
+      batch(&alice, &[(&bob_public, 30), (&carol_public, 40)]),
+    )
+    .await;
+
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(receipt["height"], 1);
+  assert_eq!(node.chain().block_at(1).unwrap().transactions().len(), 2);
+}
+
+#[tokio::test]
+async fn a_batch_over_the_balance_mines_nothing() {
+  let node = TestNode::standalone().await;
+  let (alice, alice_public) = keypair(1);
+  let (_, bob_public) = keypair(2);
+  let (_, carol_public) = keypair(3);
+
+  let (status, body) = node
+    .post(
+      "/wallet/send-batch",
+      batch(&alice, &[(&bob_public, 60), (&carol_public, 60)]),
+    )
+    .await;
+
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+  assert_eq!(body["code"], "insufficient_funds");
+  assert_eq!(node.chain().height(), 1);
+  let (_, balance) = node.get(&format!("/wallet/balance/{alice_public}")).await;
+  assert_eq!(balance["balance"], 100);
+}
+
+#[tokio::test]
+async fn a_mnemonic_restores_the_keypair_it_was_created_with() {
+  let node = TestNode::standalone().await;
+  let (status, created) = node
+    .request(Method::POST, "/wallet/create?mnemonic=true", None)
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  let mnemonic = created["mnemonic"]
+    .as_str()
+    .expect("a mnemonic was asked for");
+  assert_eq!(mnemonic.split_whitespace().count(), 12);
+
+  let (status, restored) = node
+    .post("/wallet/restore", json!({ "mnemonic": mnemonic }))
+    .await;
+
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(restored["public_key"], created["public_key"]);
+  assert_eq!(restored["secret_key"], created["secret_key"]);
+}
+
+#[tokio::test]
+async fn keypairs_come_without_a_mnemonic_unless_asked() {
+  let node = TestNode::standalone().await;
+
+  let (status, created) = node.request(Method::POST, "/wallet/create", None).await;
+
+  assert_eq!(status, StatusCode::OK);
+  assert!(created.get("mnemonic").is_none());
+}
+
+#[tokio::test]
+async fn restoring_from_an_invalid_mnemonic_is_refused() {
+  let node = TestNode::standalone().await;
+
+  let (status, body) = node
+    .post(
+      "/wallet/restore",
+      json!({ "mnemonic": "not a real mnemonic" }),
+    )
+    .await;
+
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+  assert_eq!(body["code"], "invalid_mnemonic");
+}
+
+#[tokio::test]
+async fn an_export_lists_the_transactions_involving_the_wallet() {
+  let node = TestNode::standalone().await;
+  let (alice, alice_public) = keypair(1);
+  let (bob, bob_public) = keypair(2);
+  let (carol, carol_public) = keypair(3);
+  let (_, dave_public) = keypair(4);
+  for (from, to, amount) in [
+    (&alice, &bob_public, 10),
+    (&bob, &carol_public, 5),
+    (&carol, &dave_public, 7),
+  ] {
+    let (status, _) = node.send(from, to, amount).await;
+    assert_eq!(status, StatusCode::OK);
+  }
+
+  let (status, export) = node.get(&format!("/wallet/{bob_public}/export")).await;
+
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(export["public_key"], bob_public.to_string());
+  assert_eq!(export["balance"], 105);
+  assert_eq!(
+    export["transactions"],
+    json!([
+      {
+        "height": 1,
+        "confirmations": 3,
+        "transaction": {
+          "from": alice_public.to_string(),
+          "to": bob_public.to_string(),
+          "amount": 10,
+          "signature": export["transactions"][0]["transaction"]["signature"],
+        },
+      },
+      {
+        "height": 2,
+        "confirmations": 2,
+        "transaction": {
+          "from": bob_public.to_string(),
+          "to": carol_public.to_string(),
+          "amount": 5,
+          "signature": export["transactions"][1]["transaction"]["signature"],
+        },
+      },
+    ])
+  );
+}
+
+#[tokio::test]
+async fn accounts_lists_every_address_that_has_transacted() {
+  let node = TestNode::standalone().await;
+  let (alice, alice_public) = keypair(1);
+  let (bob, bob_public) = keypair(2);
+  let (_, carol_public) = keypair(3);
+  for (from, to, amount) in [(&alice, &bob_public, 10), (&bob, &carol_public, 5)] {
+    let (status, _) = node.send(from, to, amount).await;
+    assert_eq!(status, StatusCode::OK);
+  }
+
+  let (status, all) = node.get("/accounts").await;
+
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(all["total"], 3);
+  let balances: HashMap<&str, i64> = all["accounts"]
+    .as_array()
+    .unwrap()
+    .iter()
+    .map(|account| {
+      (
+        account["public_key"].as_str().unwrap(),
+        account["balance"].as_i64().unwrap(),
+      )
+    })
+    .collect();
+  assert_eq!(
+    balances,
+    HashMap::from([
+      (alice_public.to_string().as_str(), 90),
+      (bob_public.to_string().as_str(), 105),
+      (carol_public.to_string().as_str(), 105),
+    ])
+  );
+
+  let (status, page) = node.get("/accounts?offset=1&limit=1").await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(page["total"], 3);
+  assert_eq!(page["accounts"], json!([all["accounts"][1]]));
+}
+
+#[tokio::test]
+async fn balance_deltas_sum_to_the_change_in_balance() {
+  let node = TestNode::standalone().await;
+  let (alice, alice_public) = keypair(1);
+  let (bob, bob_public) = keypair(2);
+  let (_, carol_public) = keypair(3);
+  for (from, to, amount) in [
+    (&alice, &bob_public, 10),
+    (&bob, &alice_public, 3),
+    (&bob, &carol_public, 4),
+    (&alice, &carol_public, 20),
+  ] {
+    let (status, _) = node.send(from, to, amount).await;
+    assert_eq!(status, StatusCode::OK);
+  }
+
+  let (status, deltas) = node.get(&format!("/wallet/deltas/{alice_public}")).await;
+  let (_, balance) = node.get(&format!("/wallet/balance/{alice_public}")).await;
+
+  assert_eq!(status, StatusCode::OK);
+  // Bob paying carol doesn't move alice's balance
+  assert_eq!(
+    deltas,
+    json!([
+      { "height": 1, "delta": -10 },
+      { "height": 2, "delta": 3 },
+      { "height": 4, "delta": -20 },
+    ])
+  );
+  let total: i64 = deltas
+    .as_array()
+    .unwrap()
+    .iter()
+    .map(|delta| delta["delta"].as_i64().unwrap())
+    .sum();
+  // Every key starts with 100
+  assert_eq!(total, balance["balance"].as_i64().unwrap() - 100);
+}
+
+#[tokio::test]
+async fn a_malformed_public_key_is_a_structured_bad_request() {
+  let node = TestNode::standalone().await;
+
+  let (status, body) = node.get("/wallet/balance/not-a-key").await;
+
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+  assert_eq!(
+    body,
+    json!({
+      "error": "could not parse public key \"not-a-key\"",
+      "code": "invalid_public_key",
+    })
+  );
+}
+
+#[tokio::test]
+async fn history_routes_echo_a_garbage_public_key() {
+  let node = TestNode::standalone().await;
+  for uri in ["/wallet/deltas/bad%0Akey", "/wallet/bad%0Akey/export"] {
+    let (status, body) = node.get(uri).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{uri}");
+    assert_eq!(
+      body,
+      json!({
+        "error": "could not parse public key \"bad\\nkey\"",
+        "code": "invalid_public_key",
+      }),
+      "{uri}"
+    );
+  }
+}
+
+#[tokio::test]
+async fn a_long_garbage_public_key_is_cut_short_in_the_error() {
+  let node = TestNode::standalone().await;
+  let garbage = "z".repeat(200);
+
+  let (status, body) = node.get(&format!("/wallet/deltas/{garbage}")).await;
+
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+  assert_eq!(
+    body["error"],
+    format!("could not parse public key \"{}...\"", &garbage[..80])
+  );
+}
+
+/// The balance of `public_key` counting only blocks `min_confirmations` deep.
+async fn deep_balance(node: &TestNode, public_key: &PublicKey, min_confirmations: usize) -> Value {
+  let uri = format!("/wallet/balance/{public_key}?min_confirmations={min_confirmations}");
+  node.get(&uri).await.1["balance"].clone()
+}
+
+#[tokio::test]
+async fn deep_balances_leave_out_recent_blocks() {
+  let node = TestNode::standalone().await;
+  let (alice, _) = keypair(1);
+  let (_, bob_public) = keypair(2);
+  let (carol, _) = keypair(3);
+  let (_, dave_public) = keypair(4);
+  let (status, _) = node.send(&alice, &bob_public, 10).await;
+  assert_eq!(status, StatusCode::OK);
+
+  assert_eq!(deep_balance(&node, &bob_public, 0).await, 110);
+  assert_eq!(deep_balance(&node, &bob_public, 6).await, 100);
+
+  // Bury bob's payment under five more blocks
+  for amount in 1..=5 {
+    let (status, _) = node.send(&carol, &dave_public, amount).await;
+    assert_eq!(status, StatusCode::OK);
+  }
+
+  assert_eq!(deep_balance(&node, &bob_public, 0).await, 110);
+  assert_eq!(deep_balance(&node, &bob_public, 6).await, 110);
+}
+
+#[tokio::test]
+async fn paying_yourself_is_refused() {
+  let node = TestNode::standalone().await;
+  let (alice, alice_public) = keypair(1);
+
+  let (status, _) = node.send(&alice, &alice_public, 10).await;
+
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+  assert_eq!(node.chain().height(), 1);
+}
@@ -0,0 +1,53 @@
+//! The single writer task every change to the chain goes through.
+
+mod common;
+
+use std::sync::Arc;
+
+use axum::http::StatusCode;
+use common::{keypair, TestNode};
+use tanishqoin_api::{Transaction, DEFAULT_CHAIN_ID};
+use tokio::task::JoinSet;
+
+#[tokio::test]
+async fn concurrent_sends_all_land_in_the_chain() {
+  let node = Arc::new(TestNode::standalone().await);
+  let (_, bob_public) = keypair(2);
+  let senders = 10..26;
+
+  let mut sends = JoinSet::new();
+  for seed in senders.clone() {
+    let node = node.clone();
+    sends.spawn(async move {
+      let (sender, _) = keypair(seed);
+      node.send(&sender, &bob_public, u64::from(seed)).await
+    });
+  }
+  let mut receipts = Vec::new();
+  while let Some(sent) = sends.join_next().await {
+    let (status, receipt) = sent.expect("send task panicked");
+    assert_eq!(status, StatusCode::OK);
+    receipts.push(receipt);
+  }
+
+  let chain = node.chain();
+  chain.validate().expect("the chain is valid");
+  // Sends that were waiting together share a block, so there's at most one
+  // block for each
+  assert!(chain.height() <= senders.len() + 1);
+  for receipt in &receipts {
+    let block = chain
+      .block_at(receipt["height"].as_u64().unwrap() as usize)
+      .unwrap();
+    assert_eq!(receipt["block_hash"], block.hash());
+  }
+  for seed in senders.clone() {
+    let (sender, _) = keypair(seed);
+    let transaction =
+      Transaction::new(&bob_public, &sender, u64::from(seed), DEFAULT_CHAIN_ID).unwrap();
+    assert!(chain.confirmations(transaction.signature()).is_some());
+  }
+  let (_, balance) = node.get(&format!("/wallet/balance/{bob_public}")).await;
+  let received: u64 = senders.map(u64::from).sum();
+  assert_eq!(balance["balance"], 100 + received);
+}